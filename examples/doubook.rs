@@ -1,21 +1,19 @@
-#[allow(dead_code)]
+#![allow(dead_code)]
 
-use thirtyfour::prelude::*;
+use thirtyfour::DesiredCapabilities;
 use anyhow::Result;
 use futures::StreamExt;
+use rust_crawler::pipeline::{DedupBy, Validate};
 use rust_crawler::scraper::Selector;
 use std::time::Duration;
-use rust_crawler::{Collector, Crawler, CrawlerConfig, RequestDelay, Response, Scraper};
+use rust_crawler::{Collector, Crawler, CrawlerConfig, RequestDelay, Response, Scraper, WebDriverFetcher};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     struct BookScraper {
         cover_selector: Selector,
-        title_selector: Selector,
         // info_selector: Selector,
-        score_selector: Selector,
-        people_selector: Selector,
         collector_selector: Selector,
         // isbn_selector: Selector,
     }
@@ -24,10 +22,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         fn default() -> Self {
             Self {
                 cover_selector: Selector::parse("a.cover-link").unwrap(),
-                title_selector: Selector::parse("div#wrapper h1 span").unwrap(),
                 // info_selector: Selector::parse("div.meta.abstract").unwrap(),
-                score_selector: Selector::parse("strong.rating_num").unwrap(),
-                people_selector: Selector::parse("a.rating_people span").unwrap(),
                 // isbn_selector: Selector::parse("a.isbn").unwrap(),
                 collector_selector: Selector::parse("div#collector p a").unwrap(),
                 // isbn_selector: Selector::parse("h1._3pxf09j4e").unwrap(),
@@ -57,16 +52,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         fn scrape(
             &mut self,
-            response: Response<Self::State>,
+            mut response: Response<Self::State>,
             crawler: &mut Crawler<Self>
         ) -> Result<Option<Self::Output>> {
-            let html = response.html();
+            let state = response.state.take();
+            let page = response.parse();
 
-            if let Some(state) = response.state {
+            if let Some(state) = state {
                 match state {
                     BookState::Page(_page) => {
                         // Find all books in the page
-                        for (_idx, el) in html.select(&self.cover_selector).enumerate() 
+                        for el in page.html().select(&self.cover_selector)
                         {
                             dbg!(el.value().attr("href"));
                             crawler.visit_with_state(
@@ -76,20 +72,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     },
                     BookState::Book => {
-                        let el_title = html.select(&self.title_selector).next().unwrap();
-                        let el_score = html.select(&self.score_selector).next().unwrap();
-                        let el_people = html.select(&self.people_selector).next().unwrap();
-
                         let mut stats = vec![];
-                        for (_, link) in html.select(&self.collector_selector).enumerate() {
+                        for link in page.html().select(&self.collector_selector) {
                             stats.push(link.inner_html());
                         }
 
                         // scrape books info
                         let entry = Book {
-                            title: el_title.inner_html(), 
-                            score: el_score.inner_html(),
-                            people: el_people.inner_html(),
+                            title: page.select_text("div#wrapper h1 span")?,
+                            score: page.select_text("strong.rating_num")?,
+                            people: page.select_text("a.rating_people span")?,
                             stats
                         };
 
@@ -102,36 +94,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let config = CrawlerConfig::default()
-        .allow_domain_with_delay("127.0.0.1", RequestDelay::Fixed(Duration::from_millis(1_000)))
-        .allow_domain_with_delay("book.douban.com", RequestDelay::Fixed(Duration::from_millis(1_000)));
-    let mut collector = Collector::new(BookScraper::default(), config);
+    // the search results are rendered with javascript
+    let browser = WebDriverFetcher::new("http://127.0.0.1:9515", DesiredCapabilities::chrome())
+        .wait_for("a.cover-link", Duration::from_secs(10));
 
-    let caps = DesiredCapabilities::chrome();
-    let driver = WebDriver::new("http://127.0.0.1:9515", caps).await?;
+    let config = CrawlerConfig::default()
+        .allow_domain_with_delay("search.douban.com", RequestDelay::Fixed(Duration::from_millis(1_000)))
+        .allow_domain_with_delay("book.douban.com", RequestDelay::Fixed(Duration::from_millis(1_000)))
+        .fetch_with("search.douban.com", browser.clone());
+    let mut collector = Collector::new(BookScraper::default(), config)
+        .pipe(Validate::new().check("title", |book: &Book| !book.title.is_empty()))
+        .pipe(DedupBy::new(|book: &Book| book.title.clone()));
 
     let books: Vec<i64> = vec![9787513349369, 9784344038158];
 
-    for isbn in &books {
-        driver.goto(format!("https://search.douban.com/book/subject_search?search_text={}&cat=1001",
-            isbn.to_string())).await?;
-
-        let cover_link = driver.find(By::ClassName("cover-link"))
-            .await?.attr("href").await?;
-        
-        let link = cover_link.unwrap();
-
-        if (!link.is_empty()) {
-            collector.crawler_mut().visit_with_state(link, BookState::Book);
+    for (page, isbn) in books.iter().enumerate() {
+        collector.crawler_mut().visit_with_state(
+            format!("https://search.douban.com/book/subject_search?search_text={}&cat=1001", isbn),
+            BookState::Page(page),
+        );
+    }
 
-            while let Some(output) = collector.next().await {
-                let book = output?;
-                dbg!(book);
-            }
-        } 
+    while let Some(output) = collector.next().await {
+        let book = output?;
+        dbg!(book);
     }
-    
-    driver.quit().await?;
+
+    browser.quit().await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -2,8 +2,9 @@
 
 use anyhow::Result;
 use futures::StreamExt;
-use reqwest::Url;
+use serde::Serialize;
 use std::time::Duration;
+use rust_crawler::export::JsonLinesSink;
 use rust_crawler::scraper::Selector;
 use rust_crawler::{Collector, Crawler, CrawlerConfig, RequestDelay, Response, Scraper};
 
@@ -36,19 +37,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Post,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Serialize)]
     struct Entry {
         author: String,
-        url: Url,
+        url: String,
         link: Option<String>,
         title: String,
         replies: Vec<Reply>,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Serialize)]
     struct Reply {
         author: String,
-        url: Url,
+        url: String,
         comment: String,
         replies: Vec<Reply>,
     }
@@ -59,12 +60,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         fn scrape(
             &mut self,
-            response: Response<Self::State>,
+            mut response: Response<Self::State>,
             crawler: &mut Crawler<Self>,
         ) -> Result<Option<Self::Output>> {
+            let state = response.state.take();
             let html = response.html();
 
-            if let Some(state) = response.state {
+            if let Some(state) = state {
                 match state {
                     HackernewsState::Page(page) => {
                         // find all entries
@@ -98,7 +100,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         // scrape the post
                         let entry = Entry {
                             author: author.unwrap(),
-                            url: response.response_url,
+                            url: response.response_url.to_string(),
                             link: el_title.value().attr("href").map(str::to_string),
                             title: el_title.inner_html(),
                             replies: Vec::new(),
@@ -115,11 +117,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let config = CrawlerConfig::default().allow_domain_with_delay(
-        "news.ycombinator.com",
-        RequestDelay::Fixed(Duration::from_millis(5_000)),
-    );
-    let mut collector = Collector::new(HackernewsScraper::default(), config);
+    let config = CrawlerConfig::default()
+        .allow_domain_with_delay(
+            "news.ycombinator.com",
+            RequestDelay::Fixed(Duration::from_millis(5_000)),
+        )
+        .deduplicate_requests();
+    let mut collector = Collector::new(HackernewsScraper::default(), config)
+        .pipe(JsonLinesSink::new("hackernews.jsonl"));
 
     collector.crawler_mut().visit_with_state(
         "https://news.ycombinator.com/news",
@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use anyhow::Result;
 use futures::StreamExt;
 use reqwest::Url;
@@ -45,12 +47,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         fn scrape(
             &mut self,
-            response: Response<Self::State>,
+            mut response: Response<Self::State>,
             crawler: &mut Crawler<Self>,
         ) -> Result<Option<Self::Output>> {
+            let state = response.state.take();
             let html = response.html();
 
-            if let Some(state) = response.state {
+            if let Some(state) = state {
                 match state {
                     RedditState::SubReddit { name, .. } => {
                         for (idx, el) in html.select(&self.post_selector).enumerate() {
@@ -104,7 +107,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let config = CrawlerConfig::default().allow_domain("old.reddit.com");
+    let config = CrawlerConfig::default()
+        .allow_domain("old.reddit.com")
+        .deduplicate_requests();
     let mut collector = Collector::new(Reddit::default(), config);
 
     collector.crawler_mut().visit_with_state(
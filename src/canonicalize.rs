@@ -0,0 +1,226 @@
+//! Canonical forms of urls and hosts.
+//!
+//! Different spellings of the same page, like `https://Example.com:443/a/../b#top`
+//! and `https://example.com/b`, are mapped to the same canonical url. The
+//! `Crawler` uses the canonical forms to match domains against the allow and
+//! block lists, to fingerprint requests for deduplication and to count the
+//! requests of each host. The request that is sent is never modified.
+
+use reqwest::Url;
+use url::Host;
+
+/// The rules that are applied to canonicalize urls and hosts
+#[derive(Debug, Clone)]
+pub struct CanonicalizeRules {
+    /// Remove the `#fragment`
+    strip_fragment: bool,
+    /// Remove a leading `www.` label from the host
+    strip_www: bool,
+    /// Sort the query pairs by key and value
+    sort_query: bool,
+    /// Query parameters that are removed, a trailing `*` matches any suffix
+    removed_query_params: Vec<String>,
+}
+
+impl Default for CanonicalizeRules {
+    fn default() -> Self {
+        Self {
+            strip_fragment: true,
+            strip_www: false,
+            sort_query: true,
+            removed_query_params: Self::TRACKING_QUERY_PARAMS
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl CanonicalizeRules {
+    /// Query parameters that are only used to track visitors
+    pub const TRACKING_QUERY_PARAMS: &'static [&'static str] = &[
+        "utm_*", "fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid",
+    ];
+
+    /// Rules that only apply the normalization the url parser already does:
+    /// lowercase scheme and host, punycode, no default port and resolved `..`
+    /// segments
+    pub fn none() -> Self {
+        Self {
+            strip_fragment: false,
+            strip_www: false,
+            sort_query: false,
+            removed_query_params: Vec::new(),
+        }
+    }
+
+    /// Keep the `#fragment`
+    pub fn keep_fragment(mut self) -> Self {
+        self.strip_fragment = false;
+        self
+    }
+
+    /// Treat `www.example.com` and `example.com` as the same host.
+    ///
+    /// Both hosts then share one queue, one delay and one robots.txt, so only
+    /// use this if they serve the same site.
+    pub fn strip_www(mut self) -> Self {
+        self.strip_www = true;
+        self
+    }
+
+    /// Treat `www.example.com` and `example.com` as different hosts, the
+    /// default
+    pub fn keep_www(mut self) -> Self {
+        self.strip_www = false;
+        self
+    }
+
+    /// Keep the original order of the query pairs
+    pub fn keep_query_order(mut self) -> Self {
+        self.sort_query = false;
+        self
+    }
+
+    /// Also remove the query parameter `param`, a trailing `*` matches any
+    /// parameter that starts with the prefix
+    pub fn remove_query_param(mut self, param: impl Into<String>) -> Self {
+        self.removed_query_params.push(param.into());
+        self
+    }
+
+    /// Keep all query parameters
+    pub fn keep_query_params(mut self) -> Self {
+        self.removed_query_params.clear();
+        self
+    }
+
+    /// The canonical form of the `url`
+    pub fn url(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+
+        if let Some(host) = url.host_str() {
+            let host = self.host(host);
+            // fails for hosts that were never valid in the first place
+            let _ = url.set_host(Some(&host));
+        }
+
+        if url.query().is_some() {
+            let mut pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(key, _)| !self.is_removed_query_param(key))
+                .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                .collect();
+            if self.sort_query {
+                pairs.sort();
+            }
+            if pairs.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+
+        url
+    }
+
+    /// The canonical form of the `host`: lowercase, punycode encoded and
+    /// without a trailing dot.
+    ///
+    /// If the host can't be parsed it is only lowercased.
+    pub fn host(&self, host: &str) -> String {
+        let host = host.trim_end_matches('.');
+        let mut host = match Host::parse(host) {
+            Ok(Host::Domain(domain)) => domain,
+            Ok(Host::Ipv4(ip)) => return ip.to_string(),
+            Ok(Host::Ipv6(ip)) => return format!("[{}]", ip),
+            Err(_) => host.to_lowercase(),
+        };
+        if self.strip_www {
+            if let Some(stripped) = host.strip_prefix("www.") {
+                if stripped.contains('.') {
+                    host = stripped.to_string();
+                }
+            }
+        }
+        host
+    }
+
+    /// The canonical host of the `url`, if it has one
+    pub fn url_host(&self, url: &Url) -> Option<String> {
+        url.host_str().map(|host| self.host(host))
+    }
+
+    fn is_removed_query_param(&self, key: &str) -> bool {
+        self.removed_query_params.iter().any(|param| {
+            if let Some(prefix) = param.strip_suffix('*') {
+                key.starts_with(prefix)
+            } else {
+                key == param
+            }
+        })
+    }
+}
+
+/// The canonical form of the `url` using the default rules
+pub fn canonicalize(url: &Url) -> Url {
+    CanonicalizeRules::default().url(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical(url: &str) -> String {
+        canonicalize(&Url::parse(url).unwrap()).to_string()
+    }
+
+    #[test]
+    fn canonical_urls() {
+        assert_eq!(
+            canonical("https://WWW.Example.com:443/a/../b?utm_source=x&z=1&a=2#top"),
+            "https://www.example.com/b?a=2&z=1"
+        );
+        assert_eq!(
+            canonical("http://example.com:8080/?utm_medium=mail"),
+            "http://example.com:8080/"
+        );
+        assert_eq!(
+            canonical("https://bücher.example/"),
+            "https://xn--bcher-kva.example/"
+        );
+    }
+
+    #[test]
+    fn canonical_hosts() {
+        let rules = CanonicalizeRules::default();
+        assert_eq!(rules.host("WWW.Reddit.com."), "www.reddit.com");
+        assert_eq!(rules.host("127.0.0.1"), "127.0.0.1");
+        let rules = rules.strip_www();
+        assert_eq!(rules.host("WWW.Reddit.com."), "reddit.com");
+        assert_eq!(rules.host("www.com"), "www.com");
+    }
+
+    #[test]
+    fn parser_normalization() {
+        let rules = CanonicalizeRules::none();
+        let url = |url: &str| rules.url(&Url::parse(url).unwrap()).to_string();
+        assert_eq!(
+            url("HTTPS://Example.com:443/a/b/../c/./d"),
+            "https://example.com/a/c/d"
+        );
+        assert_eq!(url("http://example.com:80/a/b/.."), "http://example.com/a/");
+        assert_eq!(
+            url("http://example.com/../a#top"),
+            "http://example.com/a#top"
+        );
+        assert_eq!(
+            url("http://example.com:8080/%2e%2E/a"),
+            "http://example.com:8080/a"
+        );
+    }
+}
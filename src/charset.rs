@@ -0,0 +1,160 @@
+//! Detecting the character encoding of a response body.
+//!
+//! The encoding is taken from the first of these that applies:
+//! a byte order mark, the `charset` of the `Content-Type` header if the body
+//! is valid in it, a `<meta charset>` or `<meta http-equiv="Content-Type">`
+//! tag at the start of the document, and otherwise it is guessed from the
+//! bytes of the body.
+
+use std::sync::OnceLock;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::bytes::Regex;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::Url;
+
+/// How many bytes at the start of a document are searched for a `<meta>`
+/// charset declaration
+pub const META_PRESCAN_SIZE: usize = 1024;
+
+/// How many bytes are fed to the statistical detector
+const DETECT_SIZE: usize = 64 * 1024;
+
+/// Where the encoding of a body was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingSource {
+    /// The byte order mark at the start of the body
+    Bom,
+    /// The `charset` parameter of the `Content-Type` header
+    Header,
+    /// A `<meta>` tag of the html document
+    Meta,
+    /// Guessed from the content of the body
+    Detected,
+}
+
+/// Detect the encoding of the `body`, the `url` hints at the language of the
+/// document by its top level domain
+pub fn detect(
+    headers: &HeaderMap,
+    body: &[u8],
+    url: Option<&Url>,
+) -> (&'static Encoding, EncodingSource) {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return (encoding, EncodingSource::Bom);
+    }
+    // the header is often wrong, so it is only trusted if the body is valid
+    if let Some(encoding) = header_charset(headers) {
+        if encoding
+            .decode_without_bom_handling_and_without_replacement(body)
+            .is_some()
+        {
+            return (encoding, EncodingSource::Header);
+        }
+    }
+    if let Some(encoding) = meta_charset(body) {
+        return (encoding, EncodingSource::Meta);
+    }
+    (guess(body, url), EncodingSource::Detected)
+}
+
+/// The encoding named by the `charset` parameter of the `Content-Type`
+pub fn header_charset(headers: &HeaderMap) -> Option<&'static Encoding> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Encoding::for_label(value.trim().trim_matches('"').as_bytes())
+        } else {
+            None
+        }
+    })
+}
+
+/// The encoding declared by a `<meta charset>` or `<meta http-equiv>` tag in
+/// the first `META_PRESCAN_SIZE` bytes
+pub fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    static META: OnceLock<Regex> = OnceLock::new();
+    let meta = META.get_or_init(|| {
+        Regex::new(r#"(?i)<meta\b[^>]*?[\s;"']charset\s*=\s*["']?\s*([a-z0-9_:.+-]+)"#).unwrap()
+    });
+    let head = &body[..body.len().min(META_PRESCAN_SIZE)];
+    let label = meta.captures(head)?.get(1)?.as_bytes();
+    let encoding = Encoding::for_label(label)?;
+    // a document that could be parsed as ascii is not UTF-16
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        Some(UTF_8)
+    } else {
+        Some(encoding)
+    }
+}
+
+/// Guess the encoding from the content of the `body`
+fn guess(body: &[u8], url: Option<&Url>) -> &'static Encoding {
+    let mut detector = EncodingDetector::new();
+    detector.feed(&body[..body.len().min(DETECT_SIZE)], true);
+    let tld = url
+        .and_then(|url| url.host_str())
+        .and_then(|host| host.rsplit('.').next())
+        .filter(|tld| tld.bytes().all(|b| b.is_ascii_alphabetic()));
+    detector.guess(tld.map(str::as_bytes), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{BIG5, GBK, SHIFT_JIS};
+    use reqwest::header::HeaderValue;
+
+    fn html_headers(content_type: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers
+    }
+
+    #[test]
+    fn sniff_meta_declarations() {
+        let page = r#"<html><head><meta charset="gbk"><title>豆瓣讀書</title>"#;
+        let (body, _, _) = GBK.encode(page);
+        assert_eq!(
+            detect(&HeaderMap::new(), &body, None),
+            (GBK, EncodingSource::Meta)
+        );
+
+        let page = r#"<meta http-equiv="Content-Type" content="text/html;charset=Big5">繁體中文"#;
+        let (body, _, _) = BIG5.encode(page);
+        assert_eq!(meta_charset(&body), Some(BIG5));
+    }
+
+    #[test]
+    fn header_is_only_trusted_if_valid() {
+        let (body, _, _) = SHIFT_JIS.encode("<meta charset=shift_jis>日本語のページです");
+        let headers = html_headers("text/html; charset=utf-8");
+        assert_eq!(
+            detect(&headers, &body, None),
+            (SHIFT_JIS, EncodingSource::Meta)
+        );
+
+        let headers = html_headers("text/html; charset=Shift_JIS");
+        assert_eq!(
+            detect(&headers, &body, None),
+            (SHIFT_JIS, EncodingSource::Header)
+        );
+
+        let body = b"\xef\xbb\xbf<p>hi</p>";
+        assert_eq!(detect(&headers, body, None), (UTF_8, EncodingSource::Bom));
+    }
+
+    #[test]
+    fn guess_without_declaration() {
+        let text = "豆瓣讀書是一個關於書籍的社區，用戶可以在這裡記錄自己讀過的書並寫下評論。";
+        let text = text.repeat(4);
+        let (body, _, _) = BIG5.encode(&text);
+        let url = Url::parse("https://book.douban.tw/").unwrap();
+        assert_eq!(
+            detect(&HeaderMap::new(), &body, Some(&url)),
+            (BIG5, EncodingSource::Detected)
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Deduplication of queued requests.
+//!
+//! Every request that is queued via `Crawler::visit`, `Crawler::request` and
+//! friends is reduced to a [`Fingerprint`]. A [`SeenSet`] remembers all
+//! fingerprints, so a request for an already queued page can be dropped
+//! before it reaches the `DomainListing`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use reqwest::{Method, Request, Url};
+
+use crate::canonicalize::CanonicalizeRules;
+
+/// A stable 64 bit fingerprint of a request.
+///
+/// The fingerprint is computed from the request method, the canonical url
+/// and optionally the request body. It does not depend on the process or the
+/// compiler version, so it can be stored and compared across runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    /// Compute the fingerprint of a request.
+    ///
+    /// The `url` is expected to be canonicalized already.
+    pub fn new(method: &Method, url: &Url, body: Option<&[u8]>) -> Self {
+        let mut hash = Self::OFFSET_BASIS;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(Self::PRIME);
+            }
+        };
+        write(method.as_str().as_bytes());
+        write(b" ");
+        write(url.as_str().as_bytes());
+        if let Some(body) = body {
+            write(b"\n");
+            write(body);
+        }
+        Fingerprint(hash)
+    }
+
+    /// Restore a fingerprint from its raw value
+    pub fn from_u64(value: u64) -> Self {
+        Fingerprint(value)
+    }
+
+    /// The raw value of the fingerprint
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Remembers the fingerprints of all requests that were queued so far.
+///
+/// The default [`HashSeenSet`] keeps everything in memory. Implement this
+/// trait to use a probabilistic or disk backed set for very large crawls.
+pub trait SeenSet {
+    /// Record the `fingerprint`.
+    ///
+    /// Returns `true` if the fingerprint was not seen before.
+    fn insert(&mut self, fingerprint: Fingerprint) -> bool;
+
+    /// Whether the `fingerprint` was already recorded
+    fn contains(&self, fingerprint: &Fingerprint) -> bool;
+
+    /// The number of recorded fingerprints
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All recorded fingerprints, used to checkpoint the set.
+    ///
+    /// Sets that can't list their fingerprints return `None` and have to
+    /// persist themselves.
+    fn fingerprints(&self) -> Option<Vec<Fingerprint>> {
+        None
+    }
+}
+
+/// An in-memory `SeenSet` backed by a `HashSet`
+#[derive(Debug, Clone, Default)]
+pub struct HashSeenSet {
+    seen: HashSet<Fingerprint>,
+}
+
+impl HashSeenSet {
+    /// All recorded fingerprints
+    pub fn iter(&self) -> impl Iterator<Item = &Fingerprint> {
+        self.seen.iter()
+    }
+}
+
+impl SeenSet for HashSeenSet {
+    fn insert(&mut self, fingerprint: Fingerprint) -> bool {
+        self.seen.insert(fingerprint)
+    }
+
+    fn contains(&self, fingerprint: &Fingerprint) -> bool {
+        self.seen.contains(fingerprint)
+    }
+
+    fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    fn fingerprints(&self) -> Option<Vec<Fingerprint>> {
+        Some(self.seen.iter().copied().collect())
+    }
+}
+
+/// What to do with a request that was already queued before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Silently drop the request
+    #[default]
+    Drop,
+    /// Drop the request and report a `CrawlError::DuplicateRequest`
+    Report,
+}
+
+/// The deduplication layer in front of the `DomainListing`
+pub struct Dedup {
+    /// All fingerprints seen so far
+    seen: Box<dyn SeenSet>,
+    /// Whether the body is part of the fingerprint
+    include_body: bool,
+    /// How to handle duplicates
+    policy: DuplicatePolicy,
+    /// The rules to canonicalize the request url, set by the `Crawler`
+    rules: CanonicalizeRules,
+}
+
+impl Dedup {
+    /// Create a new deduplication layer that uses the `seen` set
+    pub fn new(seen: impl SeenSet + 'static) -> Self {
+        Self {
+            seen: Box::new(seen),
+            include_body: false,
+            policy: DuplicatePolicy::Drop,
+            rules: CanonicalizeRules::default(),
+        }
+    }
+
+    /// Make the request body part of the fingerprint, so that for example
+    /// `POST` requests with different forms are not considered duplicates
+    pub fn include_body(mut self) -> Self {
+        self.include_body = true;
+        self
+    }
+
+    /// Report duplicates as `CrawlError::DuplicateRequest` instead of
+    /// dropping them silently
+    pub fn report_duplicates(mut self) -> Self {
+        self.policy = DuplicatePolicy::Report;
+        self
+    }
+
+    /// The fingerprint of the `request` according to this configuration.
+    ///
+    /// Streamed bodies are never part of the fingerprint.
+    pub fn fingerprint(&self, request: &Request) -> Fingerprint {
+        let body = if self.include_body {
+            request.body().and_then(|body| body.as_bytes())
+        } else {
+            None
+        };
+        Fingerprint::new(request.method(), &self.rules.url(request.url()), body)
+    }
+
+    pub(crate) fn set_rules(&mut self, rules: CanonicalizeRules) {
+        self.rules = rules;
+    }
+
+    /// Record the request and return `true` if it was not seen before
+    pub fn insert(&mut self, request: &Request) -> bool {
+        let fingerprint = self.fingerprint(request);
+        self.seen.insert(fingerprint)
+    }
+
+    /// Whether the request was already seen
+    pub fn contains(&self, request: &Request) -> bool {
+        self.seen.contains(&self.fingerprint(request))
+    }
+
+    /// How duplicates are handled
+    pub fn policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
+
+    /// The underlying set of fingerprints
+    pub fn seen(&self) -> &dyn SeenSet {
+        self.seen.as_ref()
+    }
+
+    /// Mutable access to the underlying set of fingerprints
+    pub fn seen_mut(&mut self) -> &mut dyn SeenSet {
+        self.seen.as_mut()
+    }
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Dedup::new(HashSeenSet::default())
+    }
+}
+
+impl fmt::Debug for Dedup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dedup")
+            .field("seen", &self.seen.len())
+            .field("include_body", &self.include_body)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(url: &str) -> Request {
+        reqwest::Client::new().get(url).build().unwrap()
+    }
+
+    #[test]
+    fn ignores_fragment() {
+        let dedup = Dedup::default();
+        assert_eq!(
+            dedup.fingerprint(&get("https://example.com/a#top")),
+            dedup.fingerprint(&get("https://EXAMPLE.com:443/a"))
+        );
+        assert_ne!(
+            dedup.fingerprint(&get("https://example.com/a")),
+            dedup.fingerprint(&get("https://example.com/b"))
+        );
+    }
+
+    #[test]
+    fn method_and_body() {
+        let client = reqwest::Client::new();
+        let get = client.get("https://example.com/search").build().unwrap();
+        let post_a = client
+            .post("https://example.com/search")
+            .body("q=a")
+            .build()
+            .unwrap();
+        let post_b = client
+            .post("https://example.com/search")
+            .body("q=b")
+            .build()
+            .unwrap();
+
+        let dedup = Dedup::default();
+        assert_ne!(dedup.fingerprint(&get), dedup.fingerprint(&post_a));
+        assert_eq!(dedup.fingerprint(&post_a), dedup.fingerprint(&post_b));
+
+        let dedup = Dedup::default().include_body();
+        assert_ne!(dedup.fingerprint(&post_a), dedup.fingerprint(&post_b));
+    }
+
+    #[test]
+    fn dedup_drops_seen() {
+        let mut dedup = Dedup::default();
+        assert!(dedup.insert(&get("https://example.com/")));
+        assert!(!dedup.insert(&get("https://example.com/#main")));
+        assert!(!dedup.insert(&get("https://Example.com:443/?utm_source=feed")));
+        assert!(dedup.insert(&get("https://www.example.com/")));
+        assert!(dedup.insert(&get("https://example.com/next")));
+        assert_eq!(dedup.seen().len(), 3);
+    }
+}
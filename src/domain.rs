@@ -1,56 +1,114 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use anyhow::Result;
 use futures::stream::Stream;
 use futures::{Future, FutureExt};
+use futures_timer::Delay;
+use reqwest::Url;
 
+use crate::canonicalize::CanonicalizeRules;
 use crate::error::{CrawlError, DisallowReason};
-use crate::requests::{
-    response_info, QueuedRequest, QueuedRequestBuilder, RequestDelay, RequestQueue,
-};
-use crate::response::Response;
-use crate::robots::{RobotsData, RobotsHandler};
+use crate::fetch::{Fetcher, Fetchers, ResolveFuture};
+use crate::frontier::Ticket;
+use crate::limits::{ConcurrencyLimits, Permit};
+use crate::middleware::{Action, Middleware, Middlewares};
+use crate::pattern::{default_suffixes, DomainPattern, PublicSuffixList};
+use crate::requests::{response_info, QueuedRequest, RequestDelay, RequestQueue, RetryQueue};
+use crate::response::{read_body, Body, BodyLimits, Response};
+use crate::retry::{Attempt, RetryPolicy};
+use crate::robots::{request_agent, RobotsCache, RobotsData};
+use crate::throttle::{Throttle, ThrottleSettings};
 
+// the listing is created once per crawler, boxing a list gains nothing
+#[allow(clippy::large_enum_variant)]
 pub enum DomainListing<T> {
     AllowList(AllowList<T>),
     BlockList(BlockList<T>),
 }
 
+impl<T> DomainListing<T> {
+    /// Run the `middleware` after the middlewares that were added before
+    pub fn add_middleware(&mut self, middleware: impl Middleware<T> + 'static) {
+        self.middleware_mut().push(Box::new(middleware));
+    }
+
+    fn middleware_mut(&mut self) -> &mut Middlewares<T> {
+        match self {
+            DomainListing::AllowList(list) => &mut list.middleware,
+            DomainListing::BlockList(list) => &mut list.middleware,
+        }
+    }
+
+    /// Whether the allow or block list lets requests to the `url` through,
+    /// without running the middlewares
+    pub(crate) fn is_allowed(&self, url: &Url) -> bool {
+        match self {
+            DomainListing::AllowList(list) => list.is_allowed(url),
+            DomainListing::BlockList(list) => list.is_allowed(url),
+        }
+    }
+
+    /// The error to report after the middlewares saw the `err`, if any
+    fn filter_error(&mut self, err: anyhow::Error) -> Option<anyhow::Error> {
+        match self.middleware_mut().on_error(&err) {
+            Action::Continue => Some(err),
+            Action::Drop => None,
+            Action::Fail(err) => Some(err),
+        }
+    }
+}
+
 impl<T> DomainListing<T> 
 where
     T: Unpin + Send + Sync + 'static + fmt::Debug
 {
-    pub(crate) fn add_request(
-        &mut self, 
-        request: QueuedRequestBuilder<T>,
-    ) -> Result<(), CrawlError<T>> {
-        let QueuedRequestBuilder {
-            request,
-            state,
-            depth,
-        } = request;
-
-        match request.build() {
-            Ok(request) => {
-                let queued = QueuedRequest {
-                    request,
-                    state,
-                    depth,
-                };
-
-                match self {
-                    DomainListing::AllowList(list) => Ok(list.add_request(queued)?),
-                    DomainListing::BlockList(list) => Ok(list.add_request(queued)?),
+    /// Update the `settings` of the throttled domains that changed and remove
+    /// those of the dropped hosts
+    pub(crate) fn report_throttles(&mut self, settings: &mut BTreeMap<String, ThrottleSettings>) {
+        let domains = match self {
+            DomainListing::AllowList(list) => &list.allowed,
+            DomainListing::BlockList(list) => {
+                for host in list.dropped_throttles.drain(..) {
+                    settings.remove(&host);
+                }
+                for (key, throttle) in &list.throttles {
+                    if let Some(changed) = throttle.take_changed() {
+                        settings.insert(key.clone(), changed);
+                    }
                 }
+                &list.hosts
             }
-            Err(error) => Err(CrawlError::<T>::FailedToBuildRequest {
-                error,
-                state,
-                depth,
-            })
+        };
+        for (key, domain) in domains {
+            if let Some(changed) = domain.take_throttle_change() {
+                settings.insert(key.clone(), changed);
+            }
+        }
+    }
+
+    /// Run the middlewares on the request and queue it for its domain.
+    ///
+    /// Returns whether the request was queued. Requests and errors dropped by
+    /// a middleware are not reported.
+    pub(crate) fn add_request(&mut self, mut request: QueuedRequest<T>) -> Result<bool> {
+        match self.middleware_mut().on_request(&mut request) {
+            Action::Continue => {}
+            Action::Drop => return Ok(false),
+            Action::Fail(err) => return Err(err),
+        }
+        let added = match self {
+            DomainListing::AllowList(list) => list.add_request(request),
+            DomainListing::BlockList(list) => list.add_request(request),
+        };
+        match added {
+            Err(err) => self.filter_error(err.into()).map_or(Ok(false), Err),
+            Ok(()) => Ok(true),
         }
     }
 }
@@ -62,42 +120,130 @@ where
     type Item = Result<Response<T>>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.get_mut() {
-            DomainListing::AllowList(list) => Stream::poll_next(Pin::new(list), cx),
-            DomainListing::BlockList(list) => Stream::poll_next(Pin::new(list), cx),
+        let pin = self.get_mut();
+        loop {
+            let next = match pin {
+                DomainListing::AllowList(list) => Stream::poll_next(Pin::new(list), cx),
+                DomainListing::BlockList(list) => Stream::poll_next(Pin::new(list), cx),
+            };
+            // run the middlewares, skip what they drop
+            match next {
+                Poll::Ready(Some(Ok(mut resp))) => {
+                    match pin.middleware_mut().on_response(&mut resp) {
+                        Action::Continue => return Poll::Ready(Some(Ok(resp))),
+                        Action::Drop => {}
+                        Action::Fail(err) => return Poll::Ready(Some(Err(err))),
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    if let Some(err) = pin.filter_error(err) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                }
+                next => return next,
+            }
         }
     }
 }
 
 pub struct AllowList<T> {
-    /// 所有允許的域名
+    /// 所有允許的域名，以規範化後的模式爲鍵
     allowed: HashMap<String, AllowedDomain<T>>,
     domains: Vec<String>,
+    /// 非精確匹配的域名模式，按添加順序匹配
+    patterns: Vec<DomainPattern>,
     /// 請求結果的集合
     queued_results: VecDeque<Result<Response<T>>>,
+    /// 域名匹配前的規範化規則
+    rules: CanonicalizeRules,
+    /// 用於匹配可註冊域名的公共後綴列表
+    suffixes: Arc<PublicSuffixList>,
+    /// 按順序執行的中間件
+    middleware: Middlewares<T>,
 }
 
 impl<T> Default for AllowList<T> {
     fn default() -> Self {
+        Self::new(CanonicalizeRules::default())
+    }
+}
+
+impl<T> AllowList<T> {
+    /// Create an empty list that matches hosts after canonicalizing them with
+    /// the `rules`
+    pub fn new(rules: CanonicalizeRules) -> Self {
         Self {
             allowed: Default::default(),
             domains: Vec::new(),
+            patterns: Vec::new(),
             queued_results: Default::default(),
+            rules,
+            suffixes: default_suffixes(),
+            middleware: Default::default(),
         }
     }
+
+    /// Use the `suffixes` to match `DomainPattern::Registrable` patterns
+    pub fn with_public_suffix_list(mut self, suffixes: Arc<PublicSuffixList>) -> Self {
+        self.suffixes = suffixes;
+        self
+    }
+
+    /// Whether the `url` matches one of the allowed domains
+    fn is_allowed(&self, url: &Url) -> bool {
+        self.rules
+            .url_host(url)
+            .and_then(|host| self.find_key(&host, Some(url)))
+            .is_some()
+    }
+
+    /// The key of the first pattern that matches the canonical `host`
+    fn find_key(&self, host: &str, url: Option<&Url>) -> Option<String> {
+        if self.allowed.contains_key(host) {
+            return Some(host.to_string());
+        }
+        self.patterns
+            .iter()
+            .find(|pattern| match url {
+                Some(url) => pattern.matches(host, url, &self.suffixes),
+                None => pattern.matches_host(host, &self.suffixes),
+            })
+            .map(ToString::to_string)
+    }
 }
 
 impl<T: fmt::Debug> AllowList<T> {
+    /// Allow the domain, `domain` is parsed as `DomainPattern`
     pub fn allow(&mut self, domain: String, config: AllowListConfig) {
-        self.allowed
-            .insert(domain.clone(), AllowedDomain::new(config));
-        self.domains.push(domain)
+        self.allow_pattern(DomainPattern::parse(&domain), config)
     }
 
+    /// Allow all urls that match the `pattern`, they share a single
+    /// `AllowedDomain` and therefore its delay and concurrency limit
+    pub fn allow_pattern(&mut self, pattern: DomainPattern, config: AllowListConfig) {
+        let pattern = pattern.canonicalize(&self.rules);
+        let key = pattern.to_string();
+        if self
+            .allowed
+            .insert(key.clone(), AllowedDomain::new(config))
+            .is_none()
+        {
+            self.domains.push(key);
+            if !pattern.is_exact() {
+                self.patterns.push(pattern);
+            }
+        }
+    }
+
+    /// Remove the domain or pattern from the list
     pub fn disallow(&mut self, domain: &str) -> Option<AllowedDomain<T>> {
-        if let Some(list) = self.allowed.remove(domain) {
-            let idx = self.domains.iter().position(|d| d == domain).unwrap();
+        let key = DomainPattern::parse(domain)
+            .canonicalize(&self.rules)
+            .to_string();
+        if let Some(list) = self.allowed.remove(&key) {
+            let idx = self.domains.iter().position(|d| *d == key).unwrap();
             self.domains.remove(idx);
+            self.patterns.retain(|pattern| pattern.to_string() != key);
             Some(list)
         } else {
             None
@@ -105,13 +251,18 @@ impl<T: fmt::Debug> AllowList<T> {
     }
 
     /// The matching handler for the allowed domain if any
+    ///
+    /// `domain` is either a host that is matched against all patterns or the
+    /// pattern itself.
     pub fn get_domain(&self, domain: impl AsRef<str>) -> Option<&AllowedDomain<T>> {
-        self.allowed.get(domain.as_ref())
+        let key = self.find_key(&self.rules.host(domain.as_ref()), None)?;
+        self.allowed.get(&key)
     }
 
     /// Get mutable access to the matching handler for the allowed domain if any
     pub fn get_domain_mut(&mut self, domain: impl AsRef<str>) -> Option<&mut AllowedDomain<T>> {
-        self.allowed.get_mut(domain.as_ref())
+        let key = self.find_key(&self.rules.host(domain.as_ref()), None)?;
+        self.allowed.get_mut(&key)
     }
 }
 
@@ -120,9 +271,11 @@ impl<T> AllowList<T>
 where
     T: Unpin + Send + Sync + fmt::Debug + 'static,
 {
+    #[allow(clippy::result_large_err)]
     pub(crate) fn add_request(&mut self, req: QueuedRequest<T>) -> Result<(), CrawlError<T>> {
-        if let Some(host) = req.request.url().host_str() {
-            if let Some(allowed) = self.allowed.get_mut(host) {
+        if let Some(host) = self.rules.url_host(req.request.url()) {
+            let key = self.find_key(&host, Some(req.request.url()));
+            if let Some(allowed) = key.and_then(|key| self.allowed.get_mut(&key)) {
                 allowed.add_request(req)
             } else {
                 Err(CrawlError::DisallowedRequest {
@@ -148,90 +301,233 @@ where
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let pin = self.get_mut();
+        poll_domains(pin.allowed.values_mut(), &mut pin.queued_results, cx)
+    }
+}
 
-        loop {
-            if let Some(res) = pin.queued_results.pop_front() {
-                return Poll::Ready(Some(res));
-            }
-            let mut busy = false;
-            for n in (0..pin.domains.len()).rev() {
-                let domain = pin.domains.swap_remove(n);
-                let (key, mut list) = pin.allowed.remove_entry(&domain).unwrap();
-                loop {
-                    match Stream::poll_next(Pin::new(&mut list), cx) {
-                        Poll::Ready(Some(res)) => {
-                            pin.queued_results.push_back(res);
-                        }
-                        Poll::Pending => {
-                            busy = true;
-                            break;
-                        }
-                        _ => break,
-                    }
-                }
-                pin.allowed.insert(key, list);
-                pin.domains.push(domain);
+/// The outcome of a single attempt to fetch a page
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Fetched<T> {
+    /// The final result of the request
+    Done(Result<Response<T>>),
+    /// The attempt failed for a transient reason and is retried after the
+    /// delay
+    Retry(QueuedRequest<T>, Duration),
+}
+
+type CrawlRequest<T> = Pin<Box<dyn Future<Output = Fetched<T>>>>;
+type RobotsTxtRequest = Pin<Box<dyn Future<Output = Result<Arc<RobotsData>>>>>;
+
+/// 在來源的 robots.txt 取得之前暫存請求
+struct RobotsGate<T> {
+    cache: RobotsCache,
+    /// 匹配 robots.txt 規則的產品標識，未設置時使用請求的 `User-Agent`
+    agent: Option<String>,
+    /// 正在請求 robots.txt 的來源
+    in_progress: Vec<(String, Url, RobotsTxtRequest)>,
+    /// 等待來源 robots.txt 的請求
+    waiting: HashMap<String, VecDeque<QueuedRequest<T>>>,
+    /// 請求 robots.txt 失敗的錯誤
+    errors: VecDeque<anyhow::Error>,
+}
+
+impl<T> RobotsGate<T> {
+    fn new(cache: RobotsCache) -> Self {
+        Self {
+            cache,
+            agent: None,
+            in_progress: Vec::new(),
+            waiting: Default::default(),
+            errors: Default::default(),
+        }
+    }
+
+    /// The cached robots.txt for the `url`, if it is not expired and not
+    /// assumed to disallow all urls while it is unreachable
+    fn get(&self, url: &Url) -> Option<Arc<RobotsData>> {
+        if self.cache.retry_in(url).is_some() {
+            return None;
+        }
+        self.cache.get(url)
+    }
+
+    /// The agent whose robots.txt rules apply to the `request`
+    fn agent<'a>(&'a self, request: &'a reqwest::Request) -> &'a str {
+        self.agent
+            .as_deref()
+            .unwrap_or_else(|| request_agent(request))
+    }
+
+    /// Whether the `robots` allow the `request`
+    fn allows(&self, robots: &RobotsData, request: &reqwest::Request) -> bool {
+        robots.check(request.url(), self.agent(request)).allowed
+    }
+
+    /// Hold the request back until the robots.txt of its origin is fetched.
+    ///
+    /// Returns the request if its url has no origin.
+    fn hold(&mut self, req: QueuedRequest<T>) -> Result<(), Box<QueuedRequest<T>>> {
+        let origin = match RobotsCache::origin(req.request.url()) {
+            Some(origin) => origin,
+            None => return Err(Box::new(req)),
+        };
+        if !self.waiting.contains_key(&origin) {
+            self.fetch(origin.clone(), req.request.url().clone());
+        }
+        self.waiting.entry(origin).or_default().push_back(req);
+        Ok(())
+    }
+
+    /// Fetch the robots.txt of the `origin`, once the unreachable one that is
+    /// cached expired
+    fn fetch(&mut self, origin: String, url: Url) {
+        let cache = self.cache.clone();
+        let retry_in = cache.retry_in(&url).unwrap_or_default();
+        let fetch_url = url.clone();
+        let fut = Box::pin(async move {
+            if !retry_in.is_zero() {
+                Delay::new(retry_in).await;
             }
+            cache.fetch(&fetch_url).await
+        });
+        self.in_progress.push((origin, url, fut));
+    }
 
-            if pin.queued_results.is_empty() {
-                return if !busy {
-                    Poll::Ready(None)
-                } else {
-                    Poll::Pending
+    /// The next error of a failed robots.txt request
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.errors.pop_front()
+    }
+
+    /// No robots.txt is fetched and all errors are reported
+    fn is_idle(&self) -> bool {
+        self.in_progress.is_empty() && self.errors.is_empty()
+    }
+}
+
+impl<T> RobotsGate<T>
+where
+    T: Send + Sync + fmt::Debug + 'static,
+{
+    /// Drive all robots.txt requests and release the requests that waited for
+    /// them.
+    ///
+    /// A robots.txt that failed to load is reported as
+    /// `CrawlError::RobotsTxtError` and the `RobotsPolicy` of the cache
+    /// applies. While the policy disallows all urls of the origin, the
+    /// requests keep waiting until the robots.txt is fetched again.
+    fn poll_fetched(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Vec<(Arc<RobotsData>, VecDeque<QueuedRequest<T>>)> {
+        let mut released = Vec::new();
+        for n in (0..self.in_progress.len()).rev() {
+            let (origin, url, mut fut) = self.in_progress.swap_remove(n);
+            if let Poll::Ready(result) = fut.poll_unpin(cx) {
+                let robots = match result {
+                    Ok(robots) => robots,
+                    Err(err) => {
+                        let host = url.host_str().unwrap_or_default().to_string();
+                        self.errors
+                            .push_back(err.context(CrawlError::<T>::RobotsTxtError { host }));
+                        self.cache
+                            .get(&url)
+                            .unwrap_or_else(|| self.cache.insert_unreachable(&url))
+                    }
                 };
+                if self.cache.retry_in(&url).is_some() {
+                    // hold the requests until the robots.txt is reachable
+                    self.fetch(origin, url);
+                    cx.waker().wake_by_ref();
+                    continue;
+                }
+                let requests = self.waiting.remove(&origin).unwrap_or_default();
+                released.push((robots, requests));
+            } else {
+                self.in_progress.push((origin, url, fut));
             }
         }
+        released
     }
 }
 
-type CrawlRequest<T> = Pin<Box<dyn Future<Output = Result<Response<T>>>>>;
-type RobotsTxtRequest = Pin<Box<dyn Future<Output = Result<RobotsData>>>>;
-
 pub struct AllowedDomain<T> {
-    client: reqwest::Client,
+    /// Sends the requests
+    fetchers: Arc<Fetchers>,
     /// Futures that eventually return a http response that is passed to the
     /// scraper
     in_progress_crawl_requests: Vec<CrawlRequest<T>>,
-    /// stores the future that requests the robots txt for a host
-    in_progress_robots_txt_crawls: Option<RobotsTxtRequest>,
-    /// Temporary buffer for requests that may arrive while robots.txt is being
-    /// fetched
-    tmp_request_buffer: Option<VecDeque<QueuedRequest<T>>>,
-    /// The request in process to fetch the the robots.txt
+    /// Holds back requests while the robots.txt of their origin is fetched
+    robots: RobotsGate<T>,
     /// all queued requests
     request_queue: RequestQueue<T>,
-    /// The parsed robots.txt contents of this domain
-    robots: Option<RobotsData>,
+    /// Failed requests that wait for their next attempt
+    retries: RetryQueue<T>,
+    /// When and how often to retry failed requests
+    retry: Option<Arc<RetryPolicy>>,
+    /// How response bodies are read
+    body: BodyLimits,
     /// Whether to ignore responses with a non 2xx response code see
     /// `reqwest::Response::is_success`
     skip_non_successful_responses: bool,
     /// Respect any restrictions set by the target host's robots.txt file
     respect_robots_txt: bool,
+    /// Apply the `Crawl-delay` of the robots.txt to the request queue
+    respect_crawl_delay: bool,
     /// The maximum depth request are allowed to next
     max_depth: usize,
-    /// Limits request to execute concurrently.
-    max_requests: usize,
+    /// Limits the requests that execute concurrently, shared with the other
+    /// domains
+    limits: Arc<ConcurrencyLimits>,
+    /// The host of the next request that is resolved for the per ip limits
+    resolving: Option<(String, ResolveFuture)>,
+    /// Adapts the delay and concurrency if the delay is `RequestDelay::Adaptive`
+    throttle: Option<Arc<Throttle>>,
+}
+
+/// The delay of the request queue and the throttle that replaces it, if the
+/// `delay` is adaptive
+fn throttled(delay: RequestDelay) -> (RequestDelay, Option<Arc<Throttle>>) {
+    match delay {
+        RequestDelay::Adaptive(config) => (
+            RequestDelay::Fixed(config.initial_delay()),
+            Some(Arc::new(Throttle::new(config))),
+        ),
+        delay => (delay, None),
+    }
 }
 
 impl<T: fmt::Debug> AllowedDomain<T> {
     pub fn new(config: AllowListConfig) -> Self {
+        let (delay, throttle) = match config.delay.map(throttled) {
+            Some((delay, throttle)) => (Some(delay), throttle),
+            None => (None, None),
+        };
         Self {
-            client: config.client,
+            fetchers: config
+                .fetchers
+                .unwrap_or_else(|| Arc::new(Fetchers::new(config.client))),
             in_progress_crawl_requests: Vec::new(),
-            in_progress_robots_txt_crawls: None,
-            request_queue: config
-                .delay
-                .map(RequestQueue::with_delay)
-                .unwrap_or_default(),
-            tmp_request_buffer: None,
-            robots: None,
+            robots: RobotsGate {
+                agent: config.robots_agent,
+                ..RobotsGate::new(config.robots)
+            },
+            request_queue: delay.map(RequestQueue::with_delay).unwrap_or_default(),
+            retries: Default::default(),
+            retry: config.retry,
+            body: config.body,
             skip_non_successful_responses: config.skip_non_successful_responses,
             respect_robots_txt: config.respect_robots_txt,
+            respect_crawl_delay: config.respect_crawl_delay,
             max_depth: config.max_depth,
-            max_requests: config.max_requests,
+            limits: config
+                .limits
+                .unwrap_or_else(|| Arc::new(ConcurrencyLimits::new(config.max_requests))),
+            resolving: None,
+            throttle,
         }
     }
 
+    #[allow(clippy::result_large_err)]
     pub(crate) fn add_request(&mut self, req: QueuedRequest<T>) -> Result<(), CrawlError<T>> {
         if req.depth > self.max_depth {
             return Err(CrawlError::ReachedMaxDepth {
@@ -241,35 +537,74 @@ impl<T: fmt::Debug> AllowedDomain<T> {
             });
         }
 
-        if self.respect_robots_txt && self.robots.is_none() {
-            if self.in_progress_robots_txt_crawls.is_none() {
-                // add request to fetch robots.txt
-                let mut url = req.request.url().clone();
-                url.set_path("robots.txt");
-                let fut = self.client.get(url).send();
-                let fut = Box::pin(async move {
-                    let resp = fut.await?;
-                    RobotsHandler::from_response(resp).await
-                });
-                self.in_progress_robots_txt_crawls = Some(fut);
-            }
+        if self.respect_robots_txt && self.robots.get(req.request.url()).is_none() {
             // robots not ready yet
-            let buf = self.tmp_request_buffer.get_or_insert(VecDeque::default());
-            buf.push_back(req);
+            self.robots
+                .hold(req)
+                .map_err(|req| CrawlError::InvalidRequest {
+                    request: req.request,
+                    state: req.state,
+                })
         } else {
             self.request_queue.queue_mut().push_back(req);
+            Ok(())
         }
-        Ok(())
+    }
+
+    /// The robots.txt of the `url`'s origin, if it is cached
+    pub fn robots_for(&self, url: &Url) -> Option<Arc<RobotsData>> {
+        self.robots.get(url)
     }
 
     /// Remove the configured delay
     pub fn remove_delay(&mut self) -> Option<RequestDelay> {
-        self.request_queue.remove_delay()
+        let throttle = self.throttle.take();
+        let delay = self.request_queue.remove_delay();
+        throttle.map(|throttle| throttle.delay()).or(delay)
     }
 
     /// Set a delay between requests
     pub fn set_delay(&mut self, delay: RequestDelay) -> Option<RequestDelay> {
-        self.request_queue.set_delay(delay)
+        let (delay, throttle) = throttled(delay);
+        let previous = std::mem::replace(&mut self.throttle, throttle);
+        let delay = self.request_queue.set_delay(delay);
+        previous.map(|throttle| throttle.delay()).or(delay)
+    }
+
+    /// The current delay and concurrency if the delay is adaptive
+    pub fn throttle(&self) -> Option<ThrottleSettings> {
+        self.throttle.as_ref().map(|throttle| throttle.settings())
+    }
+
+    /// The settings of the throttle, if they changed since the last call
+    pub(crate) fn take_throttle_change(&self) -> Option<ThrottleSettings> {
+        self.throttle.as_ref()?.take_changed()
+    }
+
+    /// Raise the delay to the `Crawl-delay` of the `robots` for the agent of
+    /// the `request`
+    fn raise_crawl_delay(&mut self, robots: &RobotsData, request: &reqwest::Request) {
+        if !self.respect_crawl_delay {
+            return;
+        }
+        match (robots.crawl_delay_for(self.robots.agent(request)), &self.throttle) {
+            (Some(crawl_delay), Some(throttle)) => throttle.raise_min_delay(crawl_delay),
+            (Some(crawl_delay), None) => {
+                self.request_queue.raise_delay(crawl_delay);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the domain has nothing left to do and the delay after its last
+    /// request is over, so it can be dropped without sending the next request
+    /// too early
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> bool {
+        self.in_progress_crawl_requests.is_empty()
+            && self.robots.is_idle()
+            && self.retries.is_empty()
+            && self.resolving.is_none()
+            && self.request_queue.poll_idle(cx).is_ready()
     }
 }
 
@@ -282,67 +617,102 @@ where
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let pin = self.get_mut();
 
-        // fetch robots.txt
-        if let Some(mut fut) = pin.in_progress_robots_txt_crawls.take() {
-            if let Poll::Ready(result) = fut.poll_unpin(cx) {
-                match result {
-                    Ok(data) => {
-                        pin.robots = Some(data);
-                    }
-                    Err(err) => return Poll::Ready(Some(Err(err))),
-                }
-            } else {
-                pin.in_progress_robots_txt_crawls = Some(fut);
-                return Poll::Pending;
+        if let Some(err) = pin.robots.take_error() {
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        // queue in all requests that arrived while robots.txt was being fetched
+        for (robots, requests) in pin.robots.poll_fetched(cx) {
+            if let Some(req) = requests.front() {
+                pin.raise_crawl_delay(&robots, &req.request);
             }
+            pin.request_queue.queue_mut().extend(requests);
         }
+        if let Some(err) = pin.robots.take_error() {
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        // requeue failed requests whose backoff is over
+        pin.retries.poll_requeue(&mut pin.request_queue, cx);
+
+        // follow the current delay and concurrency of the throttle
+        let max_in_flight = match &pin.throttle {
+            Some(throttle) => {
+                let settings = throttle.settings();
+                pin.request_queue.set_delay(RequestDelay::Fixed(settings.delay));
+                settings.concurrency
+            }
+            None => usize::MAX,
+        };
 
-        if let Some(mut tmp) = pin.tmp_request_buffer.take() {
-            // queue in all requests that arrived while robots.txt was being fetched
-            if let Some(robots) = pin.robots.take() {
-                while let Some(req) = tmp.pop_front() {
-                    if robots.is_not_disallowed(&req.request) {
-                        pin.request_queue.queue_mut().push_back(req);
-                    } else {
-                        pin.robots = Some(robots);
-                        pin.tmp_request_buffer = Some(tmp);
-                        return Poll::Ready(Some(Err(CrawlError::DisallowedRequest {
-                            request: req.request,
-                            state: req.state,
-                            reason: DisallowReason::RobotsTxt,
+        // the next request only leaves the queue once its host is resolved and
+        // it got a permit of every limit, finished requests of any domain
+        // release them
+        while let Some(permit) = pin
+            .request_queue
+            .queue_mut()
+            .front()
+            .filter(|_| pin.in_progress_crawl_requests.len() < max_in_flight)
+            .filter(|req| {
+                poll_resolved(
+                    &mut pin.resolving,
+                    &pin.fetchers,
+                    &pin.limits,
+                    req.request.url(),
+                    cx,
+                )
+            })
+            .and_then(|req| pin.limits.try_acquire(req.request.url()))
+        {
+            let req = match Stream::poll_next(Pin::new(&mut pin.request_queue), cx) {
+                Poll::Ready(Some(req)) => req,
+                _ => break,
+            };
+            let robots = if pin.respect_robots_txt {
+                match pin.robots.get(req.request.url()) {
+                    Some(robots) => Some(robots),
+                    None => {
+                        // the robots.txt expired, fetch it again
+                        if let Err(req) = pin.robots.hold(req) {
+                            return Poll::Ready(Some(Err(CrawlError::InvalidRequest {
+                                request: req.request,
+                                state: req.state,
+                            }
+                            .into())));
                         }
-                        .into())));
+                        cx.waker().wake_by_ref();
+                        continue;
                     }
                 }
-                pin.robots = Some(robots);
             } else {
-                // robots request failed
-                pin.request_queue.queue_mut().extend(tmp.into_iter());
-            }
-        }
+                None
+            };
 
-        while let Poll::Ready(Some(req)) = Stream::poll_next(Pin::new(&mut pin.request_queue), cx) {
-            if pin
-                .robots
-                .as_ref()
-                .map(|robots| robots.is_not_disallowed(&req.request))
+            if robots
+                .map(|robots| pin.robots.allows(&robots, &req.request))
                 .unwrap_or(true)
             {
                 // respect robots.txt
-                let mut fut = Box::pin(get_response(
-                    &pin.client,
+                let mut fut = get_response(
+                    &pin.fetchers,
                     req,
                     pin.skip_non_successful_responses,
-                ));
-                if let Poll::Ready(resp) = fut.poll_unpin(cx) {
-                    return Poll::Ready(Some(resp));
+                    pin.retry.as_ref(),
+                    pin.body,
+                    permit,
+                    pin.throttle.clone(),
+                );
+                if let Poll::Ready(fetched) = fut.poll_unpin(cx) {
+                    match fetched {
+                        Fetched::Done(resp) => return Poll::Ready(Some(resp)),
+                        Fetched::Retry(req, delay) => {
+                            pin.retries.push(req, delay);
+                            // make sure the delay is polled
+                            cx.waker().wake_by_ref();
+                        }
+                    }
                 } else {
                     pin.in_progress_crawl_requests.push(fut);
-
-                    if pin.in_progress_crawl_requests.len() > pin.max_requests {
-                        // stop when reached maximum of active requests
-                        break;
-                    }
                 }
             } else {
                 return Poll::Ready(Some(Err(CrawlError::DisallowedRequest {
@@ -358,16 +728,24 @@ where
         for n in (0..pin.in_progress_crawl_requests.len()).rev() {
             let mut request = pin.in_progress_crawl_requests.swap_remove(n);
 
-            if let Poll::Ready(resp) = request.poll_unpin(cx) {
-                return Poll::Ready(Some(resp));
+            if let Poll::Ready(fetched) = request.poll_unpin(cx) {
+                match fetched {
+                    Fetched::Done(resp) => return Poll::Ready(Some(resp)),
+                    Fetched::Retry(req, delay) => {
+                        pin.retries.push(req, delay);
+                        // make sure the delay is polled
+                        cx.waker().wake_by_ref();
+                    }
+                }
             } else {
                 pin.in_progress_crawl_requests.push(request);
             }
         }
 
         if pin.in_progress_crawl_requests.is_empty()
-            && pin.in_progress_robots_txt_crawls.is_none()
+            && pin.robots.is_idle()
             && pin.request_queue.is_empty()
+            && pin.retries.is_empty()
         {
             Poll::Ready(None)
         } else {
@@ -376,88 +754,274 @@ where
     }
 }
 
+/// Whether the host of the `url` is resolved for the per ip limits, starts to
+/// resolve it with the fetcher of the `url` otherwise
+fn poll_resolved(
+    resolving: &mut Option<(String, ResolveFuture)>,
+    fetchers: &Fetchers,
+    limits: &ConcurrencyLimits,
+    url: &Url,
+    cx: &mut Context<'_>,
+) -> bool {
+    let Some(host) = limits.unresolved_host(url) else {
+        return true;
+    };
+    if !matches!(resolving, Some((resolving, _)) if resolving == host) {
+        *resolving = Some((host.to_string(), fetchers.resolve(url)));
+    }
+    let (_, fut) = resolving.as_mut().unwrap();
+    match fut.poll_unpin(cx) {
+        Poll::Ready(addr) => {
+            // a host that can't be resolved isn't limited by its address
+            limits.insert_addr(host, addr);
+            *resolving = None;
+            true
+        }
+        Poll::Pending => false,
+    }
+}
+
+#[derive(Clone)]
 pub struct AllowListConfig {
     pub delay: Option<RequestDelay>,
     pub respect_robots_txt: bool,
+    /// Whether a robots.txt `Crawl-delay` may raise `delay`
+    pub respect_crawl_delay: bool,
     pub client: reqwest::Client,
     pub skip_non_successful_responses: bool,
     pub max_depth: usize,
+    /// Limits request to execute concurrently, if there are no `limits`
     pub max_requests: usize,
+    /// The limits of concurrent requests, shared by all domains
+    pub limits: Option<Arc<ConcurrencyLimits>>,
+    /// Retry requests that failed for transient reasons
+    pub retry: Option<Arc<RetryPolicy>>,
+    /// How response bodies are read
+    pub body: BodyLimits,
+    /// The cache of robots.txt files, shared by all domains
+    pub robots: RobotsCache,
+    /// The product token that selects the robots.txt rules, the `User-Agent`
+    /// of each request if `None`
+    pub robots_agent: Option<String>,
+    /// Sends the requests, the `client` if `None`
+    pub fetchers: Option<Arc<Fetchers>>,
 }
 
+/// Allows all domains but the blocked ones.
+///
+/// Every host gets its own `AllowedDomain` with the delay, robots.txt rules
+/// and concurrency of the default policy once a request to it is queued, so
+/// a delay throttles each host instead of the whole crawl.
 pub struct BlockList<T> {
-    client: reqwest::Client,
     /// list of domains that are blocked
     blocked_domains: HashSet<String>,
-    /// Futures that eventually return a http response that is passed to the
-    /// scraper
-    in_progress_crawl_requests: Vec<CrawlRequest<T>>,
-    /// mapping of hosts to robots.txt data
-    robots_map: HashMap<String, RobotsData>,
-    /// stores the futures that request the robots txt for a host
-    in_progress_robots_txt_crawls: Vec<(String, RobotsTxtRequest)>,
-    /// Currently in progress robot txt crawl request
-    in_progress_robots_txt_crawl_hosts: HashSet<String>,
-    /// Respect any restrictions set by the target host's robots.txt file
-    respect_robots_txt: bool,
-    /// Whether to ignore responses with a non 2xx response code see
-    /// `reqwest::Response::is_success`
-    skip_non_successful_responses: bool,
-    /// The maximum depth request are allowed to next
-    max_depth: usize,
-    /// all queued requests
-    request_queue: RequestQueue<T>,
-    /// Limits request to execute concurrently.
-    max_requests: usize,
+    /// patterns of domains that are blocked, other than exact domains
+    blocked_patterns: Vec<DomainPattern>,
+    /// The public suffix list to match registrable domains
+    suffixes: Arc<PublicSuffixList>,
+    /// The politeness policy of hosts that are requested for the first time
+    policy: AllowListConfig,
+    /// The requested hosts by their canonical host, a host is dropped once it
+    /// is idle
+    hosts: HashMap<String, AllowedDomain<T>>,
+    /// The throttles of the dropped hosts, a host that is requested again
+    /// continues with its delay and concurrency
+    throttles: HashMap<String, Arc<Throttle>>,
+    /// The hosts whose throttles were removed since the throttles were
+    /// reported
+    dropped_throttles: Vec<String>,
+    /// Results of the hosts that were not yielded yet
+    queued_results: VecDeque<Result<Response<T>>>,
+    /// The rules to canonicalize hosts before they are matched
+    rules: CanonicalizeRules,
+    /// The middlewares, in order
+    middleware: Middlewares<T>,
 }
 
 impl<T> BlockList<T> {
-    pub fn new(
-        blocked_domains: HashSet<String>,
+    /// Create a new list that blocks the `blocked_domains`, every entry is a
+    /// `DomainPattern`
+    pub fn new<I, P>(
+        blocked_domains: I,
         client: reqwest::Client,
         respect_robots_txt: bool,
         skip_non_successful_responses: bool,
         max_depth: usize,
         max_requests: usize,
-    ) -> Self {
-        BlockList {
-            client,
-            blocked_domains,
-            in_progress_crawl_requests: Vec::new(),
-            robots_map: Default::default(),
-            in_progress_robots_txt_crawls: Vec::new(),
-            in_progress_robots_txt_crawl_hosts: Default::default(),
-            respect_robots_txt,
-            skip_non_successful_responses,
-            request_queue: Default::default(),
-            max_depth,
-            max_requests,
+        rules: CanonicalizeRules,
+    ) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<DomainPattern>,
+    {
+        let mut list = BlockList {
+            blocked_domains: Default::default(),
+            blocked_patterns: Vec::new(),
+            suffixes: default_suffixes(),
+            policy: AllowListConfig {
+                delay: None,
+                respect_robots_txt,
+                respect_crawl_delay: true,
+                client: client.clone(),
+                skip_non_successful_responses,
+                max_depth,
+                max_requests,
+                // the hosts share the limits
+                limits: Some(Arc::new(ConcurrencyLimits::new(max_requests))),
+                retry: None,
+                body: Default::default(),
+                robots: RobotsCache::new(client.clone()),
+                robots_agent: None,
+                fetchers: Some(Arc::new(Fetchers::new(client))),
+            },
+            hosts: Default::default(),
+            throttles: Default::default(),
+            dropped_throttles: Default::default(),
+            queued_results: Default::default(),
+            rules,
+            middleware: Default::default(),
+        };
+        for pattern in blocked_domains {
+            list.disallow_pattern(pattern.into());
+        }
+        list
+    }
+
+    /// Use the `suffixes` to match `DomainPattern::Registrable` patterns
+    pub fn with_public_suffix_list(mut self, suffixes: Arc<PublicSuffixList>) -> Self {
+        self.suffixes = suffixes;
+        self
+    }
+
+    /// Delay the requests to each host
+    pub fn with_delay(mut self, delay: RequestDelay) -> Self {
+        self.policy.delay = Some(delay);
+        self
+    }
+
+    /// Don't raise the delay of a host to the `Crawl-delay` of its robots.txt
+    pub fn ignore_crawl_delay(mut self) -> Self {
+        self.policy.respect_crawl_delay = false;
+        self
+    }
+
+    /// Retry requests that failed for transient reasons according to the
+    /// `policy`
+    pub fn with_retry_policy(mut self, policy: Arc<RetryPolicy>) -> Self {
+        self.policy.retry = Some(policy);
+        self
+    }
+
+    /// Read response bodies according to the `limits`
+    pub fn with_body_limits(mut self, limits: BodyLimits) -> Self {
+        self.policy.body = limits;
+        self
+    }
+
+    /// Send the requests with the `fetchers` instead of the client
+    pub fn with_fetchers(mut self, fetchers: Arc<Fetchers>) -> Self {
+        self.policy.fetchers = Some(fetchers);
+        self
+    }
+
+    /// Limit the concurrent requests with the `limits` instead of
+    /// `max_requests`
+    pub fn with_limits(mut self, limits: Arc<ConcurrencyLimits>) -> Self {
+        self.policy.limits = Some(limits);
+        self
+    }
+
+    /// Share the `cache` of robots.txt files
+    pub fn with_robots_cache(mut self, cache: RobotsCache) -> Self {
+        self.policy.robots = cache;
+        self
+    }
+
+    /// Obey the robots.txt rules for the product token `agent` instead of the
+    /// `User-Agent` of each request
+    pub fn with_robots_agent(mut self, agent: impl Into<String>) -> Self {
+        self.policy.robots_agent = Some(agent.into());
+        self
+    }
+
+    /// The robots.txt of the `url`'s origin, if it is cached
+    pub fn robots_for(&self, url: &Url) -> Option<Arc<RobotsData>> {
+        self.policy.robots.get(url)
+    }
+
+    /// The queue of the `host`, if a request to it is queued or in flight
+    pub fn get_host(&self, host: impl AsRef<str>) -> Option<&AllowedDomain<T>> {
+        self.hosts.get(&self.rules.host(host.as_ref()))
+    }
+
+    /// Get mutable access to the queue of the `host`, if a request to it is
+    /// queued or in flight, for example to change its delay.
+    ///
+    /// The queue is dropped once the host is idle, later requests to the host
+    /// use the delay of the list again.
+    pub fn get_host_mut(&mut self, host: impl AsRef<str>) -> Option<&mut AllowedDomain<T>> {
+        self.hosts.get_mut(&self.rules.host(host.as_ref()))
+    }
+
+    /// Block all urls that match the `pattern`
+    pub fn disallow_pattern(&mut self, pattern: DomainPattern) {
+        match pattern.canonicalize(&self.rules) {
+            DomainPattern::Exact(domain) => {
+                self.blocked_domains.insert(domain);
+            }
+            pattern => self.blocked_patterns.push(pattern),
         }
     }
+
+    /// Whether the `url` has a host that is not blocked
+    fn is_allowed(&self, url: &Url) -> bool {
+        self.rules
+            .url_host(url)
+            .is_some_and(|host| !self.is_blocked(&host, url))
+    }
+
+    /// Whether the `url` with the canonical `host` is blocked
+    fn is_blocked(&self, host: &str, url: &Url) -> bool {
+        self.blocked_domains.contains(host)
+            || self
+                .blocked_patterns
+                .iter()
+                .any(|pattern| pattern.matches(host, url, &self.suffixes))
+    }
 }
 
 impl<T> BlockList<T>
 where
     T: Unpin + Send + Sync + fmt::Debug + 'static,
 {
+    #[allow(clippy::result_large_err)]
     pub(crate) fn add_request(&mut self, req: QueuedRequest<T>) -> Result<(), CrawlError<T>> {
-        if req.depth > self.max_depth {
-            return Err(CrawlError::ReachedMaxDepth {
-                request: req.request,
-                state: req.state,
-                depth: req.depth,
-            });
-        }
-        if let Some(host) = req.request.url().host_str() {
-            if self.blocked_domains.contains(host) {
+        if let Some(host) = self.rules.url_host(req.request.url()) {
+            if self.is_blocked(&host, req.request.url()) {
                 Err(CrawlError::DisallowedRequest {
                     request: req.request,
                     state: req.state,
                     reason: DisallowReason::UserConfig,
                 })
             } else {
-                self.request_queue.queue_mut().push_back(req);
-                Ok(())
+                let domain = match self.hosts.entry(host) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let mut domain = AllowedDomain::new(self.policy.clone());
+                        if let Some(throttle) = self.throttles.remove(entry.key()) {
+                            domain.throttle = Some(throttle);
+                        }
+                        // the robots.txt of a host that was dropped is still cached
+                        let robots = Some(&domain)
+                            .filter(|domain| domain.respect_robots_txt)
+                            .and_then(|domain| domain.robots_for(req.request.url()));
+                        if let Some(robots) = robots {
+                            domain.raise_crawl_delay(&robots, &req.request);
+                        }
+                        entry.insert(domain)
+                    }
+                };
+                domain.add_request(req)
             }
         } else {
             Err(CrawlError::InvalidRequest {
@@ -467,24 +1031,49 @@ where
         }
     }
 
-    /// Block requests to that domain
+    /// Block requests to that domain, `domain` is parsed as `DomainPattern`
     pub fn disallow(&mut self, domain: impl Into<String>) {
-        self.blocked_domains.insert(domain.into());
+        self.disallow_pattern(DomainPattern::parse(&domain.into()))
     }
 
-    /// Remove a domain from the block list
+    /// Remove a domain or pattern from the block list
     pub fn allow(&mut self, domain: impl AsRef<str>) {
-        self.blocked_domains.remove(domain.as_ref());
+        match DomainPattern::parse(domain.as_ref()).canonicalize(&self.rules) {
+            DomainPattern::Exact(domain) => {
+                self.blocked_domains.remove(&domain);
+            }
+            pattern => {
+                let key = pattern.to_string();
+                self.blocked_patterns
+                    .retain(|pattern| pattern.to_string() != key);
+            }
+        }
     }
 
-    /// Remove the configured delay
+    /// Remove the delay of all hosts, including those requested later
     pub fn remove_delay(&mut self) -> Option<RequestDelay> {
-        self.request_queue.remove_delay()
+        for host in self.hosts.values_mut() {
+            host.remove_delay();
+        }
+        self.drop_throttles();
+        self.policy.delay.take()
     }
 
-    /// Set a delay between requests
+    /// Set a delay between the requests to each host, including those
+    /// requested later
     pub fn set_delay(&mut self, delay: RequestDelay) -> Option<RequestDelay> {
-        self.request_queue.set_delay(delay)
+        for host in self.hosts.values_mut() {
+            host.set_delay(delay);
+        }
+        self.drop_throttles();
+        self.policy.delay.replace(delay)
+    }
+
+    /// Forget the throttles of the dropped hosts, they follow the new delay
+    /// once they are requested again
+    fn drop_throttles(&mut self) {
+        self.dropped_throttles
+            .extend(self.throttles.drain().map(|(host, _)| host));
     }
 }
 
@@ -496,114 +1085,65 @@ where
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let pin = self.get_mut();
-
-        // drive all robots.txt to completion
-        for n in (0..pin.in_progress_robots_txt_crawls.len()).rev() {
-            let (host, mut fut) = pin.in_progress_robots_txt_crawls.swap_remove(n);
-            if let Poll::Ready(result) = fut.poll_unpin(cx) {
-                match result {
-                    Ok(robots) => {
-                        pin.in_progress_robots_txt_crawl_hosts.remove(&host);
-                        pin.robots_map.insert(host, robots);
-                    }
-                    Err(_) => {
-                        // robots.txt lookup failed
-                        pin.in_progress_robots_txt_crawl_hosts.remove(&host);
-                        return Poll::Ready(Some(Err(
-                            CrawlError::<T>::RobotsTxtError { host }.into()
-                        )));
-                    }
-                }
-            } else {
-                pin.in_progress_robots_txt_crawls.push((host, fut))
+        let polled = poll_domains(pin.hosts.values_mut(), &mut pin.queued_results, cx);
+        // drop the idle hosts, so only the active ones are polled, but keep
+        // what their throttles learned
+        let throttles = &mut pin.throttles;
+        pin.hosts.retain(|key, host| {
+            if !host.poll_idle(cx) {
+                return true;
             }
-        }
-
-        // we add back to the queue in case robots.txt is required and not ready
-        for _ in 0..pin.request_queue.len() {
-            if pin.in_progress_crawl_requests.len() > pin.max_requests {
-                break;
+            if let Some(throttle) = host.throttle.take() {
+                throttles.insert(key.clone(), throttle);
             }
+            false
+        });
+        polled
+    }
+}
 
-            if let Poll::Ready(Some(req)) = Stream::poll_next(Pin::new(&mut pin.request_queue), cx)
-            {
-                if pin.respect_robots_txt {
-                    if let Some(host) = req.request.url().host_str() {
-                        if let Some(robots) = pin.robots_map.get(host) {
-                            if robots.is_not_disallowed(&req.request) {
-                                let fut = Box::pin(get_response(
-                                    &pin.client,
-                                    req,
-                                    pin.skip_non_successful_responses,
-                                ));
-                                pin.in_progress_crawl_requests.push(fut);
-                            } else {
-                                return Poll::Ready(Some(Err(CrawlError::DisallowedRequest {
-                                    request: req.request,
-                                    state: req.state,
-                                    reason: DisallowReason::RobotsTxt,
-                                }
-                                .into())));
-                            }
-                        } else {
-                            let mut url = req.request.url().clone();
-                            url.set_path("robots.txt");
-                            let fut = pin.client.get(url).send();
-
-                            let fut = Box::pin(async move {
-                                let resp = fut.await?;
-                                RobotsHandler::from_response(resp).await
-                            });
-
-                            pin.in_progress_robots_txt_crawls
-                                .push((host.to_string(), fut));
-                            pin.in_progress_robots_txt_crawl_hosts
-                                .insert(host.to_string());
-                            pin.request_queue.queue_mut().push_back(req);
-                        }
-                    } else {
-                        return Poll::Ready(Some(Err(CrawlError::InvalidRequest {
-                            request: req.request,
-                            state: req.state,
-                        }
-                        .into())));
-                    }
-                } else {
-                    let fut = Box::pin(get_response(
-                        &pin.client,
-                        req,
-                        pin.skip_non_successful_responses,
-                    ));
-                    pin.in_progress_crawl_requests.push(fut);
+/// Poll every domain until it is pending or done and yield their results in
+/// order, `None` once all domains are done
+fn poll_domains<'a, T, I>(
+    domains: I,
+    queued_results: &mut VecDeque<Result<Response<T>>>,
+    cx: &mut Context<'_>,
+) -> Poll<Option<Result<Response<T>>>>
+where
+    T: Unpin + Send + Sync + fmt::Debug + 'static,
+    I: Iterator<Item = &'a mut AllowedDomain<T>>,
+{
+    if let Some(res) = queued_results.pop_front() {
+        return Poll::Ready(Some(res));
+    }
+    let mut busy = false;
+    for domain in domains {
+        loop {
+            match Stream::poll_next(Pin::new(&mut *domain), cx) {
+                Poll::Ready(Some(res)) => queued_results.push_back(res),
+                Poll::Pending => {
+                    busy = true;
+                    break;
                 }
+                Poll::Ready(None) => break,
             }
         }
-
-        for n in (0..pin.in_progress_crawl_requests.len()).rev() {
-            let mut request = pin.in_progress_crawl_requests.swap_remove(n);
-
-            if let Poll::Ready(resp) = request.poll_unpin(cx) {
-                return Poll::Ready(Some(resp));
-            } else {
-                pin.in_progress_crawl_requests.push(request);
-            }
-        }
-
-        if pin.in_progress_crawl_requests.is_empty()
-            && pin.request_queue.is_empty()
-            && pin.in_progress_robots_txt_crawls.is_empty()
-        {
-            Poll::Ready(None)
-        } else {
-            Poll::Pending
-        }
+    }
+    match queued_results.pop_front() {
+        Some(res) => Poll::Ready(Some(res)),
+        None if busy => Poll::Pending,
+        None => Poll::Ready(None),
     }
 }
 
 fn get_response<T>(
-    client: &reqwest::Client,
+    fetchers: &Arc<Fetchers>,
     request: QueuedRequest<T>,
     skip_non_successful_responses: bool,
+    retry: Option<&Arc<RetryPolicy>>,
+    limits: BodyLimits,
+    permit: Permit,
+    throttle: Option<Arc<Throttle>>,
 ) -> CrawlRequest<T>
 where
     T: Unpin + Send + Sync + fmt::Debug + 'static,
@@ -612,37 +1152,137 @@ where
         request,
         state,
         depth,
+        mut attempts,
+        ticket,
     } = request;
     let request_url = request.url().clone();
     let skip_http_error_response = skip_non_successful_responses;
 
-    let request = client.execute(request);
+    // keep a copy of the request for the next attempt, streaming bodies can't be
+    // retried
+    let policy = retry.cloned();
+    let retry_request = policy
+        .as_ref()
+        .filter(|policy| attempts.len() + 1 < policy.attempts())
+        .and_then(|_| request.try_clone());
+
+    let fetchers = Arc::clone(fetchers);
 
     Box::pin(async move {
-        let mut resp = request.await?;
+        // the permits are held until the body was read
+        let _permit = permit;
+        let sent = std::time::Instant::now();
+        let resp = fetchers.fetch(request).await;
+        if let Some(throttle) = throttle {
+            let response = resp.as_ref().ok().map(|resp| (resp.status(), resp.headers()));
+            throttle.record(sent.elapsed(), response);
+        }
 
-        if !resp.status().is_success() && skip_http_error_response {
-            // skip unsuccessful response
-            return Err(CrawlError::NoSuccessResponse {
-                request_url: Some(request_url),
-                response: resp,
-                state,
+        if let Some(policy) = policy {
+            // the failed attempt and the delay before the next one, `None` if the
+            // server asks to wait longer than the policy allows
+            let failed = match &resp {
+                Ok(resp) if policy.is_retryable_status(resp.status()) => Some((
+                    Attempt {
+                        status: Some(resp.status()),
+                        error: None,
+                        delay: Duration::default(),
+                    },
+                    policy.delay(attempts.len() + 1, Some(resp.headers())),
+                )),
+                Err(err) if policy.is_retryable_fetch_error(err) => {
+                    Some((
+                        Attempt {
+                            status: None,
+                            error: Some(err.to_string()),
+                            delay: Duration::default(),
+                        },
+                        policy.delay(attempts.len() + 1, None),
+                    ))
+                }
+                _ => None,
+            };
+            match (failed, retry_request) {
+                (Some((mut attempt, Some(delay))), Some(request)) => {
+                    attempt.delay = delay;
+                    attempts.push(attempt);
+                    return Fetched::Retry(
+                        QueuedRequest {
+                            request,
+                            state,
+                            depth,
+                            attempts,
+                            ticket,
+                        },
+                        delay,
+                    );
+                }
+                (Some((attempt, _)), _) if !attempts.is_empty() => {
+                    // this was the last attempt
+                    attempts.push(attempt);
+                    return Fetched::Done(Err(CrawlError::RetriesExhausted {
+                        request_url,
+                        attempts,
+                        state,
+                    }
+                    .into()));
+                }
+                _ => {}
             }
-            .into());
         }
 
-        let (status, url, headers) = response_info(&mut resp);
+        Fetched::Done(
+            read_response(resp, request_url, state, depth, ticket, skip_http_error_response, limits)
+                .await,
+        )
+    })
+}
 
-        let text = resp.text().await?;
+async fn read_response<T>(
+    resp: Result<reqwest::Response>,
+    request_url: Url,
+    state: Option<T>,
+    depth: usize,
+    ticket: Option<Ticket>,
+    skip_http_error_response: bool,
+    limits: BodyLimits,
+) -> Result<Response<T>>
+where
+    T: Unpin + Send + Sync + fmt::Debug + 'static,
+{
+    let mut resp = resp?;
 
-        Ok(Response {
-            depth,
-            request_url,
-            response_url: url,
-            response_status: status,
-            response_headers: headers,
-            text,
+    if !resp.status().is_success() && skip_http_error_response {
+        // skip unsuccessful response
+        return Err(CrawlError::NoSuccessResponse {
+            request_url: Some(request_url),
+            response: resp,
             state,
-        })
+        }
+        .into());
+    }
+
+    let (status, url, headers) = response_info(&mut resp);
+
+    let (body, stream) = match read_body(resp, limits).await? {
+        Body::Bytes(body) => (body, None),
+        Body::Stream(stream) => (Default::default(), Some(stream)),
+        Body::TooLarge => {
+            return Err(CrawlError::BodyTooLarge {
+                request_url,
+                limit: limits.max_size.unwrap_or_default(),
+                state,
+            }
+            .into())
+        }
+    };
+
+    Ok(Response {
+        depth,
+        request_url,
+        stream,
+        state,
+        ticket,
+        ..Response::new(url, status, headers, body)
     })
 }
@@ -2,6 +2,8 @@ use reqwest::{Url, Request, Response, Error};
 use std::fmt;
 use thiserror::Error;
 
+use crate::retry::Attempt;
+
 #[derive(Debug, Error)]
 pub enum CrawlError<T: fmt::Debug> {
     #[error("Received response with non 2xx status {:?} for {:?} carrying state: {:?}", .response, .request_url, .state)]
@@ -29,10 +31,29 @@ pub enum CrawlError<T: fmt::Debug> {
         state: Option<T>,
         depth: usize,
     },
+    #[error("Gave up on {} after {} attempts, last: {}, while carrying state: {:?}", .request_url, .attempts.len(), .attempts.last().map(ToString::to_string).unwrap_or_default(), .state)]
+    RetriesExhausted {
+        request_url: Url,
+        /// 所有失敗的嘗試，按時間順序
+        attempts: Vec<Attempt>,
+        state: Option<T>,
+    },
+    #[error("Response body of {} exceeds the limit of {} bytes while carrying state: {:?}", .request_url, .limit, .state)]
+    BodyTooLarge {
+        request_url: Url,
+        /// 配置的最大響應體大小
+        limit: usize,
+        state: Option<T>,
+    },
     #[error("Failed to fetch robots.txt from host: {}", .host)]
     RobotsTxtError {
         host: String,
     },
+    #[error("Dropped a request for {}, because it was already queued before, while carrying state: {:?}", .request.url(), .state)]
+    DuplicateRequest {
+        request: Request,
+        state: Option<T>,
+    },
     #[error("Rejected a request, because its url is disallowed due to {}, while carrying state: {:?}", .reason, .state)]
     DisallowedRequest {
         reason: DisallowReason,
@@ -49,7 +70,10 @@ impl<T: fmt::Debug> CrawlError<T> {
             CrawlError::FailedToBuildRequest { state, .. } => state.as_ref(),
             CrawlError::InvalidRequest { state, .. } => state.as_ref(),
             CrawlError::ReachedMaxDepth { state, .. } => state.as_ref(),
+            CrawlError::RetriesExhausted { state, .. } => state.as_ref(),
+            CrawlError::BodyTooLarge { state, .. } => state.as_ref(),
             CrawlError::RobotsTxtError { .. } => None,
+            CrawlError::DuplicateRequest { state, .. } => state.as_ref(),
             CrawlError::DisallowedRequest { state, .. } => state.as_ref(),
         }
     }
@@ -61,7 +85,10 @@ impl<T: fmt::Debug> CrawlError<T> {
             CrawlError::FailedToBuildRequest { state, .. } => state,
             CrawlError::InvalidRequest { state, .. } => state,
             CrawlError::ReachedMaxDepth { state, .. } => state,
+            CrawlError::RetriesExhausted { state, .. } => state,
+            CrawlError::BodyTooLarge { state, .. } => state,
             CrawlError::RobotsTxtError { .. } => None,
+            CrawlError::DuplicateRequest { state, .. } => state,
             CrawlError::DisallowedRequest { state, .. } => state,
         }
     }
@@ -100,4 +127,56 @@ impl UnexpectedStatusError {
     pub fn status(&self) -> u16 {
         self.0
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Error)]
+pub enum SitemapError {
+    #[error("Failed to parse sitemap xml: {0}")]
+    InvalidXml(#[from] roxmltree::Error),
+    #[error("Expected a <urlset> or <sitemapindex> root element, found <{0}>")]
+    UnexpectedRoot(String),
+    #[error("Sitemap exceeds the maximum size of {0} bytes")]
+    TooLarge(usize),
+    #[error("Failed to decompress gzipped sitemap: {0}")]
+    Gzip(#[from] std::io::Error),
+    #[error("Sitemap {0} nests more than {1} sitemap indexes")]
+    TooDeep(Url, usize),
+    #[error("Sitemap {0} was not fetched: {1}")]
+    Disallowed(Url, DisallowReason),
+}
+
+#[derive(Debug, Error)]
+pub enum ExtractError {
+    #[error("Invalid css selector `{0}`: {1}")]
+    InvalidSelector(String, String),
+    #[error("No element matches the selector `{0}`")]
+    NoMatch(String),
+    #[error("The element matching `{selector}` has no attribute `{attr}`")]
+    MissingAttribute { selector: String, attr: String },
+    #[error("Failed to resolve `{href}` against {base}: {error}")]
+    InvalidUrl {
+        href: String,
+        base: Url,
+        error: url::ParseError,
+    },
+    #[error("The required field `{0}` matches nothing")]
+    MissingField(String),
+    #[error("The value `{value}` of the field `{field}` is not a number")]
+    InvalidNumber { field: String, value: String },
+    #[error("Invalid regex: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error("Failed to deserialize: {0}")]
+    Deserialize(#[from] serde_json::Error),
+    #[error("Invalid query `{0}`: {1}")]
+    InvalidQuery(String, String),
+    #[error("Failed to parse xml: {0}")]
+    InvalidXml(#[from] roxmltree::Error),
+    #[error("Expected an <rss>, <rdf:RDF> or <feed> root element, found <{0}>")]
+    NotAFeed(String),
+}
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("The item failed the validation of the field `{0}`")]
+    Invalid(String),
+}
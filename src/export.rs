@@ -0,0 +1,940 @@
+//! Sinks that export the items of a `Collector` to files or a database.
+//!
+//! The sinks are `ItemPipeline` stages for every `Output: Serialize`, they
+//! write each item and pass it on. The files and the database are written on
+//! a thread of the sink, and flushed when the collector is done or the sink is
+//! dropped:
+//!
+//! ```no_run
+//! # use rust_crawler::export::JsonLinesSink;
+//! # fn pipe<T: rust_crawler::Scraper>(collector: rust_crawler::Collector<T>)
+//! # where T::Output: serde::Serialize + 'static, T::State: std::fmt::Debug {
+//! let collector = collector.pipe(JsonLinesSink::new("items.jsonl"));
+//! # }
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use futures::channel::oneshot;
+use futures::future;
+use serde::de::value::SeqAccessDeserializer;
+use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::pipeline::{CloseFuture, ItemFuture, ItemPipeline};
+
+/// When a file sink continues in a new file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+impl Rotation {
+    /// Start a new file once the current one reached `bytes`
+    pub fn size(bytes: u64) -> Self {
+        Self::default().max_size(bytes)
+    }
+
+    /// Start a new file once the current one is older than `age`
+    pub fn age(age: Duration) -> Self {
+        Self::default().max_age(age)
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+}
+
+type Job<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+/// A thread that owns the file or database of a sink, so its blocking IO
+/// doesn't stall the crawl.
+///
+/// The jobs run in order, the thread exits once the writer is dropped and
+/// drops the state after the last job.
+#[derive(Debug)]
+struct Writer<S> {
+    jobs: Option<mpsc::Sender<Job<S>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<S: Send + 'static> Writer<S> {
+    fn spawn(mut state: S) -> Self {
+        let (jobs, queued) = mpsc::channel::<Job<S>>();
+        let thread = thread::spawn(move || {
+            for job in queued {
+                job(&mut state);
+            }
+        });
+        Self {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Run the `job` on the thread, resolves once it is done
+    fn run<F>(&self, job: F) -> impl Future<Output = Result<()>>
+    where
+        F: FnOnce(&mut S) -> Result<()> + Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(Box::new(move |state| {
+                let _ = done.send(job(state));
+            }));
+        }
+        async move {
+            result
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("The writer thread of the sink stopped")))
+        }
+    }
+}
+
+impl<S> Drop for Writer<S> {
+    fn drop(&mut self) {
+        // wait for the queued jobs, the state flushes when it is dropped
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The file a sink writes to, opened on the first write.
+///
+/// Rotated files are numbered, `items.jsonl` becomes `items-1.jsonl`,
+/// `items-2.jsonl` and so on.
+#[derive(Debug)]
+struct OutputFile {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    /// Written at the start of every file
+    header: Vec<u8>,
+    writer: Option<BufWriter<File>>,
+    /// The bytes written to the current file
+    written: u64,
+    opened: Instant,
+    /// The number of the current file
+    index: usize,
+}
+
+impl OutputFile {
+    fn new(path: PathBuf, rotation: Option<Rotation>, header: Vec<u8>) -> Self {
+        Self {
+            path,
+            rotation,
+            header,
+            writer: None,
+            written: 0,
+            opened: Instant::now(),
+            index: 0,
+        }
+    }
+
+    /// The path of the current file
+    fn current_path(&self) -> PathBuf {
+        if self.rotation.is_none() {
+            return self.path.clone();
+        }
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match self.path.extension() {
+            Some(ext) => format!("{}-{}.{}", stem, self.index, ext.to_string_lossy()),
+            None => format!("{}-{}", stem, self.index),
+        };
+        self.path.with_file_name(name)
+    }
+
+    fn is_due(&self) -> bool {
+        match self.rotation {
+            Some(rotation) => {
+                rotation.max_size.is_some_and(|size| self.written >= size)
+                    || rotation
+                        .max_age
+                        .is_some_and(|age| self.opened.elapsed() >= age)
+            }
+            None => false,
+        }
+    }
+
+    /// Open the next file if there is none or the current one is due for
+    /// rotation
+    fn prepare(&mut self) -> Result<()> {
+        if self.writer.is_some() && !self.is_due() {
+            return Ok(());
+        }
+        self.flush()?;
+        self.index += 1;
+        let mut writer = BufWriter::new(File::create(self.current_path())?);
+        writer.write_all(&self.header)?;
+        self.writer = Some(writer);
+        self.written = self.header.len() as u64;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.try_write(bytes)
+            .with_context(|| format!("Failed to write to {}", self.current_path().display()))
+    }
+
+    fn try_write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.prepare()?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(bytes)?;
+            self.written += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Write the `bytes` on the thread of the `writer`, resolves to the `item`
+fn write_item<O: 'static>(writer: &Writer<OutputFile>, bytes: Vec<u8>, item: O) -> ItemFuture<O> {
+    let written = writer.run(move |file| file.write(&bytes));
+    Box::pin(async move {
+        written.await?;
+        Ok(Some(item))
+    })
+}
+
+/// Flush the file on the thread of the `writer`, if it was opened
+fn close_file(writer: Option<&Writer<OutputFile>>) -> CloseFuture {
+    match writer {
+        Some(writer) => Box::pin(writer.run(OutputFile::flush)),
+        None => Box::pin(future::ready(Ok(()))),
+    }
+}
+
+/// Writes every item as one line of JSON
+#[derive(Debug)]
+pub struct JsonLinesSink {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    writer: Option<Writer<OutputFile>>,
+}
+
+impl JsonLinesSink {
+    /// Write to the file at `path`, which is truncated when the first item is
+    /// written
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            rotation: None,
+            writer: None,
+        }
+    }
+
+    /// Continue in a new file according to the `rotation`
+    pub fn rotate(mut self, rotation: Rotation) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+}
+
+impl<O: Serialize + 'static> ItemPipeline<O> for JsonLinesSink {
+    fn process_item(&mut self, item: O) -> ItemFuture<O> {
+        let mut line = match serde_json::to_vec(&item) {
+            Ok(line) => line,
+            Err(err) => {
+                let err = anyhow::Error::new(err)
+                    .context(format!("Failed to write to {}", self.path.display()));
+                return Box::pin(future::ready(Err(err)));
+            }
+        };
+        line.push(b'\n');
+        let writer = self.writer.get_or_insert_with(|| {
+            Writer::spawn(OutputFile::new(
+                self.path.clone(),
+                self.rotation,
+                Vec::new(),
+            ))
+        });
+        write_item(writer, line, item)
+    }
+
+    fn close(&mut self) -> CloseFuture {
+        close_file(self.writer.as_ref())
+    }
+}
+
+/// Writes the items as rows of a CSV file.
+///
+/// The header is inferred from the fields of the first item in the order
+/// they are serialized, unless the `columns` are set. Nested fields are
+/// joined with a `.` like `author.name` and arrays are written as JSON.
+/// Fields that aren't in the header are not written.
+#[derive(Debug)]
+pub struct CsvSink {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    delimiter: u8,
+    header: Option<Vec<String>>,
+    writer: Option<Writer<OutputFile>>,
+}
+
+impl CsvSink {
+    /// Write to the file at `path`, which is truncated when the first item is
+    /// written
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            rotation: None,
+            delimiter: b',',
+            header: None,
+            writer: None,
+        }
+    }
+
+    /// Continue in a new file according to the `rotation`, each file starts
+    /// with the header
+    pub fn rotate(mut self, rotation: Rotation) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Separate the fields with `delimiter` instead of `,`
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Write the `columns` in this order, instead of the fields of the first
+    /// item
+    pub fn columns<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.header = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The record of the `item`, starts the writer with the header on the
+    /// first item
+    fn row<O: Serialize>(&mut self, item: &O) -> Result<(&Writer<OutputFile>, Vec<u8>)> {
+        let fields = flatten(item)?;
+        let header = self
+            .header
+            .get_or_insert_with(|| fields.iter().map(|(name, _)| name.clone()).collect());
+        let row: Vec<String> = header
+            .iter()
+            .map(|name| {
+                fields
+                    .iter()
+                    .find(|(field, _)| field == name)
+                    .map(|(_, value)| to_field(value))
+                    .unwrap_or_default()
+            })
+            .collect();
+        let row = record(self.delimiter, &row)?;
+
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => {
+                let header = record(self.delimiter, header.iter())?;
+                Writer::spawn(OutputFile::new(self.path.clone(), self.rotation, header))
+            }
+        };
+        Ok((self.writer.insert(writer), row))
+    }
+}
+
+impl<O: Serialize + 'static> ItemPipeline<O> for CsvSink {
+    fn process_item(&mut self, item: O) -> ItemFuture<O> {
+        let path = self.path.clone();
+        match self.row(&item) {
+            Ok((writer, row)) => write_item(writer, row, item),
+            Err(err) => {
+                let err = err.context(format!("Failed to write to {}", path.display()));
+                Box::pin(future::ready(Err(err)))
+            }
+        }
+    }
+
+    fn close(&mut self) -> CloseFuture {
+        close_file(self.writer.as_ref())
+    }
+}
+
+/// The `fields` as a CSV record
+fn record<I, F>(delimiter: u8, fields: I) -> Result<Vec<u8>>
+where
+    I: IntoIterator<Item = F>,
+    F: AsRef<[u8]>,
+{
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner().map_err(|err| err.into_error())?)
+}
+
+/// The leaf values of an item keyed by their path, like `author.name`
+type Fields = Vec<(String, Value)>;
+
+/// The leaf values of the `item` in the order they are serialized.
+///
+/// A value that isn't an object is keyed by `value`.
+fn flatten<O: Serialize>(item: &O) -> Result<Fields> {
+    let json = serde_json::to_vec(item)?;
+    let mut fields = Vec::new();
+    Leaves {
+        prefix: None,
+        fields: &mut fields,
+    }
+    .deserialize(&mut serde_json::Deserializer::from_slice(&json))?;
+    Ok(fields)
+}
+
+/// Collects the leaves of a JSON value, unlike `serde_json::Map` it keeps the
+/// order of the keys
+struct Leaves<'a> {
+    prefix: Option<String>,
+    fields: &'a mut Fields,
+}
+
+impl Leaves<'_> {
+    fn push<E>(self, value: Value) -> Result<(), E> {
+        let name = self.prefix.unwrap_or_else(|| "value".to_string());
+        self.fields.push((name, value));
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Leaves<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Leaves<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<(), E> {
+        self.push(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<(), E> {
+        self.push(Value::from(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<(), E> {
+        self.push(Value::from(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<(), E> {
+        self.push(Value::from(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<(), E> {
+        self.push(Value::from(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<(), E> {
+        self.push(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        self.push(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+        let value = Value::deserialize(SeqAccessDeserializer::new(seq))?;
+        self.push(value)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            let prefix = match &self.prefix {
+                Some(prefix) => format!("{}.{}", prefix, key),
+                None => key,
+            };
+            map.next_value_seed(Leaves {
+                prefix: Some(prefix),
+                fields: &mut *self.fields,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The text of a CSV field
+fn to_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteSink;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+    use rusqlite::types::Value as SqlValue;
+    use rusqlite::{params_from_iter, Connection};
+
+    /// Inserts the items as rows of a SQLite table, requires the `sqlite`
+    /// feature.
+    ///
+    /// The table and its columns are created from the fields of the items,
+    /// nested fields are joined with a `.` and arrays are stored as JSON.
+    /// With a `key`, an item replaces the row with the same key. The rows are
+    /// inserted in batches, each in one transaction.
+    #[derive(Debug)]
+    pub struct SqliteSink {
+        path: PathBuf,
+        table: String,
+        key: Option<String>,
+        batch_size: usize,
+        /// The rows of the next batch
+        rows: Vec<Fields>,
+        writer: Option<Writer<Table>>,
+    }
+
+    impl SqliteSink {
+        /// Insert into the `table` of the database at `path`
+        pub fn new(path: impl AsRef<Path>, table: impl Into<String>) -> Self {
+            Self {
+                path: path.as_ref().to_path_buf(),
+                table: table.into(),
+                key: None,
+                batch_size: 100,
+                rows: Vec::new(),
+                writer: None,
+            }
+        }
+
+        /// Replace the row whose `column` has the same value as the item
+        pub fn key(mut self, column: impl Into<String>) -> Self {
+            self.key = Some(column.into());
+            self
+        }
+
+        /// Insert the rows in batches of `size` instead of 100.
+        ///
+        /// The last, smaller batch is inserted when the collector is done or
+        /// the sink is dropped.
+        pub fn batch_size(mut self, size: usize) -> Self {
+            self.batch_size = size.max(1);
+            self
+        }
+
+        /// Insert the rows of the batch on the thread of the writer
+        fn insert(&mut self) -> impl Future<Output = Result<()>> {
+            let rows = std::mem::take(&mut self.rows);
+            let writer = self.writer.get_or_insert_with(|| {
+                Writer::spawn(Table::new(
+                    self.path.clone(),
+                    self.table.clone(),
+                    self.key.clone(),
+                ))
+            });
+            writer.run(move |table| table.insert(rows))
+        }
+    }
+
+    impl<O: Serialize + 'static> ItemPipeline<O> for SqliteSink {
+        fn process_item(&mut self, item: O) -> ItemFuture<O> {
+            match flatten(&item) {
+                Ok(fields) => self.rows.push(fields),
+                Err(err) => {
+                    let err = err.context(format!("Failed to insert into {}", self.path.display()));
+                    return Box::pin(future::ready(Err(err)));
+                }
+            }
+            if self.rows.len() < self.batch_size {
+                return Box::pin(future::ready(Ok(Some(item))));
+            }
+            let inserted = self.insert();
+            Box::pin(async move {
+                inserted.await?;
+                Ok(Some(item))
+            })
+        }
+
+        fn close(&mut self) -> CloseFuture {
+            if self.rows.is_empty() {
+                return Box::pin(future::ready(Ok(())));
+            }
+            Box::pin(self.insert())
+        }
+    }
+
+    impl Drop for SqliteSink {
+        fn drop(&mut self) {
+            // the writer waits for the batch when it is dropped
+            if !self.rows.is_empty() {
+                drop(self.insert());
+            }
+        }
+    }
+
+    /// The table of a `SqliteSink`, owned by its writer thread
+    #[derive(Debug)]
+    struct Table {
+        path: PathBuf,
+        name: String,
+        key: Option<String>,
+        conn: Option<Connection>,
+        /// The columns of the table
+        columns: Vec<String>,
+    }
+
+    impl Table {
+        fn new(path: PathBuf, name: String, key: Option<String>) -> Self {
+            Self {
+                path,
+                name,
+                key,
+                conn: None,
+                columns: Vec::new(),
+            }
+        }
+
+        /// Insert the `rows` in one transaction
+        fn insert(&mut self, rows: Vec<Fields>) -> Result<()> {
+            self.try_insert(rows)
+                .with_context(|| format!("Failed to insert into {}", self.path.display()))
+        }
+
+        fn try_insert(&mut self, rows: Vec<Fields>) -> Result<()> {
+            for fields in &rows {
+                self.prepare(fields)?;
+            }
+            let table = quote(&self.name);
+            let Some(conn) = self.conn.as_mut() else {
+                return Ok(());
+            };
+            let tx = conn.transaction()?;
+            for fields in rows {
+                let names: Vec<String> = fields.iter().map(|(name, _)| quote(name)).collect();
+                let mut sql = format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    table,
+                    names.join(", "),
+                    vec!["?"; names.len()].join(", ")
+                );
+                if let Some(key) = self.key.as_ref().filter(|key| has(&fields, key)) {
+                    let updates: Vec<String> = names
+                        .iter()
+                        .map(|name| format!("{} = excluded.{}", name, name))
+                        .collect();
+                    sql.push_str(&format!(
+                        " ON CONFLICT({}) DO UPDATE SET {}",
+                        quote(key),
+                        updates.join(", ")
+                    ));
+                }
+                tx.execute(
+                    &sql,
+                    params_from_iter(fields.into_iter().map(|(_, value)| to_sql(value))),
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        }
+
+        /// Open the database and create the table, the unique index of the
+        /// key and the columns of the `fields`
+        fn prepare(&mut self, fields: &Fields) -> Result<()> {
+            if self.conn.is_none() {
+                let conn = Connection::open(&self.path)?;
+                let mut columns: Vec<String> = fields.iter().map(|(name, _)| quote(name)).collect();
+                if let Some(key) = self.key.as_ref().filter(|key| !has(fields, key)) {
+                    columns.push(quote(key));
+                }
+                conn.execute(
+                    &format!(
+                        "CREATE TABLE IF NOT EXISTS {} ({})",
+                        quote(&self.name),
+                        columns.join(", ")
+                    ),
+                    [],
+                )?;
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT name FROM pragma_table_info({})",
+                    literal(&self.name)
+                ))?;
+                self.columns = stmt
+                    .query_map([], |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()?;
+                drop(stmt);
+                // the table may exist from before without the key or its index
+                if let Some(key) = &self.key {
+                    if !self.columns.contains(key) {
+                        conn.execute(
+                            &format!(
+                                "ALTER TABLE {} ADD COLUMN {}",
+                                quote(&self.name),
+                                quote(key)
+                            ),
+                            [],
+                        )?;
+                        self.columns.push(key.clone());
+                    }
+                    conn.execute(
+                        &format!(
+                            "CREATE UNIQUE INDEX IF NOT EXISTS {} ON {} ({})",
+                            quote(&format!("{}_{}_key", self.name, key)),
+                            quote(&self.name),
+                            quote(key)
+                        ),
+                        [],
+                    )?;
+                }
+                self.conn = Some(conn);
+            }
+            let conn = self.conn.as_ref().unwrap();
+            // add the columns of fields that earlier items didn't have
+            for (name, _) in fields {
+                if !self.columns.contains(name) {
+                    conn.execute(
+                        &format!(
+                            "ALTER TABLE {} ADD COLUMN {}",
+                            quote(&self.name),
+                            quote(name)
+                        ),
+                        [],
+                    )?;
+                    self.columns.push(name.clone());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn has(fields: &Fields, name: &str) -> bool {
+        fields.iter().any(|(field, _)| field == name)
+    }
+
+    fn quote(name: &str) -> String {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+
+    fn literal(text: &str) -> String {
+        format!("'{}'", text.replace('\'', "''"))
+    }
+
+    fn to_sql(value: Value) -> SqlValue {
+        match value {
+            Value::Null => SqlValue::Null,
+            Value::Bool(b) => SqlValue::Integer(b as i64),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => SqlValue::Integer(i),
+                None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => SqlValue::Text(s),
+            value => SqlValue::Text(value.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Story {
+        id: u32,
+        title: String,
+        author: Author,
+        tags: Vec<&'static str>,
+    }
+
+    #[derive(Serialize)]
+    struct Author {
+        name: String,
+    }
+
+    fn story(id: u32, title: &str) -> Story {
+        Story {
+            id,
+            title: title.to_string(),
+            author: Author {
+                name: "pg".to_string(),
+            },
+            tags: vec!["a", "b"],
+        }
+    }
+
+    fn process<P: ItemPipeline<Story>>(sink: &mut P, item: Story) {
+        futures::executor::block_on(sink.process_item(item)).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust-crawler-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_json_lines_with_rotation() {
+        let dir = temp_dir("jsonl");
+        let mut sink = JsonLinesSink::new(dir.join("stories.jsonl")).rotate(Rotation::size(1));
+        process(&mut sink, story(1, "first"));
+        process(&mut sink, story(2, "second"));
+        drop(sink);
+
+        let first = std::fs::read_to_string(dir.join("stories-1.jsonl")).unwrap();
+        assert_eq!(
+            first,
+            "{\"id\":1,\"title\":\"first\",\"author\":{\"name\":\"pg\"},\"tags\":[\"a\",\"b\"]}\n"
+        );
+        let second = std::fs::read_to_string(dir.join("stories-2.jsonl")).unwrap();
+        assert!(second.starts_with("{\"id\":2"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_csv_with_inferred_header() {
+        let dir = temp_dir("csv");
+        let path = dir.join("stories.csv");
+        let mut sink = CsvSink::new(&path);
+        process(&mut sink, story(1, "a, b"));
+        process(&mut sink, story(2, "c"));
+        drop(sink);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "id,title,author.name,tags\n1,\"a, b\",pg,\"[\"\"a\"\",\"\"b\"\"]\"\n2,c,pg,\"[\"\"a\"\",\"\"b\"\"]\"\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_csv_with_configured_columns() {
+        let dir = temp_dir("csv-columns");
+        let path = dir.join("stories.csv");
+        let mut sink = CsvSink::new(&path)
+            .delimiter(b';')
+            .columns(["title", "id", "score"]);
+        process(&mut sink, story(1, "first"));
+        drop(sink);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "title;id;score\nfirst;1;\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn upsert_into_sqlite() {
+        let dir = temp_dir("sqlite");
+        let path = dir.join("stories.db");
+        let mut sink = SqliteSink::new(&path, "stories").key("id");
+        process(&mut sink, story(1, "first"));
+        process(&mut sink, story(2, "second"));
+        process(&mut sink, story(1, "updated"));
+        drop(sink);
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, title, \"author.name\" FROM stories ORDER BY id")
+            .unwrap();
+        let rows: Vec<(i64, String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (1, "updated".to_string(), "pg".to_string()),
+                (2, "second".to_string(), "pg".to_string())
+            ]
+        );
+        drop(stmt);
+        drop(conn);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn upsert_into_existing_sqlite_table() {
+        let dir = temp_dir("sqlite-existing");
+        let path = dir.join("stories.db");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute("CREATE TABLE stories (title)", []).unwrap();
+        drop(conn);
+
+        let mut sink = SqliteSink::new(&path, "stories").key("id");
+        process(&mut sink, story(1, "first"));
+        process(&mut sink, story(1, "updated"));
+        drop(sink);
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let rows: Vec<(i64, String)> = conn
+            .prepare("SELECT id, title FROM stories")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows, [(1, "updated".to_string())]);
+        drop(conn);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn insert_sqlite_batches() {
+        let dir = temp_dir("sqlite-batches");
+        let path = dir.join("stories.db");
+        let count = || -> i64 {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.query_row("SELECT COUNT(*) FROM stories", [], |row| row.get(0))
+                .unwrap()
+        };
+        let mut sink = SqliteSink::new(&path, "stories").batch_size(2);
+        process(&mut sink, story(1, "first"));
+        process(&mut sink, story(2, "second"));
+        process(&mut sink, story(3, "third"));
+        assert_eq!(count(), 2);
+        drop(sink);
+
+        assert_eq!(count(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
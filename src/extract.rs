@@ -0,0 +1,439 @@
+//! Declarative extraction of structs from html pages.
+//!
+//! A `Schema` maps field names to `Field`s, which select an element with a
+//! css selector, take its text, html or an attribute and optionally post
+//! process it with a regex. The extracted values are deserialized into any
+//! type that implements `serde::Deserialize`.
+//!
+//! ```
+//! use std::sync::OnceLock;
+//!
+//! use rust_crawler::extract::{Extract, Field, Schema};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Book {
+//!     title: String,
+//!     score: Option<f32>,
+//!     tags: Vec<String>,
+//! }
+//!
+//! impl Extract for Book {
+//!     fn schema() -> &'static Schema {
+//!         static SCHEMA: OnceLock<Schema> = OnceLock::new();
+//!         SCHEMA.get_or_init(|| {
+//!             Schema::new()
+//!                 .field("title", Field::text("div#wrapper h1 span"))
+//!                 .field("score", Field::text("strong.rating_num").number().optional())
+//!                 .field("tags", Field::text("div#db-tags-section a").all())
+//!         })
+//!     }
+//! }
+//! ```
+//!
+//! `Scraper::scrape` can then return `Ok(Some(Book::extract(&response)?))`.
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Selector};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+
+use crate::error::ExtractError;
+use crate::Response;
+
+/// A type that is extracted from a page with its `Schema`
+pub trait Extract: DeserializeOwned {
+    /// The fields of the type, built once and kept in a `static` so it isn't
+    /// rebuilt for every page
+    fn schema() -> &'static Schema;
+
+    /// Extract the type from the html of the `response`
+    fn extract<T>(response: &Response<T>) -> Result<Self, ExtractError> {
+        Self::schema().deserialize(response)
+    }
+}
+
+/// Maps field names to the `Field`s they are extracted with
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    fields: Vec<(String, Field)>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extract the field `name` as described by `field`
+    pub fn field(mut self, name: impl Into<String>, field: Field) -> Self {
+        self.fields.push((name.into(), field));
+        self
+    }
+
+    /// Extract all fields from the html of the `response`
+    pub fn extract<T>(&self, response: &Response<T>) -> Result<Map<String, Value>, ExtractError> {
+        let page = response.parse();
+        self.extract_element(page.html().root_element(), &page.base_url())
+    }
+
+    /// Extract all fields from the elements below `root`, urls are resolved
+    /// against `base`
+    pub fn extract_element(
+        &self,
+        root: ElementRef<'_>,
+        base: &Url,
+    ) -> Result<Map<String, Value>, ExtractError> {
+        let mut values = Map::with_capacity(self.fields.len());
+        for (name, field) in &self.fields {
+            values.insert(name.clone(), field.extract(name, root, base)?);
+        }
+        Ok(values)
+    }
+
+    /// Extract all fields from the `response` and deserialize them into `D`
+    pub fn deserialize<D, T>(&self, response: &Response<T>) -> Result<D, ExtractError>
+    where
+        D: DeserializeOwned,
+    {
+        Ok(serde_json::from_value(Value::Object(
+            self.extract(response)?,
+        ))?)
+    }
+}
+
+/// What is taken from a selected element
+#[derive(Debug, Clone)]
+enum Source {
+    /// The trimmed text content
+    Text,
+    /// The inner html
+    Html,
+    /// The value of an attribute
+    Attr(String),
+    /// An attribute resolved to an absolute url
+    Url(String),
+    /// An object with the fields of the schema
+    Nested(Schema),
+}
+
+/// How a single field is extracted.
+///
+/// A field is required by default, if its selector matches nothing the
+/// extraction fails with `ExtractError::MissingField`. The selector and regex
+/// are compiled once when the field is built, an invalid one fails the
+/// extraction.
+#[derive(Debug, Clone)]
+pub struct Field {
+    selector: String,
+    /// The compiled `selector`, or why it is invalid
+    parsed: Result<Selector, String>,
+    source: Source,
+    /// Applied to the extracted string, the first capture group or the whole
+    /// match is kept
+    regex: Option<Result<Regex, regex::Error>>,
+    /// Extract all matching elements as array
+    all: bool,
+    /// `null` instead of an error if nothing matches
+    optional: bool,
+    /// Parse the string as number
+    number: bool,
+}
+
+impl Field {
+    fn new(selector: impl Into<String>, source: Source) -> Self {
+        let selector = selector.into();
+        Self {
+            parsed: Selector::parse(&selector).map_err(|err| format!("{:?}", err)),
+            selector,
+            source,
+            regex: None,
+            all: false,
+            optional: false,
+            number: false,
+        }
+    }
+
+    /// The trimmed text of the element that matches the css `selector`
+    pub fn text(selector: impl Into<String>) -> Self {
+        Self::new(selector, Source::Text)
+    }
+
+    /// The inner html of the element that matches the css `selector`
+    pub fn html(selector: impl Into<String>) -> Self {
+        Self::new(selector, Source::Html)
+    }
+
+    /// The attribute `attr` of the element that matches the css `selector`
+    pub fn attr(selector: impl Into<String>, attr: impl Into<String>) -> Self {
+        Self::new(selector, Source::Attr(attr.into()))
+    }
+
+    /// The attribute `attr` of the element that matches the css `selector`,
+    /// resolved to an absolute url
+    pub fn url(selector: impl Into<String>, attr: impl Into<String>) -> Self {
+        Self::new(selector, Source::Url(attr.into()))
+    }
+
+    /// An object extracted with the `schema` from the element that matches
+    /// the css `selector`
+    pub fn nested(selector: impl Into<String>, schema: Schema) -> Self {
+        Self::new(selector, Source::Nested(schema))
+    }
+
+    /// Only keep the first capture group of the `regex`, or the whole match if
+    /// it has no groups. A value that doesn't match is treated as missing.
+    pub fn regex(mut self, regex: impl Into<String>) -> Self {
+        self.regex = Some(Regex::new(&regex.into()));
+        self
+    }
+
+    /// Extract the values of all matching elements as array
+    pub fn all(mut self) -> Self {
+        self.all = true;
+        self
+    }
+
+    /// Extract `null`, or an empty array, if nothing matches
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Parse the value as number
+    pub fn number(mut self) -> Self {
+        self.number = true;
+        self
+    }
+
+    fn extract(&self, name: &str, root: ElementRef<'_>, base: &Url) -> Result<Value, ExtractError> {
+        let selector = self
+            .parsed
+            .as_ref()
+            .map_err(|err| ExtractError::InvalidSelector(self.selector.clone(), err.clone()))?;
+        let regex = match &self.regex {
+            Some(regex) => Some(regex.as_ref().map_err(Clone::clone)?),
+            None => None,
+        };
+
+        let mut values = Vec::new();
+        for el in root.select(selector) {
+            if let Some(value) = self.value(name, el, base, regex)? {
+                values.push(value);
+                if !self.all {
+                    break;
+                }
+            }
+        }
+
+        match (self.all, values.pop()) {
+            (true, last) => {
+                values.extend(last);
+                if values.is_empty() && !self.optional {
+                    return Err(ExtractError::MissingField(name.to_string()));
+                }
+                Ok(Value::Array(values))
+            }
+            (false, Some(value)) => Ok(value),
+            (false, None) if self.optional => Ok(Value::Null),
+            (false, None) => Err(ExtractError::MissingField(name.to_string())),
+        }
+    }
+
+    /// The value of a single element, `None` if it lacks the attribute or
+    /// doesn't match the regex
+    fn value(
+        &self,
+        name: &str,
+        el: ElementRef<'_>,
+        base: &Url,
+        regex: Option<&Regex>,
+    ) -> Result<Option<Value>, ExtractError> {
+        let raw = match &self.source {
+            Source::Nested(schema) => {
+                return schema
+                    .extract_element(el, base)
+                    .map(|map| Some(Value::Object(map)))
+            }
+            Source::Text => el.text().collect::<String>().trim().to_string(),
+            Source::Html => el.inner_html(),
+            Source::Attr(attr) | Source::Url(attr) => match el.value().attr(attr) {
+                Some(value) => value.trim().to_string(),
+                None => return Ok(None),
+            },
+        };
+
+        let raw = match regex {
+            Some(regex) => match regex.captures(&raw) {
+                Some(caps) => caps
+                    .get(1)
+                    .or_else(|| caps.get(0))
+                    .unwrap()
+                    .as_str()
+                    .to_string(),
+                None => return Ok(None),
+            },
+            None => raw,
+        };
+
+        let raw = match &self.source {
+            Source::Url(_) => base
+                .join(&raw)
+                .map_err(|error| ExtractError::InvalidUrl {
+                    href: raw,
+                    base: base.clone(),
+                    error,
+                })?
+                .to_string(),
+            _ => raw,
+        };
+
+        if self.number {
+            let number = parse_number(&raw).ok_or_else(|| ExtractError::InvalidNumber {
+                field: name.to_string(),
+                value: raw.clone(),
+            })?;
+            Ok(Some(Value::Number(number)))
+        } else {
+            Ok(Some(Value::String(raw)))
+        }
+    }
+}
+
+/// Parse an integer or float, ignoring commas that group the digits in
+/// thousands like `1,234,567.8`
+fn parse_number(value: &str) -> Option<Number> {
+    static GROUPED: OnceLock<Regex> = OnceLock::new();
+    let grouped = GROUPED.get_or_init(|| Regex::new(r"^[+-]?\d{1,3}(,\d{3})+(\.\d+)?$").unwrap());
+    let value = value.trim();
+    let value = if grouped.is_match(value) {
+        Cow::Owned(value.replace(',', ""))
+    } else {
+        Cow::Borrowed(value)
+    };
+    if let Ok(int) = value.parse::<i64>() {
+        return Some(int.into());
+    }
+    value.parse::<f64>().ok().and_then(Number::from_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    const PAGE: &str = r#"<div id="wrapper"><h1><span> 三體 </span></h1>
+        <strong class="rating_num">8.8</strong>
+        <a class="rating_people" href="/subject/2567698/collections"><span>1,234,567</span>人評價</a>
+        <div id="db-tags-section"><a>科幻</a><a>劉慈欣</a></div>
+        <ul><li class="comment"><b>alice</b><p>好看</p></li><li class="comment"><b>bob</b><p>很好</p></li></ul>
+        </div>"#;
+
+    #[derive(Debug, Deserialize)]
+    struct Comment {
+        author: String,
+        text: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Book {
+        title: String,
+        score: f32,
+        people: u64,
+        subject: String,
+        link: String,
+        tags: Vec<String>,
+        isbn: Option<String>,
+        comments: Vec<Comment>,
+    }
+
+    impl Extract for Book {
+        fn schema() -> &'static Schema {
+            static SCHEMA: OnceLock<Schema> = OnceLock::new();
+            SCHEMA.get_or_init(|| {
+                Schema::new()
+                    .field("title", Field::text("h1 span"))
+                    .field("score", Field::text("strong.rating_num").number())
+                    .field("people", Field::text("a.rating_people span").number())
+                    .field(
+                        "subject",
+                        Field::attr("a.rating_people", "href").regex(r"/subject/(\d+)"),
+                    )
+                    .field("link", Field::url("a.rating_people", "href"))
+                    .field("tags", Field::text("#db-tags-section a").all())
+                    .field("isbn", Field::text("span.isbn").optional())
+                    .field(
+                        "comments",
+                        Field::nested(
+                            "li.comment",
+                            Schema::new()
+                                .field("author", Field::text("b"))
+                                .field("text", Field::text("p")),
+                        )
+                        .all(),
+                    )
+            })
+        }
+    }
+
+    #[test]
+    fn extract_struct() {
+        let resp = Response::<()>::from_html("https://book.douban.com/subject/2567698/", PAGE);
+        let book = Book::extract(&resp).unwrap();
+        assert_eq!(book.title, "三體");
+        assert_eq!(book.score, 8.8);
+        assert_eq!(book.people, 1_234_567);
+        assert_eq!(book.subject, "2567698");
+        assert_eq!(
+            book.link,
+            "https://book.douban.com/subject/2567698/collections"
+        );
+        assert_eq!(book.tags, ["科幻", "劉慈欣"]);
+        assert_eq!(book.isbn, None);
+        assert_eq!(book.comments[1].author, "bob");
+        assert_eq!(book.comments[1].text, "很好");
+    }
+
+    #[test]
+    fn missing_required_fields() {
+        let resp = Response::<()>::from_html("https://book.douban.com/", "<p>gone</p>");
+        assert!(matches!(
+            Book::extract(&resp),
+            Err(ExtractError::MissingField(field)) if field == "title"
+        ));
+
+        let schema = Schema::new().field("score", Field::text("p").number());
+        assert!(matches!(
+            schema.extract(&resp),
+            Err(ExtractError::InvalidNumber { .. })
+        ));
+        let schema = Schema::new().field("p", Field::text("p").regex(r"\d+"));
+        assert!(matches!(
+            schema.extract(&resp),
+            Err(ExtractError::MissingField(_))
+        ));
+        let schema = Schema::new().field("p", Field::text("p["));
+        assert!(matches!(
+            schema.extract(&resp),
+            Err(ExtractError::InvalidSelector(..))
+        ));
+        let schema = Schema::new().field("p", Field::text("p").regex("("));
+        assert!(matches!(
+            schema.extract(&resp),
+            Err(ExtractError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse_number("1,234,567"), Some(1_234_567.into()));
+        assert_eq!(parse_number(" -1,234.5 "), Number::from_f64(-1234.5));
+        assert_eq!(parse_number("8.8"), Number::from_f64(8.8));
+        // a decimal comma is not a thousands separator
+        assert_eq!(parse_number("8,8"), None);
+        assert_eq!(parse_number("12,34,567"), None);
+    }
+}
@@ -0,0 +1,169 @@
+//! RSS and Atom feeds.
+
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::error::ExtractError;
+
+/// The format of a feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    /// RSS 0.9x, 1.0 (RDF) and 2.0
+    Rss,
+    Atom,
+}
+
+/// An RSS or Atom feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feed {
+    pub kind: FeedKind,
+    pub title: Option<String>,
+    /// The website of the feed
+    pub link: Option<String>,
+    pub items: Vec<FeedItem>,
+}
+
+/// An `<item>` of an RSS or an `<entry>` of an Atom feed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedItem {
+    pub title: Option<String>,
+    pub link: Option<String>,
+    /// The `<guid>` or `<id>`
+    pub id: Option<String>,
+    /// The publication date as written
+    pub published: Option<String>,
+    /// The `<description>` or `<summary>`, falling back to the `<content>`
+    pub summary: Option<String>,
+}
+
+impl Feed {
+    /// Parse an RSS or Atom feed
+    pub fn parse(xml: &str) -> Result<Self, ExtractError> {
+        let options = ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let doc = Document::parse_with_options(xml, options)?;
+        let root = doc.root_element();
+        match root.tag_name().name() {
+            "rss" => {
+                let channel = child(root, "channel");
+                let items = channel
+                    .map(|channel| children(channel, "item").map(rss_item).collect())
+                    .unwrap_or_default();
+                Ok(Self {
+                    kind: FeedKind::Rss,
+                    title: channel.and_then(|channel| child_text(channel, "title")),
+                    link: channel.and_then(|channel| child_text(channel, "link")),
+                    items,
+                })
+            }
+            // RSS 1.0 lists the items next to the channel
+            "RDF" => {
+                let channel = child(root, "channel");
+                Ok(Self {
+                    kind: FeedKind::Rss,
+                    title: channel.and_then(|channel| child_text(channel, "title")),
+                    link: channel.and_then(|channel| child_text(channel, "link")),
+                    items: children(root, "item").map(rss_item).collect(),
+                })
+            }
+            "feed" => Ok(Self {
+                kind: FeedKind::Atom,
+                title: child_text(root, "title"),
+                link: atom_link(root),
+                items: children(root, "entry").map(atom_entry).collect(),
+            }),
+            other => Err(ExtractError::NotAFeed(other.to_string())),
+        }
+    }
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// The trimmed text of the first child `name`, including CDATA
+fn child_text(node: Node, name: &str) -> Option<String> {
+    let text: String = child(node, name)?
+        .descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn rss_item(item: Node) -> FeedItem {
+    FeedItem {
+        title: child_text(item, "title"),
+        link: child_text(item, "link"),
+        id: child_text(item, "guid"),
+        published: child_text(item, "pubDate").or_else(|| child_text(item, "date")),
+        summary: child_text(item, "description").or_else(|| child_text(item, "encoded")),
+    }
+}
+
+/// The `href` of the `alternate` link, or the first link without `rel`
+fn atom_link(node: Node) -> Option<String> {
+    children(node, "link")
+        .find(|link| link.attribute("rel").unwrap_or("alternate") == "alternate")
+        .and_then(|link| link.attribute("href"))
+        .map(str::to_string)
+}
+
+fn atom_entry(entry: Node) -> FeedItem {
+    FeedItem {
+        title: child_text(entry, "title"),
+        link: atom_link(entry),
+        id: child_text(entry, "id"),
+        published: child_text(entry, "published").or_else(|| child_text(entry, "updated")),
+        summary: child_text(entry, "summary").or_else(|| child_text(entry, "content")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rss_and_atom() {
+        let rss = r#"<?xml version="1.0"?>
+<!DOCTYPE rss PUBLIC "-//Netscape Communications//DTD RSS 0.91//EN" "http://my.netscape.com/publish/formats/rss-0.91.dtd">
+<rss version="2.0"><channel><title>Hacker News</title><link>https://news.ycombinator.com/</link>
+<item><title>Show HN</title><link>https://a.example/</link><pubDate>Mon, 01 Aug 2022 10:00:00 +0000</pubDate>
+<description><![CDATA[<p>Comments</p>]]></description></item>
+</channel></rss>"#;
+        let feed = Feed::parse(rss).unwrap();
+        assert_eq!(feed.kind, FeedKind::Rss);
+        assert_eq!(feed.title.as_deref(), Some("Hacker News"));
+        assert_eq!(feed.items.len(), 1);
+        assert_eq!(feed.items[0].summary.as_deref(), Some("<p>Comments</p>"));
+
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>r/rust</title>
+<link rel="self" href="https://www.reddit.com/r/rust/.rss"/><link href="https://www.reddit.com/r/rust/"/>
+<entry><title>Rust 1.63</title><id>t3_abc</id><link href="https://www.reddit.com/r/rust/comments/abc/"/>
+<updated>2022-08-11T14:00:00+00:00</updated></entry></feed>"#;
+        let feed = Feed::parse(atom).unwrap();
+        assert_eq!(feed.kind, FeedKind::Atom);
+        assert_eq!(feed.link.as_deref(), Some("https://www.reddit.com/r/rust/"));
+        assert_eq!(feed.items[0].id.as_deref(), Some("t3_abc"));
+        assert_eq!(
+            feed.items[0].published.as_deref(),
+            Some("2022-08-11T14:00:00+00:00")
+        );
+
+        assert!(matches!(
+            Feed::parse("<html></html>"),
+            Err(ExtractError::NotAFeed(_))
+        ));
+    }
+}
@@ -0,0 +1,757 @@
+//! How the requests of the crawler are turned into responses.
+//!
+//! By default all requests are sent with the `reqwest::Client` of the
+//! crawler. Pages that are rendered with javascript can instead be loaded in
+//! a headless browser with the [`WebDriverFetcher`], selected per domain with
+//! `CrawlerConfig::fetch_with`. Tests can serve canned responses without
+//! network with a [`MockFetcher`], see `CrawlerConfig::set_fetcher`.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use futures_timer::Delay;
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{Method, ResponseBuilderExt, StatusCode, Url};
+use thirtyfour::extensions::query::ElementQueryable;
+use thirtyfour::{By, Capabilities, WebDriver};
+use tokio::sync::Semaphore;
+
+use crate::pattern::{default_suffixes, DomainPattern, PublicSuffixList};
+use crate::retry::is_connection_reset;
+
+pub type FetchFuture = Pin<Box<dyn Future<Output = Result<reqwest::Response>>>>;
+pub type ResolveFuture = Pin<Box<dyn Future<Output = Option<IpAddr>>>>;
+
+/// Sends a request and resolves to its response.
+///
+/// The crawler reads the body of the response and applies its retry policy,
+/// so a fetcher only has to produce a `reqwest::Response`.
+pub trait Fetcher: Send + Sync {
+    fn fetch(&self, request: reqwest::Request) -> FetchFuture;
+
+    /// The ip address the host of the `url` resolves to, for the limits of
+    /// `ConcurrencyLimits::per_ip`, `None` if it can't be resolved.
+    ///
+    /// Looks up the host with the resolver of the system by default.
+    fn resolve(&self, url: &Url) -> ResolveFuture {
+        let host = url.host_str().unwrap_or_default().to_string();
+        let port = url.port_or_known_default().unwrap_or(80);
+        Box::pin(async move {
+            tokio::net::lookup_host((host.as_str(), port))
+                .await
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .map(|addr| addr.ip())
+        })
+    }
+}
+
+impl<F: Fetcher + ?Sized> Fetcher for Arc<F> {
+    fn fetch(&self, request: reqwest::Request) -> FetchFuture {
+        (**self).fetch(request)
+    }
+
+    fn resolve(&self, url: &Url) -> ResolveFuture {
+        (**self).resolve(url)
+    }
+}
+
+impl Fetcher for reqwest::Client {
+    fn fetch(&self, request: reqwest::Request) -> FetchFuture {
+        let resp = self.execute(request);
+        Box::pin(async move { Ok(resp.await?) })
+    }
+}
+
+/// Why a request failed without a response, as far as retries and robots.txt
+/// requests are concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchErrorKind {
+    /// The connection could not be established
+    Connect,
+    /// The request timed out
+    Timeout,
+    /// The connection was reset or closed by the server
+    Reset,
+    /// Any other error
+    Other,
+}
+
+impl FetchErrorKind {
+    /// The kind of an `error` returned by a `Fetcher`, a `reqwest::Error` or
+    /// the `MockError` of a `MockResponse`
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<MockError>() {
+            return error.kind;
+        }
+        match error.downcast_ref::<reqwest::Error>() {
+            Some(error) => Self::of_reqwest(error),
+            None => FetchErrorKind::Other,
+        }
+    }
+
+    /// The kind of a `reqwest::Error`
+    pub fn of_reqwest(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            FetchErrorKind::Timeout
+        } else if error.is_connect() {
+            FetchErrorKind::Connect
+        } else if is_connection_reset(error) {
+            FetchErrorKind::Reset
+        } else {
+            FetchErrorKind::Other
+        }
+    }
+}
+
+/// Selects the fetcher of a request by the host of its url
+pub struct Fetchers {
+    /// Used for all requests that match none of the `routes`
+    default: Arc<dyn Fetcher>,
+    /// The fetchers of the domains that match the patterns, the first match wins
+    routes: Vec<(DomainPattern, Arc<dyn Fetcher>)>,
+    /// The public suffix list to match registrable domains
+    suffixes: Arc<PublicSuffixList>,
+}
+
+impl Fetchers {
+    /// Fetch all requests with `default`
+    pub fn new(default: impl Fetcher + 'static) -> Self {
+        Self {
+            default: Arc::new(default),
+            routes: Vec::new(),
+            suffixes: default_suffixes(),
+        }
+    }
+
+    /// Fetch the requests for domains that match the `pattern` with `fetcher`
+    pub fn route(mut self, pattern: impl Into<DomainPattern>, fetcher: Arc<dyn Fetcher>) -> Self {
+        self.routes.push((pattern.into(), fetcher));
+        self
+    }
+
+    /// Use the `suffixes` to match `DomainPattern::Registrable` patterns
+    pub fn with_public_suffix_list(mut self, suffixes: Arc<PublicSuffixList>) -> Self {
+        self.suffixes = suffixes;
+        self
+    }
+
+    /// The fetcher for a request to the `url`
+    pub fn get(&self, url: &reqwest::Url) -> &Arc<dyn Fetcher> {
+        let host = url.host_str().unwrap_or_default();
+        self.routes
+            .iter()
+            .find(|(pattern, _)| pattern.matches(host, url, &self.suffixes))
+            .map(|(_, fetcher)| fetcher)
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Fetcher for Fetchers {
+    fn fetch(&self, request: reqwest::Request) -> FetchFuture {
+        self.get(request.url()).fetch(request)
+    }
+
+    fn resolve(&self, url: &Url) -> ResolveFuture {
+        self.get(url).resolve(url)
+    }
+}
+
+/// Loads pages in a browser that is controlled with the WebDriver protocol,
+/// like chromedriver or geckodriver, so their responses contain the DOM after
+/// the javascript of the page ran.
+///
+/// The fetcher keeps a pool of browser sessions that are reused for
+/// subsequent requests. Only `GET` requests are supported, their headers and
+/// the status of the page are not visible to the browser, so every response
+/// is a `200 OK` with the page source as `text/html` body and the url the
+/// browser ended up on after redirects.
+///
+/// Sessions are quit in the background once the fetcher and all its clones
+/// are dropped, `quit` closes the idle ones right away.
+#[derive(Clone)]
+pub struct WebDriverFetcher {
+    /// The url of the WebDriver server, like `http://localhost:9515`
+    server_url: Arc<str>,
+    capabilities: Capabilities,
+    /// How long the browser may take to load a page
+    page_load_timeout: Duration,
+    /// A css selector to wait for after a page was loaded
+    wait_for: Option<(Arc<str>, Duration)>,
+    /// How often the `wait_for` selector is checked
+    poll_interval: Duration,
+    pool: Arc<SessionPool>,
+}
+
+impl WebDriverFetcher {
+    /// The maximum of concurrent sessions, unless set with `max_sessions`
+    pub const MAX_SESSIONS: usize = 4;
+
+    /// Load pages with the WebDriver server at `server_url`, with browsers
+    /// that have the `capabilities`, like `DesiredCapabilities::chrome()`
+    pub fn new(server_url: impl Into<String>, capabilities: impl Into<Capabilities>) -> Self {
+        Self {
+            server_url: server_url.into().into(),
+            capabilities: capabilities.into(),
+            page_load_timeout: Duration::from_secs(30),
+            wait_for: None,
+            poll_interval: Duration::from_millis(100),
+            pool: Arc::new(SessionPool::new(Self::MAX_SESSIONS)),
+        }
+    }
+
+    /// Open at most `sessions` browser sessions, requests wait until a
+    /// session is free
+    pub fn max_sessions(mut self, sessions: usize) -> Self {
+        self.pool = Arc::new(SessionPool::new(sessions.max(1)));
+        self
+    }
+
+    /// Fail requests whose page takes longer than `timeout` to load, 30
+    /// seconds by default
+    pub fn page_load_timeout(mut self, timeout: Duration) -> Self {
+        self.page_load_timeout = timeout;
+        self
+    }
+
+    /// Wait up to `timeout` until an element matches the css `selector`
+    /// before the page source is taken, fail the request otherwise
+    pub fn wait_for(mut self, selector: impl Into<String>, timeout: Duration) -> Self {
+        self.wait_for = Some((selector.into().into(), timeout));
+        self
+    }
+
+    /// How often the `wait_for` selector is checked, every 100ms by default
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Close all idle sessions of the pool
+    pub async fn quit(&self) -> Result<()> {
+        let idle = std::mem::take(&mut *self.pool.idle.lock().unwrap());
+        for driver in idle {
+            driver.quit().await?;
+        }
+        Ok(())
+    }
+
+    /// Load the `url` in the `driver` and return the final url and its source
+    async fn load(&self, driver: &WebDriver, url: &str) -> Result<(reqwest::Url, String)> {
+        let timeout = self.page_load_timeout;
+        tokio::time::timeout(timeout, driver.goto(url))
+            .await
+            .map_err(|_| anyhow!("loading {} timed out after {:?}", url, timeout))??;
+        if let Some((selector, timeout)) = &self.wait_for {
+            driver
+                .query(By::Css(selector))
+                .wait(*timeout, self.poll_interval)
+                .first()
+                .await?;
+        }
+        let source = driver.source().await?;
+        Ok((driver.current_url().await?, source))
+    }
+}
+
+impl Fetcher for WebDriverFetcher {
+    fn fetch(&self, request: reqwest::Request) -> FetchFuture {
+        let fetcher = self.clone();
+        Box::pin(async move {
+            if request.method() != Method::GET {
+                bail!(
+                    "WebDriverFetcher can't send {} requests to {}",
+                    request.method(),
+                    request.url()
+                );
+            }
+
+            let _permit = fetcher.pool.permits.acquire().await?;
+            let idle = fetcher.pool.idle.lock().unwrap().pop();
+            let driver = match idle {
+                Some(driver) => driver,
+                None => WebDriver::new(&fetcher.server_url, fetcher.capabilities.clone()).await?,
+            };
+            let mut session = Session {
+                driver: Some(driver),
+                pool: Arc::clone(&fetcher.pool),
+            };
+
+            match fetcher.load(session.driver(), request.url().as_str()).await {
+                Ok((url, source)) => {
+                    session.release();
+                    let resp = http::Response::builder()
+                        .header(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static("text/html; charset=utf-8"),
+                        )
+                        .url(url)
+                        .body(source)?;
+                    Ok(resp.into())
+                }
+                Err(err) => {
+                    // the session may be broken, don't reuse it
+                    if let Some(driver) = session.driver.take() {
+                        let _ = driver.quit().await;
+                    }
+                    Err(err)
+                }
+            }
+        })
+    }
+}
+
+/// The browser sessions of a `WebDriverFetcher`
+struct SessionPool {
+    /// Limits the sessions that are open at the same time
+    permits: Semaphore,
+    /// Sessions that are not used by a request
+    idle: Mutex<Vec<WebDriver>>,
+}
+
+impl SessionPool {
+    fn new(sessions: usize) -> Self {
+        Self {
+            permits: Semaphore::new(sessions),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Drop for SessionPool {
+    fn drop(&mut self) {
+        quit_later(std::mem::take(self.idle.get_mut().unwrap()));
+    }
+}
+
+/// A session that is used by a request, it is quit unless it is released
+/// back to the pool, like when the request is dropped while the page loads
+struct Session {
+    driver: Option<WebDriver>,
+    pool: Arc<SessionPool>,
+}
+
+impl Session {
+    fn driver(&self) -> &WebDriver {
+        self.driver
+            .as_ref()
+            .expect("a session that was not released")
+    }
+
+    /// Keep the session for the next request
+    fn release(&mut self) {
+        if let Some(driver) = self.driver.take() {
+            self.pool.idle.lock().unwrap().push(driver);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        quit_later(self.driver.take());
+    }
+}
+
+/// Quit the `drivers` in the background, if there is a runtime to run on
+fn quit_later(drivers: impl IntoIterator<Item = WebDriver>) {
+    let drivers: Vec<_> = drivers.into_iter().collect();
+    if drivers.is_empty() {
+        return;
+    }
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        runtime.spawn(async move {
+            for driver in drivers {
+                let _ = driver.quit().await;
+            }
+        });
+    }
+}
+
+/// A canned response of a `MockFetcher`
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+    /// How long the fetcher waits before it responds
+    latency: Duration,
+    /// Fail the request with this error instead of responding
+    error: Option<MockError>,
+}
+
+/// The error of a `MockResponse` that fails instead of responding
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{message}")]
+pub struct MockError {
+    kind: FetchErrorKind,
+    message: String,
+}
+
+impl MockError {
+    /// How the request failed
+    pub fn kind(&self) -> FetchErrorKind {
+        self.kind
+    }
+}
+
+impl MockResponse {
+    /// An empty response with the `status`
+    pub fn new(status: u16) -> Self {
+        Self {
+            status: StatusCode::from_u16(status).expect("a valid status code"),
+            headers: Vec::new(),
+            body: Bytes::new(),
+            latency: Duration::default(),
+            error: None,
+        }
+    }
+
+    /// A `200 OK` with the `html` as body
+    pub fn html(html: impl Into<String>) -> Self {
+        Self::new(200)
+            .header(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            )
+            .body(html.into())
+    }
+
+    /// A request that fails with the `error`, which is not retried
+    pub fn error(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(MockError {
+                kind: FetchErrorKind::Other,
+                message: error.into(),
+            }),
+            ..Self::new(200)
+        }
+    }
+
+    /// A request that fails like a `reqwest::Error` of the `kind`, so a
+    /// refused, timed out or reset connection is retried like a real one
+    pub fn failure(kind: FetchErrorKind) -> Self {
+        let message = match kind {
+            FetchErrorKind::Connect => "connection refused",
+            FetchErrorKind::Timeout => "operation timed out",
+            FetchErrorKind::Reset => "connection reset by peer",
+            FetchErrorKind::Other => "request failed",
+        };
+        Self {
+            error: Some(MockError {
+                kind,
+                message: message.to_string(),
+            }),
+            ..Self::new(200)
+        }
+    }
+
+    /// Add a header to the response
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Respond after `latency`
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    fn into_response(self, url: Url) -> Result<reqwest::Response> {
+        if let Some(error) = self.error {
+            return Err(error.into());
+        }
+        let mut resp = http::Response::builder().status(self.status).url(url);
+        for (name, value) in self.headers {
+            resp = resp.header(name, value);
+        }
+        Ok(resp.body(self.body)?.into())
+    }
+}
+
+/// Serves canned responses keyed by url, to test scrapers and crawl policies
+/// without network.
+///
+/// Several responses for the same url are served in order, the last one is
+/// repeated. Urls without a response are answered with `404 Not Found`. The
+/// fetcher is cheap to clone, all clones share the same responses, so a clone
+/// can be kept to inspect the `requests` after the crawl.
+///
+/// Hosts are only resolved to the addresses that were added with `address`.
+#[derive(Debug, Clone, Default)]
+pub struct MockFetcher {
+    responses: Arc<Mutex<HashMap<Url, VecDeque<MockResponse>>>>,
+    /// The urls of all fetched requests, in order
+    requests: Arc<Mutex<Vec<Url>>>,
+    /// The ip addresses of the hosts
+    addrs: Arc<Mutex<HashMap<String, IpAddr>>>,
+}
+
+impl MockFetcher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Serve the `response` for the `url`, after the responses that were
+    /// already added for it
+    pub fn on(self, url: &str, response: MockResponse) -> Self {
+        self.insert(url, response);
+        self
+    }
+
+    /// Serve the `html` for the `url`
+    pub fn html(self, url: &str, html: impl Into<String>) -> Self {
+        self.on(url, MockResponse::html(html))
+    }
+
+    /// Resolve the `host` to the `addr`
+    pub fn address(self, host: &str, addr: IpAddr) -> Self {
+        self.addrs.lock().unwrap().insert(host.to_string(), addr);
+        self
+    }
+
+    /// Serve the `response` for the `url`, after the responses that were
+    /// already added for it
+    pub fn insert(&self, url: &str, response: MockResponse) {
+        let url = Url::parse(url).expect("a valid url");
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(url)
+            .or_default()
+            .push_back(response);
+    }
+
+    /// The urls of all fetched requests, in order
+    pub fn requests(&self) -> Vec<Url> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// How often the `url` was fetched
+    pub fn request_count(&self, url: &str) -> usize {
+        let url = Url::parse(url).expect("a valid url");
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| **request == url)
+            .count()
+    }
+}
+
+impl Fetcher for MockFetcher {
+    fn fetch(&self, request: reqwest::Request) -> FetchFuture {
+        let url = request.url().clone();
+        self.requests.lock().unwrap().push(url.clone());
+        let response = match self.responses.lock().unwrap().get_mut(&url) {
+            Some(responses) if responses.len() > 1 => responses.pop_front(),
+            Some(responses) => responses.front().cloned(),
+            None => None,
+        }
+        .unwrap_or_else(|| MockResponse::new(404));
+        Box::pin(async move {
+            if !response.latency.is_zero() {
+                Delay::new(response.latency).await;
+            }
+            response.into_response(url)
+        })
+    }
+
+    fn resolve(&self, url: &Url) -> ResolveFuture {
+        let host = url.host_str().unwrap_or_default();
+        let addr = self.addrs.lock().unwrap().get(host).copied();
+        Box::pin(async move { addr })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use thirtyfour::DesiredCapabilities;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const RENDERED: &str = "<html><body><h1 id=\"title\">Rendered</h1></body></html>";
+
+    /// Answers the WebDriver commands of a single session, counting the opened
+    /// and closed sessions
+    async fn handle(stream: TcpStream, sessions: Arc<[AtomicUsize; 2]>) {
+        let mut stream = BufReader::new(stream);
+        let mut location = String::from("about:blank");
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let mut parts = line.split_whitespace();
+            let method = parts.next().unwrap_or_default().to_string();
+            let path = parts.next().unwrap_or_default().to_string();
+
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                stream.read_line(&mut header).await.unwrap();
+                let header = header.trim();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+
+            let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+            let value = match (method.as_str(), segments.as_slice()) {
+                ("POST", ["session"]) => {
+                    sessions[0].fetch_add(1, Ordering::SeqCst);
+                    json!({ "sessionId": "stub", "capabilities": {} })
+                }
+                ("DELETE", ["session", _]) => {
+                    sessions[1].fetch_add(1, Ordering::SeqCst);
+                    Value::Null
+                }
+                ("POST", ["session", _, "url"]) => {
+                    location = body["url"].as_str().unwrap().to_string();
+                    if location.ends_with("/slow") {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                    Value::Null
+                }
+                ("GET", ["session", _, "url"]) => json!(format!("{}#rendered", location)),
+                ("GET", ["session", _, "source"]) => json!(RENDERED),
+                ("POST", ["session", _, "element"]) => {
+                    json!({ "element-6066-11e4-a52e-4f735466cecf": "title" })
+                }
+                ("POST", ["session", _, "elements"]) => {
+                    json!([{ "element-6066-11e4-a52e-4f735466cecf": "title" }])
+                }
+                _ => Value::Null,
+            };
+            let body = json!({ "value": value }).to_string();
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.get_mut().write_all(resp.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_rendered_pages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sessions = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let counts = Arc::clone(&sessions);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle(stream, Arc::clone(&counts)));
+            }
+        });
+
+        let browser =
+            WebDriverFetcher::new(format!("http://{}", addr), DesiredCapabilities::chrome())
+                .max_sessions(1)
+                .page_load_timeout(Duration::from_millis(200))
+                .wait_for("#title", Duration::from_secs(1));
+        let fetchers = Fetchers::new(reqwest::Client::new()).route(
+            DomainPattern::subdomains("example.com"),
+            Arc::new(browser.clone()),
+        );
+
+        for page in ["a", "b"] {
+            let url = format!("https://www.example.com/{}", page);
+            let request = reqwest::Request::new(Method::GET, url.parse().unwrap());
+            let resp = fetchers.fetch(request).await.unwrap();
+            assert_eq!(resp.url().as_str(), format!("{}#rendered", url));
+            assert_eq!(resp.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+            assert_eq!(resp.text().await.unwrap(), RENDERED);
+        }
+        // the session was reused
+        assert_eq!(sessions[0].load(Ordering::SeqCst), 1);
+
+        let post = reqwest::Request::new(Method::POST, "https://example.com/".parse().unwrap());
+        assert!(fetchers.fetch(post).await.is_err());
+
+        // a page that doesn't load in time fails and its session is quit
+        let slow = reqwest::Request::new(Method::GET, "https://example.com/slow".parse().unwrap());
+        assert!(fetchers.fetch(slow).await.is_err());
+        assert_eq!(sessions[1].load(Ordering::SeqCst), 1);
+
+        // the idle sessions are quit once the fetcher is dropped
+        let page = reqwest::Request::new(Method::GET, "https://example.com/a".parse().unwrap());
+        fetchers.fetch(page).await.unwrap();
+        assert_eq!(sessions[0].load(Ordering::SeqCst), 2);
+        drop((fetchers, browser));
+        for _ in 0..50 {
+            if sessions[1].load(Ordering::SeqCst) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(sessions[1].load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn mock_responses() {
+        let fetcher = MockFetcher::new()
+            .on(
+                "https://example.com/data",
+                MockResponse::new(201)
+                    .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+                    .body("[1]"),
+            )
+            .on(
+                "https://example.com/reset",
+                MockResponse::failure(FetchErrorKind::Reset),
+            )
+            .on("https://example.com/error", MockResponse::error("broken"));
+        let get = |url: &str| reqwest::Request::new(Method::GET, url.parse().unwrap());
+
+        let resp = fetcher
+            .fetch(get("https://example.com/data"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(resp.text().await.unwrap(), "[1]");
+
+        let resp = fetcher
+            .fetch(get("https://example.com/missing"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let policy = crate::RetryPolicy::default();
+        let err = fetcher
+            .fetch(get("https://example.com/reset"))
+            .await
+            .unwrap_err();
+        assert_eq!(FetchErrorKind::of(&err), FetchErrorKind::Reset);
+        assert!(policy.is_retryable_fetch_error(&err));
+        let err = fetcher
+            .fetch(get("https://example.com/error"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "broken");
+        assert!(!policy.is_retryable_fetch_error(&err));
+        assert_eq!(fetcher.requests().len(), 4);
+    }
+}
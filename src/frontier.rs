@@ -0,0 +1,404 @@
+//! Checkpoints of the crawl frontier.
+//!
+//! The frontier is every request that was queued but did not finish yet. A
+//! [`Journal`] appends a record to a JSON lines file whenever a request is
+//! queued or finished, so the frontier can be restored after the process was
+//! killed. `Collector::checkpoint` compacts the file to the pending requests,
+//! the fingerprints of all seen requests and the current `Stats`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Method, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::dedup::Fingerprint;
+use crate::requests::QueuedRequest;
+use crate::Stats;
+
+/// Reports the id of a journaled request once it is dropped.
+///
+/// The ticket travels with the request through the queues and ends up in the
+/// `Response`, so the request is only considered finished after the response
+/// was handed to the `Scraper`, or after the request failed for good.
+#[derive(Debug)]
+pub(crate) struct Ticket {
+    id: u64,
+    finished: Arc<Mutex<Vec<u64>>>,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if let Ok(mut finished) = self.finished.lock() {
+            finished.push(self.id);
+        }
+    }
+}
+
+/// A `reqwest::Request` and its state in a form that can be stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerializedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, Vec<u8>)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Vec<u8>>,
+    pub depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<serde_json::Value>,
+}
+
+impl SerializedRequest {
+    fn new(request: &reqwest::Request, depth: usize, state: Option<serde_json::Value>) -> Self {
+        Self {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            headers: request
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            // streaming bodies are lost
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(<[u8]>::to_vec),
+            depth,
+            state,
+        }
+    }
+
+    /// Restore the request
+    fn to_request(&self) -> Result<reqwest::Request> {
+        let method = Method::from_str(&self.method)?;
+        let url = Url::parse(&self.url)?;
+        let mut request = reqwest::Request::new(method, url);
+        for (name, value) in &self.headers {
+            request
+                .headers_mut()
+                .append(HeaderName::from_str(name)?, HeaderValue::from_bytes(value)?);
+        }
+        if let Some(body) = &self.body {
+            *request.body_mut() = Some(body.clone().into());
+        }
+        Ok(request)
+    }
+}
+
+/// A single line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    /// A request was queued
+    Queued { id: u64, request: SerializedRequest },
+    /// A request finished, either successfully or for good
+    Finished { id: u64 },
+    /// Fingerprints of requests that were seen by the `SeenSet`
+    Seen { fingerprints: Vec<u64> },
+    /// The `Stats` at the time of the last checkpoint
+    Stats { stats: Stats },
+}
+
+/// The frontier restored from a journal
+pub(crate) struct Restored<S> {
+    pub requests: Vec<QueuedRequest<S>>,
+    pub seen: Vec<Fingerprint>,
+    pub stats: Option<Stats>,
+}
+
+fn serialize_state<S: Serialize>(state: &S) -> serde_json::Result<serde_json::Value> {
+    serde_json::to_value(state)
+}
+
+/// Appends the changes of the frontier to a file
+pub(crate) struct Journal<S> {
+    path: PathBuf,
+    file: BufWriter<File>,
+    next_id: u64,
+    /// The pending requests
+    pending: BTreeMap<u64, SerializedRequest>,
+    /// Ids of requests whose tickets were dropped
+    finished: Arc<Mutex<Vec<u64>>>,
+    serialize: fn(&S) -> serde_json::Result<serde_json::Value>,
+}
+
+impl<S> Journal<S> {
+    /// Open the journal at `path` and restore its frontier.
+    ///
+    /// If the file does not exist a new journal is created. The file is
+    /// compacted right away.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, Restored<S>)>
+    where
+        S: Serialize + DeserializeOwned,
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut pending = BTreeMap::new();
+        let mut seen = Vec::new();
+        let mut stats = None;
+
+        if path.exists() {
+            let reader = BufReader::new(
+                File::open(&path)
+                    .with_context(|| format!("Failed to open journal {}", path.display()))?,
+            );
+            let mut lines = reader.lines().peekable();
+            while let Some(line) = lines.next() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = match serde_json::from_str::<Record>(&line) {
+                    Ok(record) => record,
+                    // the last line may be cut off if the process was killed while writing
+                    Err(_) if lines.peek().is_none() => break,
+                    Err(err) => return Err(err).context("Corrupt journal record"),
+                };
+                match record {
+                    Record::Queued { id, request } => {
+                        pending.insert(id, request);
+                    }
+                    Record::Finished { id } => {
+                        pending.remove(&id);
+                    }
+                    Record::Seen { fingerprints } => {
+                        seen.extend(fingerprints.into_iter().map(Fingerprint::from_u64));
+                    }
+                    Record::Stats { stats: s } => stats = Some(s),
+                }
+            }
+        }
+
+        let mut journal = Journal {
+            file: BufWriter::new(OpenOptions::new().create(true).append(true).open(&path)?),
+            path,
+            next_id: pending
+                .keys()
+                .next_back()
+                .map(|id| id + 1)
+                .unwrap_or_default(),
+            pending,
+            finished: Default::default(),
+            serialize: serialize_state::<S>,
+        };
+
+        let mut requests = Vec::with_capacity(journal.pending.len());
+        for (id, req) in &journal.pending {
+            let state = req
+                .state
+                .clone()
+                .map(serde_json::from_value::<S>)
+                .transpose()
+                .context("Failed to restore the state of a request")?;
+            requests.push(QueuedRequest {
+                request: req.to_request()?,
+                state,
+                depth: req.depth,
+                attempts: Vec::new(),
+                ticket: Some(journal.ticket(*id)),
+            });
+        }
+
+        journal.compact(stats.as_ref(), Some(&seen))?;
+
+        Ok((
+            journal,
+            Restored {
+                requests,
+                seen,
+                stats,
+            },
+        ))
+    }
+
+    fn ticket(&self, id: u64) -> Ticket {
+        Ticket {
+            id,
+            finished: Arc::clone(&self.finished),
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> Result<()> {
+        serde_json::to_writer(&mut self.file, record)?;
+        self.file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Attach a ticket to the request and return the record of the request
+    /// that is written with `queued` once the request was accepted
+    pub fn prepare(&mut self, request: &mut QueuedRequest<S>) -> Result<(u64, SerializedRequest)> {
+        let state = request.state.as_ref().map(self.serialize).transpose()?;
+        let id = self.next_id;
+        self.next_id += 1;
+        request.ticket = Some(self.ticket(id));
+        Ok((
+            id,
+            SerializedRequest::new(&request.request, request.depth, state),
+        ))
+    }
+
+    /// Record that the prepared request was queued
+    pub fn queued(&mut self, id: u64, request: SerializedRequest) -> Result<()> {
+        self.write(&Record::Queued {
+            id,
+            request: request.clone(),
+        })?;
+        self.file.flush()?;
+        self.pending.insert(id, request);
+        Ok(())
+    }
+
+    /// Write all requests that finished since the last call
+    pub fn sync(&mut self) -> Result<()> {
+        let finished = std::mem::take(&mut *self.finished.lock().unwrap());
+        if finished.is_empty() {
+            return Ok(());
+        }
+        for id in finished {
+            // requests that were rejected right away were never written
+            if self.pending.remove(&id).is_some() {
+                self.write(&Record::Finished { id })?;
+            }
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Rewrite the journal with only the pending requests, the `seen`
+    /// fingerprints and the `stats`
+    pub fn compact(&mut self, stats: Option<&Stats>, seen: Option<&[Fingerprint]>) -> Result<()> {
+        self.sync()?;
+        let tmp = self.path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        let mut write = |record: &Record| -> Result<()> {
+            serde_json::to_writer(&mut file, record)?;
+            file.write_all(b"\n")?;
+            Ok(())
+        };
+        if let Some(stats) = stats {
+            write(&Record::Stats {
+                stats: stats.clone(),
+            })?;
+        }
+        if let Some(seen) = seen {
+            for chunk in seen.chunks(1024) {
+                write(&Record::Seen {
+                    fingerprints: chunk.iter().map(Fingerprint::as_u64).collect(),
+                })?;
+            }
+        }
+        for (id, request) in &self.pending {
+            write(&Record::Queued {
+                id: *id,
+                request: request.clone(),
+            })?;
+        }
+        file.flush()?;
+        drop(file);
+        fs::rename(&tmp, &self.path)?;
+
+        self.file = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        Ok(())
+    }
+
+    /// Record the fingerprint of a request that was seen
+    pub fn seen(&mut self, fingerprint: Fingerprint) -> Result<()> {
+        self.write(&Record::Seen {
+            fingerprints: vec![fingerprint.as_u64()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::QueuedRequestBuilder;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "rust-crawler-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn request(url: &str, state: &str) -> QueuedRequest<String> {
+        QueuedRequestBuilder {
+            request: reqwest::Client::new().get(url).header("x-page", "1"),
+            state: Some(state.to_string()),
+            depth: 2,
+        }
+        .build()
+        .unwrap()
+    }
+
+    fn queue(
+        journal: &mut Journal<String>,
+        mut req: QueuedRequest<String>,
+    ) -> QueuedRequest<String> {
+        let (id, record) = journal.prepare(&mut req).unwrap();
+        journal.queued(id, record).unwrap();
+        req
+    }
+
+    #[test]
+    fn restores_pending_requests() {
+        let path = journal_path("restore");
+        let (mut journal, restored) = Journal::<String>::open(&path).unwrap();
+        assert!(restored.requests.is_empty());
+
+        let finished = queue(&mut journal, request("https://example.com/a", "a"));
+        let pending = queue(&mut journal, request("https://example.com/b", "b"));
+        journal.seen(Fingerprint::from_u64(42)).unwrap();
+        drop(finished);
+        journal.sync().unwrap();
+        let stats = Stats {
+            request_count: 2,
+            ..Default::default()
+        };
+        journal.compact(Some(&stats), None).unwrap();
+        drop(pending);
+        drop(journal);
+
+        // the pending request was dropped after the checkpoint, but never synced
+        let (_, restored) = Journal::<String>::open(&path).unwrap();
+        assert_eq!(restored.requests.len(), 1);
+        let req = &restored.requests[0];
+        assert_eq!(req.request.url().as_str(), "https://example.com/b");
+        assert_eq!(req.request.headers()["x-page"], "1");
+        assert_eq!(req.state.as_deref(), Some("b"));
+        assert_eq!(req.depth, 2);
+        assert_eq!(restored.stats.unwrap().request_count, 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignores_truncated_record() {
+        let path = journal_path("truncated");
+        let (mut journal, _) = Journal::<String>::open(&path).unwrap();
+        let _req = queue(&mut journal, request("https://example.com/a", "a"));
+        journal.seen(Fingerprint::from_u64(7)).unwrap();
+        journal.file.flush().unwrap();
+        drop(journal);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"queued","id":3,"req"#).unwrap();
+        drop(file);
+
+        let (_, restored) = Journal::<String>::open(&path).unwrap();
+        assert_eq!(restored.requests.len(), 1);
+        assert_eq!(restored.seen, vec![Fingerprint::from_u64(7)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
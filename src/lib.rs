@@ -1,24 +1,71 @@
 use anyhow::Result;
 use futures::stream::Stream;
 use futures::FutureExt;
+use reqwest::header::InvalidHeaderValue;
 use reqwest::IntoUrl;
-use std::collections::{HashMap, HashSet, VecDeque};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
+pub mod canonicalize;
+pub mod charset;
+pub mod dedup;
 mod domain;
 pub mod error;
+pub mod export;
+pub mod extract;
+pub mod feed;
+pub mod fetch;
+mod frontier;
+pub mod limits;
+pub mod links;
+pub mod middleware;
+pub mod pattern;
+pub mod pipeline;
+pub mod query;
 mod requests;
+pub mod retry;
 pub mod response;
 
 pub mod robots;
+pub mod sitemap;
+pub mod throttle;
 
-pub use crate::requests::RequestDelay;
+pub use crate::canonicalize::CanonicalizeRules;
+use crate::dedup::{Dedup, DuplicatePolicy};
+use crate::error::CrawlError;
+pub use crate::fetch::{
+    FetchErrorKind, Fetcher, Fetchers, MockError, MockFetcher, MockResponse, WebDriverFetcher,
+};
+use crate::frontier::Journal;
+pub use crate::frontier::SerializedRequest;
+pub use crate::limits::ConcurrencyLimits;
+pub use crate::links::{FollowRule, LinkExtractor};
+pub use crate::middleware::{Action, Middleware};
+pub use crate::pattern::DomainPattern;
+use crate::pattern::PublicSuffixList;
+pub use crate::pipeline::ItemPipeline;
+use crate::pipeline::Pipeline;
+pub use crate::requests::{QueuedRequest, RequestDelay};
 use crate::requests::{response_info, QueuedRequestBuilder};
-pub use crate::response::Response;
+use crate::response::{read_body, Body};
+pub use crate::response::{BodyLimits, BodyStream, ParsedResponse, Response};
+pub use crate::retry::RetryPolicy;
+pub use crate::robots::{RobotsCache, RobotsPolicy, UserAgent};
+use crate::robots::RobotsData;
+use crate::sitemap::{
+    Found, Sitemap, SitemapClient, SitemapJob, SitemapRequest, SitemapRobots, SitemapUrl,
+};
+pub use crate::throttle::{AutoThrottle, ThrottleSettings};
 pub use domain::{AllowList, AllowListConfig, BlockList, DomainListing};
+/// Reexport the encodings of `Response::encoding`
+pub use encoding_rs;
 /// Reexport all the scraper types
 pub use scraper;
 
@@ -30,6 +77,12 @@ pub struct Collector<T: Scraper> {
     crawler: Crawler<T>,
     /// The scraper that extracts the information from a `Response`
     pub scraper: T,
+    /// Rules whose links are queued for every response
+    follow: Vec<FollowRule<T::State>>,
+    /// The stages every output passes before it is yielded
+    pipeline: Pipeline<T::Output>,
+    /// Outputs of the scraper that wait for the pipeline
+    outputs: VecDeque<T::Output>,
 }
 
 impl<T> Collector<T>
@@ -42,9 +95,33 @@ where
         Self {
             crawler: Crawler::new(config),
             scraper,
+            follow: Vec::new(),
+            pipeline: Default::default(),
+            outputs: Default::default(),
         }
     }
 
+    /// Queue the links that the `rule` finds on every crawled page, before the
+    /// page is passed to the scraper
+    pub fn follow(mut self, rule: FollowRule<T::State>) -> Self {
+        self.follow.push(rule);
+        self
+    }
+
+    /// Pass every output to the `stage`, after the stages that were added
+    /// before, like `collector.pipe(validate).pipe(dedup)`
+    pub fn pipe(mut self, stage: impl ItemPipeline<T::Output> + 'static) -> Self {
+        self.pipeline.push(Box::new(stage));
+        self
+    }
+
+    /// Run the `middleware` on all requests and responses, after the
+    /// middlewares that were added before
+    pub fn middleware(mut self, middleware: impl Middleware<T::State> + 'static) -> Self {
+        self.crawler.list.add_middleware(middleware);
+        self
+    }
+
     /// The scraper of this collector
     pub fn scraper(&self) -> &T {
         &self.scraper
@@ -69,6 +146,79 @@ where
     pub fn stats(&self) -> &Stats {
         &self.crawler.stats
     }
+
+    /// Stats about the requests to each host, by canonical host
+    pub fn host_stats(&self) -> &BTreeMap<String, HostStats> {
+        &self.crawler.hosts
+    }
+
+    /// Write a checkpoint of the frontier to the journal file of a collector
+    /// created with `Collector::resume_from`.
+    ///
+    /// This compacts the journal to the pending requests, the seen set and the
+    /// current `Stats`. Queued and finished requests are journaled as they
+    /// happen, so this only keeps the file small.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let crawler = &mut self.crawler;
+        let journal = match crawler.journal.as_mut() {
+            Some(journal) => journal,
+            None => anyhow::bail!("The collector has no journal, use `Collector::resume_from`"),
+        };
+        let seen = crawler
+            .dedup
+            .as_ref()
+            .and_then(|dedup| dedup.seen().fingerprints());
+        journal.compact(Some(&crawler.stats), seen.as_deref())
+    }
+}
+
+impl<T> Collector<T>
+where
+    T: Scraper + Unpin + 'static,
+    <T as Scraper>::State: Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+    <T as Scraper>::Output: Unpin,
+{
+    /// Create a new `Collector` that journals its frontier to the file at
+    /// `path` and continues the crawl that was journaled there before.
+    ///
+    /// The pending requests with their depth and state are queued again, the
+    /// seen set of the deduplication layer and the `Stats` are restored. If the
+    /// file does not exist yet, the crawl starts empty. Requests that were in
+    /// flight when the process stopped are sent again.
+    ///
+    /// Seed requests should be queued with `CrawlerConfig::deduplicate_requests`
+    /// enabled, so that they are not crawled a second time after a restart.
+    pub fn resume_from(scraper: T, config: CrawlerConfig, path: impl AsRef<Path>) -> Result<Self> {
+        let (journal, restored) = Journal::open(path)?;
+        let mut crawler = Crawler::new(config);
+
+        if let Some(stats) = restored.stats {
+            // the throttles start over
+            crawler.stats = Stats {
+                throttles: Default::default(),
+                ..stats
+            };
+        }
+        if let Some(dedup) = crawler.dedup.as_mut() {
+            for fingerprint in restored.seen {
+                dedup.seen_mut().insert(fingerprint);
+            }
+        }
+        for req in restored.requests {
+            if let Err(err) = crawler.list.add_request(req) {
+                crawler.queued_results.push_back(CrawlResult::Crawled(Err(err)));
+            }
+        }
+        crawler.journal = Some(journal);
+
+        Ok(Self {
+            crawler,
+            scraper,
+            follow: Vec::new(),
+            pipeline: Default::default(),
+            outputs: Default::default(),
+        })
+    }
 }
 
 impl<T> Stream for Collector<T>
@@ -83,19 +233,70 @@ where
         let pin = self.get_mut();
 
         loop {
-            match pin.crawler.poll(cx) {
+            // finish the item in the pipeline before the next one starts
+            let processing = match pin.pipeline.poll_item(cx) {
+                Poll::Ready(Some(result)) => return Poll::Ready(Some(result)),
+                Poll::Ready(None) => false,
+                Poll::Pending => true,
+            };
+            if !processing {
+                if let Some(output) = pin.outputs.pop_front() {
+                    if let Some(output) = pin.pipeline.start(output) {
+                        return Poll::Ready(Some(Ok(output)));
+                    }
+                    continue;
+                }
+            } else if pin.outputs.len() >= Self::MAX_PENDING_OUTPUTS {
+                return Poll::Pending;
+            }
+            // keep crawling while a stage is pending, the outputs wait for it
+            match pin.poll_output(cx) {
+                Poll::Ready(Some(Ok(output))) => pin.outputs.push_back(output),
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) if processing => return Poll::Pending,
+                Poll::Ready(None) => {
+                    return pin.pipeline.poll_close(cx).map(|closed| closed.err().map(Err))
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T> Collector<T>
+where
+    T: Scraper + Unpin + 'static,
+    <T as Scraper>::State: Unpin + Send + Sync + 'static,
+    <T as Scraper>::Output: Unpin,
+{
+    /// How many outputs wait for a pending pipeline stage before the crawler
+    /// is paused
+    pub const MAX_PENDING_OUTPUTS: usize = 1024;
+
+    /// The next output of the scraper
+    fn poll_output(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T::Output>>> {
+        loop {
+            match self.crawler.poll(cx) {
                 Poll::Ready(Some(result)) => match result {
                     CrawlResult::Finished(Ok(output)) => return Poll::Ready(Some(Ok(output))),
                     CrawlResult::Finished(Err(err)) => return Poll::Ready(Some(Err(err))),
                     CrawlResult::Crawled(Ok(response)) => {
                         // make sure the crawler knows the depth
-                        pin.crawler.current_depth = response.depth;
-                        pin.crawler.stats.response_count =
-                            pin.crawler.stats.response_count.wrapping_add(1);
+                        self.crawler.current_depth = response.depth;
+                        self.crawler.stats.response_count =
+                            self.crawler.stats.response_count.wrapping_add(1);
 
-                        let output = pin.scraper.scrape(response, &mut pin.crawler);
+                        for rule in &self.follow {
+                            for (url, state) in rule.links(&response) {
+                                match state {
+                                    Some(state) => self.crawler.visit_with_state(url, state),
+                                    None => self.crawler.visit(url),
+                                }
+                            }
+                        }
+                        let output = self.scraper.scrape(response, &mut self.crawler);
 
-                        pin.crawler.current_depth = 0;
+                        self.crawler.current_depth = 0;
 
                         match output {
                             Ok(Some(output)) => return Poll::Ready(Some(Ok(output))),
@@ -122,16 +323,37 @@ pub struct Crawler<T: Scraper> {
     in_progress_complete_requests: Vec<OutputRequest<T::Output>>,
     /// All injected futures that create a new state
     in_progress_crawl_requests: Vec<CrawlRequest<T::State>>,
+    /// Sitemaps that are downloaded to seed the frontier
+    in_progress_sitemaps: Vec<SitemapRequest<T::State>>,
     queued_results: VecDeque<CrawlResult<T>>,
-    /// The client that issues all the requests
+    /// The client that builds all the requests
     client: reqwest::Client,
+    /// Sends the requests of sitemaps and `crawl_with_fetchers` futures,
+    /// shared with the `list`
+    fetchers: Arc<Fetchers>,
+    /// The `User-Agent` header added to requests that don't set one
+    user_agent: Option<reqwest::header::HeaderValue>,
+    /// The product token that selects the robots.txt rules
+    robots_agent: Option<String>,
     /// used to track the depth of submitted requests
     current_depth: usize,
     /// Either a list that only allows a set of domains or disallows a set of
     /// domains
     list: DomainListing<T::State>,
+    /// The robots.txt files of all origins, shared with the `list`
+    robots: RobotsCache,
     /// Stats about requests
     stats: Stats,
+    /// Stats about the requests to each canonical host
+    hosts: BTreeMap<String, HostStats>,
+    /// The rules that canonicalize the hosts of `hosts`
+    canonicalize: CanonicalizeRules,
+    /// Drops requests that were already queued before
+    dedup: Option<Dedup>,
+    /// Journals the frontier, if the crawl can be resumed
+    journal: Option<Journal<T::State>>,
+    /// How response bodies are read
+    body: BodyLimits,
     /// The maximum depth request are allowed to next
     max_depth: usize,
     /// Respect any restrictions set by the target host's robots.txt file
@@ -144,7 +366,77 @@ pub struct Crawler<T: Scraper> {
 impl<T: Scraper> Crawler<T> {
     /// Create a new crawler following the config
     pub fn new(config: CrawlerConfig) -> Self {
-        let client = config.client.unwrap_or_default();
+        let robots_agent = config
+            .robots_agent
+            .as_ref()
+            .map(|(agent, _)| agent.clone())
+            .or_else(|| config.user_agent.as_ref().map(|(agent, _)| agent.product_token()));
+        let user_agent = config
+            .user_agent
+            .map(|(_, header)| header)
+            .or_else(|| config.robots_agent.map(|(_, header)| header));
+        let builder = || match &user_agent {
+            Some(agent) => reqwest::Client::builder().user_agent(agent.clone()),
+            None => reqwest::Client::builder(),
+        };
+        let robots = config.robots_cache.unwrap_or_else(|| {
+            // the cache follows the redirects of robots.txt files itself, up
+            // to the limit of its policy
+            if let Some(fetcher) = &config.fetcher {
+                return RobotsCache::with_fetcher(Arc::clone(fetcher));
+            }
+            let client = match &config.client {
+                Some(client) => client.clone(),
+                None => builder()
+                    .redirect(reqwest::redirect::Policy::none())
+                    .build()
+                    .unwrap_or_default(),
+            };
+            RobotsCache::new(client)
+        });
+        let robots = match config.robots_policy {
+            Some(policy) => robots.with_policy(policy),
+            None => robots,
+        };
+        // a custom client doesn't send the user agent
+        let robots = match &user_agent {
+            Some(agent) if robots.user_agent().is_none() => robots.with_user_agent(agent.clone()),
+            _ => robots,
+        };
+        let client = config
+            .client
+            .unwrap_or_else(|| builder().build().unwrap_or_default());
+        let fetchers = match config.fetcher {
+            Some(fetcher) => Fetchers::new(fetcher),
+            None => Fetchers::new(client.clone()),
+        };
+        let fetchers = config.fetchers.into_iter().fold(
+            fetchers.with_public_suffix_list(config.public_suffixes.clone()),
+            |fetchers, (pattern, fetcher)| fetchers.route(pattern, fetcher),
+        );
+        let fetchers = Arc::new(fetchers);
+
+        // one set of limits for all domains, so a slow host can't take the
+        // permits of the others
+        let limits = ConcurrencyLimits::new(
+            config
+                .max_requests
+                .unwrap_or(CrawlerConfig::MAX_CONCURRENT_REQUESTS),
+        )
+        .with_public_suffix_list(config.public_suffixes.clone());
+        let limits = config.max_requests_per_domain.into_iter().fold(
+            limits,
+            |limits, (pattern, max_requests)| limits.per_domain(pattern, max_requests),
+        );
+        let limits = match config.max_requests_per_host {
+            Some(max_requests) => limits.per_host(max_requests),
+            None => limits,
+        };
+        let limits = match config.max_requests_per_ip {
+            Some(max_requests) => limits.per_ip(max_requests),
+            None => limits,
+        };
+        let limits = Arc::new(limits);
 
         let list = if config.allowed_domains.is_empty() {
             let block_list = BlockList::new(
@@ -156,24 +448,54 @@ impl<T: Scraper> Crawler<T> {
                 config
                     .max_requests
                     .unwrap_or(CrawlerConfig::MAX_CONCURRENT_REQUESTS),
-            );
+                config.canonicalize.clone(),
+            )
+            .with_public_suffix_list(config.public_suffixes.clone())
+            .with_robots_cache(robots.clone())
+            .with_body_limits(config.body)
+            .with_fetchers(Arc::clone(&fetchers))
+            .with_limits(Arc::clone(&limits));
+            let block_list = match config.host_delay {
+                Some(delay) => block_list.with_delay(delay),
+                None => block_list,
+            };
+            let block_list = if config.respect_robots_crawl_delay {
+                block_list
+            } else {
+                block_list.ignore_crawl_delay()
+            };
+            let block_list = match robots_agent.clone() {
+                Some(agent) => block_list.with_robots_agent(agent),
+                None => block_list,
+            };
+            let block_list = match config.retry.clone() {
+                Some(policy) => block_list.with_retry_policy(policy),
+                None => block_list,
+            };
             DomainListing::BlockList(block_list)
         } else {
-            let mut allow_list = AllowList::default();
+            let mut allow_list = AllowList::new(config.canonicalize.clone())
+                .with_public_suffix_list(config.public_suffixes.clone());
             let max_requests = config
                 .max_requests
-                .unwrap_or(CrawlerConfig::MAX_CONCURRENT_REQUESTS)
-                / config.allowed_domains.len();
-            for (domain, delay) in config.allowed_domains {
+                .unwrap_or(CrawlerConfig::MAX_CONCURRENT_REQUESTS);
+            for (pattern, delay) in config.allowed_domains {
                 let allow = AllowListConfig {
                     delay,
                     respect_robots_txt: config.respect_robots_txt,
+                    respect_crawl_delay: config.respect_robots_crawl_delay,
                     client: client.clone(),
                     skip_non_successful_responses: config.skip_non_successful_responses,
                     max_depth: config.max_depth.unwrap_or(usize::MAX),
                     max_requests,
+                    limits: Some(Arc::clone(&limits)),
+                    retry: config.retry.clone(),
+                    body: config.body,
+                    robots: robots.clone(),
+                    robots_agent: robots_agent.clone(),
+                    fetchers: Some(Arc::clone(&fetchers)),
                 };
-                allow_list.allow(domain, allow);
+                allow_list.allow_pattern(pattern, allow);
             }
             DomainListing::AllowList(allow_list)
         };
@@ -181,11 +503,24 @@ impl<T: Scraper> Crawler<T> {
         Self {
             in_progress_complete_requests: Default::default(),
             in_progress_crawl_requests: Default::default(),
+            in_progress_sitemaps: Default::default(),
             queued_results: Default::default(),
             client,
+            fetchers,
+            user_agent,
+            robots_agent,
             current_depth: 0,
             list,
+            robots,
             stats: Default::default(),
+            hosts: Default::default(),
+            dedup: config.dedup.map(|mut dedup| {
+                dedup.set_rules(config.canonicalize.clone());
+                dedup
+            }),
+            canonicalize: config.canonicalize,
+            journal: None,
+            body: config.body,
             max_depth: config.max_depth.unwrap_or(usize::MAX),
             respect_robots_txt: config.respect_robots_txt,
             skip_non_successful_responses: config.skip_non_successful_responses,
@@ -207,6 +542,37 @@ impl<T: Scraper> Crawler<T> {
     pub fn skips_non_successful_responses(&self) -> bool {
         self.skip_non_successful_responses
     }
+
+    /// The robots.txt of the `url`'s origin, if it was fetched and is not
+    /// expired.
+    ///
+    /// Use `RobotsData::check` to find out whether and why a url would be
+    /// rejected.
+    pub fn robots_for(&self, url: &reqwest::Url) -> Option<Arc<RobotsData>> {
+        self.robots.get(url)
+    }
+
+    /// The cache of all fetched robots.txt files
+    pub fn robots_cache(&self) -> &RobotsCache {
+        &self.robots
+    }
+
+    /// The product token whose robots.txt rules are obeyed, if configured.
+    ///
+    /// Otherwise the `User-Agent` of each request is matched.
+    pub fn robots_user_agent(&self) -> Option<&str> {
+        self.robots_agent.as_deref()
+    }
+
+    /// The deduplication layer, if requests are deduplicated
+    pub fn dedup(&self) -> Option<&Dedup> {
+        self.dedup.as_ref()
+    }
+
+    /// Mutable access to the deduplication layer
+    pub fn dedup_mut(&mut self) -> Option<&mut Dedup> {
+        self.dedup.as_mut()
+    }
 }
 
 impl<T> Crawler<T>
@@ -222,23 +588,50 @@ where
         TCrawlFunction: FnOnce(&reqwest::Client) -> TCrawlFuture,
         TCrawlFuture: Future<Output = Result<(reqwest::Response, Option<T::State>)>> + 'static,
     {
-        let depth = self.current_depth + 1;
         let fut = (fun)(&self.client);
+        self.push_crawl(fut)
+    }
+
+    /// Like `crawl`, but the request is sent with the `Fetchers` of the
+    /// crawler, so the configured `Fetcher`s like a `MockFetcher` apply.
+    pub fn crawl_with_fetchers<TCrawlFunction, TCrawlFuture>(&mut self, fun: TCrawlFunction)
+    where
+        TCrawlFunction: FnOnce(&Fetchers) -> TCrawlFuture,
+        TCrawlFuture: Future<Output = Result<(reqwest::Response, Option<T::State>)>> + 'static,
+    {
+        let fut = (fun)(&self.fetchers);
+        self.push_crawl(fut)
+    }
+
+    fn push_crawl<TCrawlFuture>(&mut self, fut: TCrawlFuture)
+    where
+        TCrawlFuture: Future<Output = Result<(reqwest::Response, Option<T::State>)>> + 'static,
+    {
+        let depth = self.current_depth + 1;
+        let limits = self.body;
         let fut = Box::pin(async move {
             let (mut resp, state) = fut.await?;
             let (status, url, headers) = response_info(&mut resp);
-            let text = resp.text().await?;
+            let (body, stream) = match read_body(resp, limits).await? {
+                Body::Bytes(body) => (body, None),
+                Body::Stream(stream) => (Default::default(), Some(stream)),
+                Body::TooLarge => {
+                    return Err(CrawlError::BodyTooLarge {
+                        request_url: url,
+                        limit: limits.max_size.unwrap_or_default(),
+                        state,
+                    }
+                    .into())
+                }
+            };
 
+            // Note: There is no way to determine the original url since only the response is
+            // returned from the future So `Response::new` sets the `request_url = response_url`
             Ok(Response {
                 depth,
-                // Note: There is no way to determine the original url since only the response is
-                // returned from the future So we set the `request_url = response_url`
-                request_url: url.clone(),
-                response_url: url,
-                response_status: status,
-                response_headers: headers,
-                text,
+                stream,
                 state,
+                ..Response::new(url, status, headers, body)
             })
         });
 
@@ -256,6 +649,17 @@ where
         self.in_progress_complete_requests.push(Box::pin(fut))
     }
 
+    /// Like `complete`, but the job sends its requests with the `Fetchers` of
+    /// the crawler like those of `crawl_with_fetchers`.
+    pub fn complete_with_fetchers<TCrawlFunction, TCrawlFuture>(&mut self, fun: TCrawlFunction)
+    where
+        TCrawlFunction: FnOnce(&Fetchers) -> TCrawlFuture,
+        TCrawlFuture: Future<Output = Result<Option<T::Output>>> + 'static,
+    {
+        let fut = (fun)(&self.fetchers);
+        self.in_progress_complete_requests.push(Box::pin(fut))
+    }
+
     /// This queues in a GET request for the `url`, without any state attached
     pub fn visit(&mut self, url: impl IntoUrl) {
         self.request(self.client.request(reqwest::Method::GET, url))
@@ -266,6 +670,139 @@ where
         self.request_with_state(self.client.request(reqwest::Method::GET, url), state)
     }
 
+    /// Download the sitemap at `url` and queue a GET request for each of its
+    /// urls, with the state returned by `state_fn`.
+    ///
+    /// Sitemap indexes are followed up to `sitemap::MAX_SITEMAP_NESTING` levels
+    /// and gzipped sitemaps are decompressed. The sitemaps themselves and the
+    /// requests pass the same checks as requests queued with `visit`.
+    pub fn visit_sitemap<F>(&mut self, url: impl IntoUrl, state_fn: F)
+    where
+        F: Fn(&SitemapUrl) -> Option<T::State> + 'static,
+    {
+        match url.into_url() {
+            Ok(url) => {
+                let job = self.sitemap_job(url, Arc::new(state_fn));
+                self.fetch_sitemap(job);
+            }
+            Err(err) => self
+                .queued_results
+                .push_back(CrawlResult::Crawled(Err(err.into()))),
+        }
+    }
+
+    /// Like `visit_sitemap`, but for all sitemaps that are declared in the
+    /// robots.txt of the `url`'s host, or its `/sitemap.xml` if there are none
+    pub fn discover_sitemaps<F>(&mut self, url: impl IntoUrl, state_fn: F)
+    where
+        F: Fn(&SitemapUrl) -> Option<T::State> + 'static,
+    {
+        match url.into_url() {
+            Ok(url) => {
+                let job = self.sitemap_job(url, Arc::new(state_fn));
+                if self.is_sitemap_allowed(&job) {
+                    self.in_progress_sitemaps
+                        .push(sitemap::find(self.robots.clone(), job));
+                }
+            }
+            Err(err) => self
+                .queued_results
+                .push_back(CrawlResult::Crawled(Err(err.into()))),
+        }
+    }
+
+    fn sitemap_job(
+        &self,
+        url: reqwest::Url,
+        state_fn: sitemap::StateFn<T::State>,
+    ) -> SitemapJob<T::State> {
+        SitemapJob {
+            url,
+            state_fn,
+            depth: self.current_depth,
+            nesting: 0,
+        }
+    }
+
+    /// Whether the allow and block lists let the sitemap of the `job` through,
+    /// otherwise the error is reported
+    fn is_sitemap_allowed(&mut self, job: &SitemapJob<T::State>) -> bool {
+        if self.list.is_allowed(&job.url) {
+            return true;
+        }
+        let err = error::SitemapError::Disallowed(job.url.clone(), error::DisallowReason::UserConfig);
+        self.queued_results
+            .push_back(CrawlResult::Crawled(Err(err.into())));
+        false
+    }
+
+    /// Download the sitemap of the `job` if the domain lists allow it, and the
+    /// robots.txt of its origin if it is respected
+    fn fetch_sitemap(&mut self, job: SitemapJob<T::State>) {
+        if !self.is_sitemap_allowed(&job) {
+            return;
+        }
+        let robots = self.respect_robots_txt.then(|| SitemapRobots {
+            cache: self.robots.clone(),
+            agent: self.sitemap_agent(),
+        });
+        self.in_progress_sitemaps
+            .push(sitemap::fetch(self.sitemap_client(), robots, job));
+    }
+
+    /// Sends the sitemap requests with the `User-Agent` of the crawler
+    fn sitemap_client(&self) -> SitemapClient {
+        SitemapClient {
+            fetchers: Arc::clone(&self.fetchers),
+            user_agent: self.user_agent.clone(),
+        }
+    }
+
+    /// The agent whose robots.txt rules apply to sitemaps
+    fn sitemap_agent(&self) -> String {
+        self.robots_agent
+            .clone()
+            .or_else(|| {
+                let agent = self.user_agent.as_ref()?.to_str().ok()?;
+                Some(agent.to_string())
+            })
+            .unwrap_or_else(|| "*".to_string())
+    }
+
+    /// Queue the urls of a downloaded sitemap or the downloads of the
+    /// sitemaps it references
+    fn handle_sitemap(&mut self, job: SitemapJob<T::State>, found: Found) {
+        match found {
+            Found::Sitemap(Sitemap::Urls(urls)) => {
+                let depth = std::mem::replace(&mut self.current_depth, job.depth);
+                for entry in urls {
+                    let state = (job.state_fn)(&entry);
+                    let request = self.client.request(reqwest::Method::GET, entry.loc);
+                    self.queue_request(request, state);
+                }
+                self.current_depth = depth;
+            }
+            Found::Sitemap(Sitemap::Index(entries)) => {
+                if job.nesting >= sitemap::MAX_SITEMAP_NESTING {
+                    let err = error::SitemapError::TooDeep(job.url, sitemap::MAX_SITEMAP_NESTING);
+                    self.queued_results
+                        .push_back(CrawlResult::Crawled(Err(err.into())));
+                    return;
+                }
+                for entry in entries {
+                    let nested = job.nested(entry.loc, job.nesting + 1);
+                    self.fetch_sitemap(nested);
+                }
+            }
+            Found::Discovered(urls) => {
+                for url in urls {
+                    let nested = job.nested(url, job.nesting);
+                    self.fetch_sitemap(nested);
+                }
+            }
+        }
+    }
+
     /// This queues in a whole request with no state attached
     pub fn request(&mut self, req: reqwest::RequestBuilder) {
         self.queue_request(req, None)
@@ -282,9 +819,72 @@ where
             state,
             depth: self.current_depth + 1,
         };
-        if let Err(err) = self.list.add_request(req) {
-            self.queued_results
-                .push_back(CrawlResult::Crawled(Err(err.into())))
+        let mut req = match req.build() {
+            Ok(req) => req,
+            Err(err) => {
+                self.queued_results
+                    .push_back(CrawlResult::Crawled(Err(err.into())));
+                return;
+            }
+        };
+        if let Some(agent) = &self.user_agent {
+            req.request
+                .headers_mut()
+                .entry(reqwest::header::USER_AGENT)
+                .or_insert_with(|| agent.clone());
+        }
+
+        // the fingerprint is only recorded once the request was queued
+        let fingerprint = self.dedup.as_ref().map(|dedup| dedup.fingerprint(&req.request));
+        if let (Some(dedup), Some(fingerprint)) = (self.dedup.as_ref(), fingerprint) {
+            if dedup.seen().contains(&fingerprint) {
+                self.stats.duplicate_count = self.stats.duplicate_count.wrapping_add(1);
+                if dedup.policy() == DuplicatePolicy::Report {
+                    self.queued_results
+                        .push_back(CrawlResult::Crawled(Err(CrawlError::DuplicateRequest {
+                            request: req.request,
+                            state: req.state,
+                        }
+                        .into())));
+                }
+                return;
+            }
+        }
+
+        let record = match self.journal.as_mut().map(|journal| journal.prepare(&mut req)) {
+            Some(Ok(record)) => Some(record),
+            Some(Err(err)) => {
+                self.queued_results.push_back(CrawlResult::Crawled(Err(err)));
+                return;
+            }
+            None => None,
+        };
+
+        let host = self.canonicalize.url_host(req.request.url());
+        match self.list.add_request(req) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(err) => {
+                self.queued_results.push_back(CrawlResult::Crawled(Err(err)));
+                return;
+            }
+        }
+        if let Some(host) = host {
+            let stats = self.hosts.entry(host).or_default();
+            stats.request_count = stats.request_count.wrapping_add(1);
+        }
+        if let (Some(dedup), Some(fingerprint)) = (self.dedup.as_mut(), fingerprint) {
+            dedup.seen_mut().insert(fingerprint);
+            if let Some(journal) = self.journal.as_mut() {
+                if let Err(err) = journal.seen(fingerprint) {
+                    self.queued_results.push_back(CrawlResult::Crawled(Err(err)));
+                }
+            }
+        }
+        if let (Some(journal), Some((id, record))) = (self.journal.as_mut(), record) {
+            if let Err(err) = journal.queued(id, record) {
+                self.queued_results.push_back(CrawlResult::Crawled(Err(err)));
+            }
         }
     }
 
@@ -293,9 +893,21 @@ where
         &self.client
     }
 
+    /// Stats about the requests to each host, by canonical host
+    pub fn host_stats(&self) -> &BTreeMap<String, HostStats> {
+        &self.hosts
+    }
+
     /// advance all requests
     fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Option<CrawlResult<T>>> {
         loop {
+            // record all requests that finished since the last poll
+            if let Some(journal) = self.journal.as_mut() {
+                if let Err(err) = journal.sync() {
+                    self.queued_results.push_back(CrawlResult::Crawled(Err(err)));
+                }
+            }
+
             // drain all results
             if let Some(result) = self.queued_results.pop_front() {
                 return Poll::Ready(Some(result));
@@ -333,6 +945,31 @@ where
                 }
             }
 
+            // queue the urls of all downloaded sitemaps before the domain listing
+            // is polled
+            let mut sitemaps = Vec::new();
+            for n in (0..self.in_progress_sitemaps.len()).rev() {
+                let mut request = self.in_progress_sitemaps.swap_remove(n);
+                if let Poll::Ready(sitemap) = request.poll_unpin(cx) {
+                    sitemaps.push(sitemap);
+                } else {
+                    self.in_progress_sitemaps.push(request);
+                }
+            }
+            let in_progress = self.in_progress_sitemaps.len();
+            for (job, found) in sitemaps {
+                match found {
+                    Ok(found) => self.handle_sitemap(job, found),
+                    Err(err) => self.queued_results.push_back(CrawlResult::Crawled(Err(
+                        err.context(format!("Failed to fetch sitemap {}", job.url)),
+                    ))),
+                }
+            }
+            if self.in_progress_sitemaps.len() > in_progress {
+                // nested sitemaps need to be polled once to register the waker
+                cx.waker().wake_by_ref();
+            }
+
             let mut busy = false;
             loop {
                 match Stream::poll_next(Pin::new(&mut self.list), cx) {
@@ -346,6 +983,7 @@ where
                     _ => break,
                 }
             }
+            self.list.report_throttles(&mut self.stats.throttles);
 
             // If no new results have been queued either, signal `NotReady` or `Done` if all
             // queues are drained
@@ -353,6 +991,7 @@ where
                 if !busy
                     && self.in_progress_crawl_requests.is_empty()
                     && self.in_progress_complete_requests.is_empty()
+                    && self.in_progress_sitemaps.is_empty()
                 {
                     return Poll::Ready(None);
                 }
@@ -363,6 +1002,7 @@ where
 }
 
 /// The result type a `Crawler` produces
+#[allow(clippy::large_enum_variant)]
 enum CrawlResult<T: Scraper> {
     /// A submitted request to produce the `Scraper::Output` type has finished
     Finished(Result<T::Output>),
@@ -396,12 +1036,25 @@ pub trait Scraper: Sized {
 }
 
 /// Stats about sent requests and received responses
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Stats {
     /// number of sent requests
     pub request_count: usize,
     /// number of received successful responses
     pub response_count: usize,
+    /// number of requests that were dropped as duplicates
+    pub duplicate_count: usize,
+    /// The current delay and concurrency of every domain with a
+    /// `RequestDelay::Adaptive`, by the domain of the allow list or the host
+    #[serde(default)]
+    pub throttles: BTreeMap<String, ThrottleSettings>,
+}
+
+/// Stats about the requests to a single host
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct HostStats {
+    /// number of queued requests
+    pub request_count: usize,
 }
 
 /// Configure a `Collector` and its `Crawler`
@@ -412,20 +1065,57 @@ pub struct CrawlerConfig {
     ///
     /// Default is `MAX_CONCURRENT_REQUESTS`
     max_requests: Option<usize>,
+    /// Limits the requests to each host, unlimited by default
+    max_requests_per_host: Option<usize>,
+    /// Limits of the hosts that match a pattern, instead of
+    /// `max_requests_per_host`
+    max_requests_per_domain: Vec<(DomainPattern, usize)>,
+    /// Limits the requests to each ip address, unlimited by default
+    max_requests_per_ip: Option<usize>,
     /// Whether to ignore responses with a non 2xx response code see
     /// `reqwest::Response::is_success`
     skip_non_successful_responses: bool,
     /// Domain whitelist, if empty any domains are allowed to visit
-    allowed_domains: HashMap<String, Option<RequestDelay>>,
+    allowed_domains: Vec<(DomainPattern, Option<RequestDelay>)>,
     /// Domain blacklist
-    disallowed_domains: HashSet<String>,
+    disallowed_domains: Vec<DomainPattern>,
+    /// The delay between requests to each host, if no domains are allowed
+    host_delay: Option<RequestDelay>,
+    /// The public suffix list used to match `DomainPattern::Registrable`
+    public_suffixes: Arc<PublicSuffixList>,
     /// respects the any restrictions set by the target host's
     /// robots.txt file. See <http://www.robotstxt.org/>` for more information.
     respect_robots_txt: bool,
+    /// Whether the `Crawl-delay` of a host's robots.txt raises the configured
+    /// delay of its request queue. Only applies if `respect_robots_txt` is set.
+    respect_robots_crawl_delay: bool,
+    /// Drops requests that were already queued, disabled by default
+    dedup: Option<Dedup>,
+    /// How urls and hosts are canonicalized for domain matching and
+    /// deduplication
+    canonicalize: CanonicalizeRules,
+    /// Retry requests that failed for transient reasons, disabled by default
+    retry: Option<Arc<RetryPolicy>>,
+    /// How response bodies are read, unlimited by default
+    body: BodyLimits,
+    /// A cache of robots.txt files to share with other crawlers
+    robots_cache: Option<RobotsCache>,
+    /// How robots.txt files are fetched and what is assumed if they are
+    /// unreachable
+    robots_policy: Option<RobotsPolicy>,
+    /// The identity sent as `User-Agent` header, with its header value
+    user_agent: Option<(UserAgent, reqwest::header::HeaderValue)>,
+    /// The product token that selects the robots.txt rules, defaults to the
+    /// product of the `user_agent`, with its header value
+    robots_agent: Option<(String, reqwest::header::HeaderValue)>,
     // /// Delay a request
     // request_delay: Option<RequestDelay>,
     /// The client that will be used to send the requests
     client: Option<reqwest::Client>,
+    /// Sends the requests instead of the client
+    fetcher: Option<Arc<dyn Fetcher>>,
+    /// Fetchers that send the requests for some domains instead of the client
+    fetchers: Vec<(DomainPattern, Arc<dyn Fetcher>)>,
 }
 
 impl Default for CrawlerConfig {
@@ -433,11 +1123,27 @@ impl Default for CrawlerConfig {
         Self {
             max_depth: None,
             max_requests: None,
+            max_requests_per_host: None,
+            max_requests_per_domain: Vec::new(),
+            max_requests_per_ip: None,
             skip_non_successful_responses: true,
             allowed_domains: Default::default(),
             disallowed_domains: Default::default(),
+            host_delay: None,
+            public_suffixes: pattern::default_suffixes(),
             respect_robots_txt: false,
+            respect_robots_crawl_delay: true,
+            dedup: None,
+            canonicalize: Default::default(),
+            retry: None,
+            body: Default::default(),
+            robots_cache: None,
+            robots_policy: None,
+            user_agent: None,
+            robots_agent: None,
             client: None,
+            fetcher: None,
+            fetchers: Vec::new(),
         }
     }
 }
@@ -455,16 +1161,138 @@ impl CrawlerConfig {
         self
     }
 
+    /// Don't apply the `Crawl-delay` of a host's robots.txt, only use the
+    /// configured `RequestDelay`
+    pub fn ignore_robots_crawl_delay(mut self) -> Self {
+        self.respect_robots_crawl_delay = false;
+        self
+    }
+
+    /// Use the `cache` for robots.txt files, for example to share them with
+    /// another crawler or to change the TTL after which they are fetched again
+    pub fn robots_cache(mut self, cache: RobotsCache) -> Self {
+        self.robots_cache = Some(cache);
+        self
+    }
+
+    /// Fetch robots.txt files according to the `policy`, which decides how
+    /// unreachable robots.txt files are retried and what is assumed until
+    /// they can be fetched
+    pub fn robots_policy(mut self, policy: RobotsPolicy) -> Self {
+        self.robots_policy = Some(policy);
+        self
+    }
+
+    /// Obey the robots.txt rules for the product token `agent`, like
+    /// `ourbot`, instead of the `User-Agent` header of each request.
+    ///
+    /// Unless a `user_agent` is set, `agent` is also sent as `User-Agent`, an
+    /// `agent` that is not a valid header value is an error.
+    pub fn robots_user_agent(
+        mut self,
+        agent: impl Into<String>,
+    ) -> std::result::Result<Self, InvalidHeaderValue> {
+        let agent = agent.into();
+        let header = UserAgent::new(agent.as_str()).header_value()?;
+        self.robots_agent = Some((agent, header));
+        Ok(self)
+    }
+
+    /// Send the `agent` as `User-Agent` header of all requests that don't set
+    /// one, and obey the robots.txt rules for its product token unless
+    /// `robots_user_agent` is set.
+    ///
+    /// The header is set on the default client, and on each queued request,
+    /// robots.txt and sitemap request if a custom client is used. An `agent`
+    /// that is not a valid header value is an error.
+    pub fn user_agent(
+        mut self,
+        agent: UserAgent,
+    ) -> std::result::Result<Self, InvalidHeaderValue> {
+        let header = agent.header_value()?;
+        self.user_agent = Some((agent, header));
+        Ok(self)
+    }
+
     pub fn scrape_non_success_response(mut self) -> Self {
         self.skip_non_successful_responses = false;
         self
     }
 
+    /// Drop requests for pages that were already queued, using an in-memory
+    /// set of request fingerprints
+    pub fn deduplicate_requests(self) -> Self {
+        self.deduplicate(Dedup::default())
+    }
+
+    /// Drop requests for pages that were already queued, using the `dedup`
+    /// layer, for example with a custom `SeenSet`
+    pub fn deduplicate(mut self, dedup: Dedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Retry requests that failed with a transient error, like a `503` or a
+    /// reset connection, according to the `policy`.
+    ///
+    /// Requests that still fail after the last attempt are reported as
+    /// `CrawlError::RetriesExhausted`.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(Arc::new(policy));
+        self
+    }
+
+    /// Reject responses whose body is larger than `max_size` bytes with
+    /// `CrawlError::BodyTooLarge`.
+    ///
+    /// Streamed bodies are only checked against their `Content-Length`.
+    pub fn max_body_size(mut self, max_size: usize) -> Self {
+        self.body.max_size = Some(max_size);
+        self
+    }
+
+    /// Don't buffer bodies above `size` bytes, like large downloads, but hand
+    /// them to the scraper as stream, see `Response::take_body_stream`. A body
+    /// is streamed if its `Content-Length` is above `size`, or once more than
+    /// `size` bytes of it were read.
+    pub fn stream_bodies_over(mut self, size: usize) -> Self {
+        self.body.stream_over = Some(size);
+        self
+    }
+
+    /// Set the rules that canonicalize urls and hosts before they are matched
+    /// against the allowed and disallowed domains and fingerprinted for
+    /// deduplication
+    pub fn canonicalize(mut self, rules: CanonicalizeRules) -> Self {
+        self.canonicalize = rules;
+        self
+    }
+
     pub fn set_client(mut self, client: reqwest::Client) -> Self {
         self.client = Some(client);
         self
     }
 
+    /// Send all requests, including those for robots.txt files, sitemaps and
+    /// the futures of `Crawler::crawl_with_fetchers`, with the `fetcher`
+    /// instead of the client, like a `MockFetcher` in tests.
+    pub fn set_fetcher(mut self, fetcher: impl Fetcher + 'static) -> Self {
+        self.fetcher = Some(Arc::new(fetcher));
+        self
+    }
+
+    /// Send the requests for domains that match the `pattern` with the
+    /// `fetcher`, like a `WebDriverFetcher` for pages that are rendered with
+    /// javascript, instead of the client.
+    ///
+    /// The first matching pattern wins, see `DomainPattern::parse` for the
+    /// supported patterns.
+    pub fn fetch_with(mut self, pattern: impl Into<String>, fetcher: impl Fetcher + 'static) -> Self {
+        self.fetchers
+            .push((DomainPattern::from(pattern.into()), Arc::new(fetcher)));
+        self
+    }
+
     /// *NOTE* [`reqwest::Client`] already uses Arc under the hood, so
     /// it's preferable to just `clone` it and pass via [`Self::set_client`]
     #[deprecated(
@@ -476,9 +1304,9 @@ impl CrawlerConfig {
         self
     }
 
-    pub fn disallow_domain(mut self, domain: impl Into<String>) -> Self {
-        self.disallowed_domains.insert(domain.into());
-        self
+    /// Block the domain, see `DomainPattern::parse` for the supported patterns
+    pub fn disallow_domain(self, domain: impl Into<String>) -> Self {
+        self.disallow_pattern(DomainPattern::from(domain.into()))
     }
 
     pub fn disallow_domains<I, T>(mut self, domains: I) -> Self
@@ -487,32 +1315,45 @@ impl CrawlerConfig {
         T: Into<String>,
     {
         for domain in domains.into_iter() {
-            self.disallowed_domains.insert(domain.into());
+            self = self.disallow_domain(domain);
         }
         self
     }
 
-    pub fn allow_domain_with_delay(
-        mut self,
-        domain: impl Into<String>,
-        delay: RequestDelay,
-    ) -> Self {
-        self.allowed_domains.insert(domain.into(), Some(delay));
+    /// Block all urls that match the `pattern`
+    pub fn disallow_pattern(mut self, pattern: DomainPattern) -> Self {
+        self.disallowed_domains.push(pattern);
         self
     }
 
-    pub fn allow_domain(mut self, domain: impl Into<String>) -> Self {
-        self.allowed_domains.insert(domain.into(), None);
+    /// Delay the requests to each host that is not blocked, if no domains are
+    /// allowed. Every host has its own queue, so the delay doesn't slow down
+    /// the requests to other hosts.
+    ///
+    /// Allowed domains have their own delay, see `allow_domain_with_delay`.
+    pub fn host_delay(mut self, delay: RequestDelay) -> Self {
+        self.host_delay = Some(delay);
         self
     }
 
+    /// Allow the domain with a delay between its requests, see
+    /// `DomainPattern::parse` for the supported patterns
+    pub fn allow_domain_with_delay(self, domain: impl Into<String>, delay: RequestDelay) -> Self {
+        self.allow_pattern_with_delay(DomainPattern::from(domain.into()), delay)
+    }
+
+    /// Allow the domain, see `DomainPattern::parse` for the supported patterns
+    pub fn allow_domain(self, domain: impl Into<String>) -> Self {
+        self.insert_allowed(DomainPattern::from(domain.into()), None)
+    }
+
     pub fn allow_domains<I, T>(mut self, domains: I) -> Self
     where
         I: IntoIterator<Item = T>,
         T: Into<String>,
     {
         for domain in domains.into_iter() {
-            self.allowed_domains.insert(domain.into(), None);
+            self = self.allow_domain(domain);
         }
         self
     }
@@ -523,13 +1364,667 @@ impl CrawlerConfig {
         T: Into<String>,
     {
         for (domain, delay) in domains.into_iter() {
-            self.allowed_domains.insert(domain.into(), Some(delay));
+            self = self.allow_domain_with_delay(domain, delay);
         }
         self
     }
 
+    /// Allow all urls that match the `pattern`.
+    ///
+    /// All matching hosts share the same request queue and concurrency limit.
+    pub fn allow_pattern(self, pattern: DomainPattern) -> Self {
+        self.insert_allowed(pattern, None)
+    }
+
+    /// Allow all urls that match the `pattern` with a delay between their
+    /// requests
+    pub fn allow_pattern_with_delay(self, pattern: DomainPattern, delay: RequestDelay) -> Self {
+        self.insert_allowed(pattern, Some(delay))
+    }
+
+    fn insert_allowed(mut self, pattern: DomainPattern, delay: Option<RequestDelay>) -> Self {
+        let key = pattern.to_string();
+        self.allowed_domains.retain(|(p, _)| p.to_string() != key);
+        self.allowed_domains.push((pattern, delay));
+        self
+    }
+
+    /// Use the `list` to determine registrable domains for
+    /// `DomainPattern::Registrable`.
+    ///
+    /// By default the list that is bundled with the crate is used.
+    pub fn public_suffix_list(mut self, list: PublicSuffixList) -> Self {
+        self.public_suffixes = Arc::new(list);
+        self
+    }
+
     pub fn max_concurrent_requests(mut self, max_requests: usize) -> Self {
         self.max_requests = Some(max_requests);
         self
     }
+
+    /// Limit the concurrent requests to each host, in addition to
+    /// `max_concurrent_requests`
+    pub fn max_requests_per_host(mut self, max_requests: usize) -> Self {
+        self.max_requests_per_host = Some(max_requests);
+        self
+    }
+
+    /// Limit the concurrent requests to each host that matches the `pattern`,
+    /// instead of `max_requests_per_host`.
+    ///
+    /// The first matching pattern wins, see `DomainPattern::parse` for the
+    /// supported patterns.
+    pub fn max_requests_per_domain(mut self, pattern: impl Into<String>, max_requests: usize) -> Self {
+        self.max_requests_per_domain
+            .push((DomainPattern::from(pattern.into()), max_requests));
+        self
+    }
+
+    /// Limit the concurrent requests to each ip address, hosts are resolved
+    /// with `Fetcher::resolve` before their first request
+    pub fn max_requests_per_ip(mut self, max_requests: usize) -> Self {
+        self.max_requests_per_ip = Some(max_requests);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DisallowReason;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    /// Scrapes the `<h1>` of every page
+    struct Titles;
+
+    impl Scraper for Titles {
+        type Output = String;
+        type State = ();
+
+        fn scrape(
+            &mut self,
+            response: Response<Self::State>,
+            _: &mut Crawler<Self>,
+        ) -> Result<Option<Self::Output>> {
+            Ok(Some(response.parse().select_text("h1")?))
+        }
+    }
+
+    fn page(title: &str, links: &[&str]) -> String {
+        let links: String = links
+            .iter()
+            .map(|link| format!("<a href=\"{}\">{}</a>", link, link))
+            .collect();
+        format!("<html><body><h1>{}</h1>{}</body></html>", title, links)
+    }
+
+    async fn crawl(collector: Collector<Titles>) -> (Vec<String>, Vec<anyhow::Error>) {
+        let (mut titles, mut errors) = (Vec::new(), Vec::new());
+        collector
+            .for_each(|result| {
+                match result {
+                    Ok(title) => titles.push(title),
+                    Err(err) => errors.push(err),
+                }
+                futures::future::ready(())
+            })
+            .await;
+        titles.sort();
+        (titles, errors)
+    }
+
+    #[tokio::test]
+    async fn crawl_follows_links() {
+        let fetcher = MockFetcher::new()
+            .html("https://example.com/", page("home", &["/a", "/b"]))
+            .html("https://example.com/a", page("a", &["/", "/b"]))
+            .html("https://example.com/b", page("b", &["/a", "https://other.com/"]))
+            .html("https://other.com/", page("other", &[]));
+        let config = CrawlerConfig::default()
+            .allow_domain("example.com")
+            .deduplicate_requests()
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()));
+        collector.crawler_mut().visit("https://example.com/");
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["a", "b", "home"]);
+        // other.com is not allowed
+        assert_eq!(errors.len(), 1);
+        assert_eq!(fetcher.requests().len(), 3);
+        assert_eq!(fetcher.request_count("https://example.com/a"), 1);
+    }
+
+    #[tokio::test]
+    async fn dedup_skips_rejected_requests() {
+        let fetcher = MockFetcher::new()
+            .html("https://example.com/", page("home", &["/a"]))
+            .html("https://example.com/a", page("a", &["/deep"]))
+            .html("https://example.com/deep", page("deep", &[]));
+        let config = CrawlerConfig::default()
+            .max_depth(1)
+            .deduplicate_requests()
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()));
+        collector.crawler_mut().visit("https://example.com/");
+        let mut errors = 0;
+        while let Some(result) = collector.next().await {
+            errors += result.is_err() as usize;
+        }
+        assert_eq!(errors, 1);
+
+        // /deep was too deep the first time and is not a duplicate now
+        collector.crawler_mut().visit("https://example.com/deep");
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["deep"]);
+        assert!(errors.is_empty());
+        assert_eq!(fetcher.request_count("https://example.com/deep"), 1);
+    }
+
+    #[test]
+    fn count_requests_by_canonical_host() {
+        let mut collector = Collector::new(Titles, CrawlerConfig::default());
+        collector.crawler_mut().visit("https://Example.com:443/a#top");
+        collector.crawler_mut().visit("https://example.com/b");
+        collector.crawler_mut().visit("https://www.example.com/");
+        let hosts = collector.host_stats();
+        assert_eq!(hosts["example.com"].request_count, 2);
+        assert_eq!(hosts["www.example.com"].request_count, 1);
+
+        // requests that are not queued are not counted
+        let config = CrawlerConfig::default().allow_domain("example.com");
+        let mut collector = Collector::new(Titles, config);
+        collector.crawler_mut().visit("https://example.com/");
+        collector.crawler_mut().visit("https://other.com/");
+        let hosts = collector.host_stats();
+        assert_eq!(hosts["example.com"].request_count, 1);
+        assert!(!hosts.contains_key("other.com"));
+    }
+
+    #[tokio::test]
+    async fn send_the_user_agent_with_a_custom_client() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        assert!(CrawlerConfig::default()
+            .user_agent(UserAgent::new("bot\n"))
+            .is_err());
+        assert!(CrawlerConfig::default().robots_user_agent("bot\r").is_err());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut heads = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let len = stream.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..len]).to_lowercase();
+                let body = if head.starts_with("get /robots.txt") {
+                    "User-agent: *\nAllow: /\n".to_string()
+                } else {
+                    page("home", &[])
+                };
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(resp.as_bytes()).await.unwrap();
+                heads.push(head);
+            }
+            heads
+        });
+
+        let config = CrawlerConfig::default()
+            .set_client(reqwest::Client::new())
+            .user_agent(UserAgent::new("TestBot/1.0"))
+            .unwrap()
+            .respect_robots_txt();
+        let mut collector = Collector::new(Titles, config);
+        collector.crawler_mut().visit(format!("http://{}/", addr));
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["home"]);
+        assert!(errors.is_empty());
+
+        let heads = server.await.unwrap();
+        assert!(heads[0].starts_with("get /robots.txt"));
+        for head in heads {
+            assert!(head.contains("user-agent: testbot/1.0\r\n"), "{}", head);
+        }
+    }
+
+    #[tokio::test]
+    async fn hold_requests_while_robots_txt_is_unreachable() {
+        let fetcher = MockFetcher::new()
+            .on("https://example.com/robots.txt", MockResponse::error("broken body"))
+            .on("https://example.com/robots.txt", MockResponse::new(200))
+            .html("https://example.com/", page("home", &[]));
+        let policy = RobotsPolicy::default().unreachable_ttl(Duration::from_millis(50));
+        let config = CrawlerConfig::default()
+            .respect_robots_txt()
+            .robots_policy(policy)
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config);
+        collector.crawler_mut().visit("https://example.com/");
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["home"]);
+        // the failed robots.txt is reported, but not cached as allow all
+        assert_eq!(errors.len(), 1);
+        assert_eq!(fetcher.request_count("https://example.com/robots.txt"), 2);
+    }
+
+    #[tokio::test]
+    async fn sitemaps_and_crawl_use_the_fetcher() {
+        let fetcher = MockFetcher::new()
+            .on(
+                "https://example.com/sitemap.xml",
+                MockResponse::new(200).body("https://example.com/a\n"),
+            )
+            .on("https://example.com/a", MockResponse::failure(FetchErrorKind::Reset))
+            .html("https://example.com/a", page("a", &[]))
+            .html("https://example.com/b", page("b", &[]))
+            .on("https://example.com/c", MockResponse::new(200).body("c"));
+        let retry = RetryPolicy::new(2).backoff(Duration::ZERO, Duration::ZERO);
+        let config = CrawlerConfig::default()
+            .allow_domain("example.com")
+            .retry(retry)
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config);
+        collector
+            .crawler_mut()
+            .visit_sitemap("https://example.com/sitemap.xml", |_| None);
+        collector.crawler_mut().crawl_with_fetchers(|fetchers| {
+            let url = "https://example.com/b".parse().unwrap();
+            let resp = fetchers.fetch(reqwest::Request::new(reqwest::Method::GET, url));
+            async move { Ok((resp.await?, None)) }
+        });
+        collector.crawler_mut().complete_with_fetchers(|fetchers| {
+            let url = "https://example.com/c".parse().unwrap();
+            let resp = fetchers.fetch(reqwest::Request::new(reqwest::Method::GET, url));
+            async move { Ok(Some(resp.await?.text().await?)) }
+        });
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["a", "b", "c"]);
+        assert!(errors.is_empty());
+        // the mocked reset was retried
+        assert_eq!(fetcher.request_count("https://example.com/a"), 2);
+    }
+
+    #[tokio::test]
+    async fn sitemaps_obey_the_domain_lists() {
+        let config = CrawlerConfig::default().allow_domain("example.com");
+        let mut collector = Collector::new(Titles, config);
+        collector
+            .crawler_mut()
+            .visit_sitemap("https://other.com/sitemap.xml", |_| None);
+        collector
+            .crawler_mut()
+            .discover_sitemaps("https://other.com/", |_| None);
+
+        let (titles, errors) = crawl(collector).await;
+        assert!(titles.is_empty());
+        assert_eq!(errors.len(), 2);
+        for err in errors {
+            assert!(matches!(
+                err.downcast_ref::<error::SitemapError>(),
+                Some(error::SitemapError::Disallowed(_, DisallowReason::UserConfig))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn crawl_respects_robots_txt() {
+        let fetcher = MockFetcher::new()
+            .on(
+                "https://example.com/robots.txt",
+                MockResponse::new(200).body("User-agent: *\nDisallow: /private\n"),
+            )
+            .html("https://example.com/", page("home", &["/private", "/public"]))
+            .html("https://example.com/public", page("public", &[]))
+            .html("https://example.com/private", page("private", &[]));
+        let config = CrawlerConfig::default()
+            .respect_robots_txt()
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()));
+        collector.crawler_mut().visit("https://example.com/");
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["home", "public"]);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0].downcast_ref::<CrawlError<()>>(),
+            Some(CrawlError::DisallowedRequest {
+                reason: DisallowReason::RobotsTxt,
+                ..
+            })
+        ));
+        assert_eq!(fetcher.request_count("https://example.com/robots.txt"), 1);
+        assert_eq!(fetcher.request_count("https://example.com/private"), 0);
+    }
+
+    #[tokio::test]
+    async fn crawl_retries_transient_errors() {
+        let fetcher = MockFetcher::new()
+            .on("https://example.com/", MockResponse::new(503))
+            .on(
+                "https://example.com/",
+                MockResponse::html(page("home", &[])).latency(Duration::from_millis(5)),
+            )
+            .on("https://example.com/gone", MockResponse::new(503));
+        let config = CrawlerConfig::default()
+            .retry(
+                RetryPolicy::new(3)
+                    .backoff(Duration::from_millis(1), Duration::from_millis(1))
+                    .without_jitter(),
+            )
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config);
+        collector.crawler_mut().visit("https://example.com/");
+        collector.crawler_mut().visit("https://example.com/gone");
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["home"]);
+        assert_eq!(fetcher.request_count("https://example.com/"), 2);
+        assert_eq!(fetcher.request_count("https://example.com/gone"), 3);
+        assert!(matches!(
+            errors[0].downcast_ref::<CrawlError<()>>(),
+            Some(CrawlError::RetriesExhausted { attempts, .. }) if attempts.len() == 3
+        ));
+    }
+
+    #[tokio::test]
+    async fn crawl_limits_requests_per_host() {
+        let slow = MockResponse::html(page("slow", &[])).latency(Duration::from_millis(50));
+        let fetcher = MockFetcher::new()
+            .on("https://slow.com/1", slow.clone())
+            .on("https://slow.com/2", slow.clone())
+            .on("https://slow.com/3", slow)
+            .html("https://fast.com/1", page("fast", &[]))
+            .html("https://fast.com/2", page("fast", &[]));
+        let config = CrawlerConfig::default()
+            .max_concurrent_requests(2)
+            .max_requests_per_host(1)
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config);
+        for url in ["/1", "/2", "/3"] {
+            collector.crawler_mut().visit(format!("https://slow.com{}", url));
+        }
+        collector.crawler_mut().visit("https://fast.com/1");
+        collector.crawler_mut().visit("https://fast.com/2");
+
+        // the queued requests to slow.com don't hold back fast.com
+        let titles: Vec<_> = collector.map(Result::unwrap).collect().await;
+        assert_eq!(titles, ["fast", "fast", "slow", "slow", "slow"]);
+    }
+
+    #[tokio::test]
+    async fn crawl_limits_requests_per_ip() {
+        let slow = MockResponse::html(page("slow", &[])).latency(Duration::from_millis(50));
+        let fetcher = MockFetcher::new()
+            .on("https://a.com/", slow.clone())
+            .on("https://b.com/", slow)
+            .html("https://c.com/", page("fast", &[]))
+            .address("a.com", [10, 0, 0, 1].into())
+            .address("b.com", [10, 0, 0, 1].into())
+            .address("c.com", [10, 0, 0, 2].into());
+        let config = CrawlerConfig::default()
+            .max_requests_per_ip(1)
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config);
+        for url in ["https://a.com/", "https://b.com/", "https://c.com/"] {
+            collector.crawler_mut().visit(url);
+        }
+
+        // a.com and b.com share an address, c.com is resolved by the fetcher
+        // and isn't held back
+        let start = std::time::Instant::now();
+        let titles: Vec<_> = collector.map(Result::unwrap).collect().await;
+        assert_eq!(titles, ["fast", "slow", "slow"]);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn crawl_delays_each_host() {
+        let fetcher = MockFetcher::new()
+            .html("https://a.com/", page("a", &["/1"]))
+            .html("https://a.com/1", page("a1", &[]))
+            .html("https://b.com/", page("b", &["/1"]))
+            .html("https://b.com/1", page("b1", &[]));
+        let config = CrawlerConfig::default()
+            .host_delay(RequestDelay::fixed(Duration::from_millis(100)))
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()));
+        collector.crawler_mut().visit("https://a.com/");
+        collector.crawler_mut().visit("https://b.com/");
+
+        let start = std::time::Instant::now();
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["a", "a1", "b", "b1"]);
+        assert!(errors.is_empty());
+        // the second request to each host waits for its delay
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn crawl_drops_idle_hosts() {
+        let fetcher = MockFetcher::new()
+            .html("https://a.com/", page("a", &["/1"]))
+            .html("https://a.com/1", page("a1", &[]))
+            .html("https://b.com/", page("b", &[]));
+        let throttle = AutoThrottle::new().bounds(Duration::ZERO, Duration::ZERO);
+        let config = CrawlerConfig::default()
+            .host_delay(RequestDelay::adaptive(throttle))
+            .set_fetcher(fetcher);
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()));
+        collector.crawler_mut().visit("https://a.com/");
+        collector.crawler_mut().visit("https://b.com/");
+
+        let mut titles = Vec::new();
+        while let Some(title) = collector.next().await {
+            titles.push(title.unwrap());
+        }
+        titles.sort();
+        assert_eq!(titles, ["a", "a1", "b"]);
+        match &collector.crawler().list {
+            DomainListing::BlockList(list) => {
+                assert!(list.get_host("a.com").is_none());
+                assert!(list.get_host("b.com").is_none());
+            }
+            DomainListing::AllowList(_) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn crawl_keeps_the_throttle_of_dropped_hosts() {
+        let fetcher = MockFetcher::new()
+            .on("https://a.com/", MockResponse::new(429))
+            .on("https://a.com/1", MockResponse::new(404))
+            .on("https://a.com/2", MockResponse::new(404));
+        let throttle = AutoThrottle::new()
+            .start_delay(Duration::ZERO)
+            .bounds(Duration::ZERO, Duration::from_millis(50));
+        let config = CrawlerConfig::default()
+            .host_delay(RequestDelay::adaptive(throttle))
+            .set_fetcher(fetcher);
+        let mut collector = Collector::new(Titles, config);
+        collector.crawler_mut().visit("https://a.com/");
+        while collector.next().await.is_some() {}
+        // a.com is dropped once its delay is over
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(collector.next().await.is_none());
+        match &collector.crawler().list {
+            DomainListing::BlockList(list) => assert!(list.get_host("a.com").is_none()),
+            DomainListing::AllowList(_) => unreachable!(),
+        }
+
+        // the 429 raised the delay, which still applies once a.com is back
+        collector.crawler_mut().visit("https://a.com/1");
+        collector.crawler_mut().visit("https://a.com/2");
+        let start = std::time::Instant::now();
+        while collector.next().await.is_some() {}
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(50), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn crawl_reports_throttle_settings() {
+        let fetcher = MockFetcher::new()
+            .html("https://example.com/", page("home", &["/busy"]))
+            .on("https://example.com/busy", MockResponse::new(429));
+        let throttle = AutoThrottle::new()
+            .start_delay(Duration::ZERO)
+            .bounds(Duration::ZERO, Duration::from_millis(50));
+        let config = CrawlerConfig::default()
+            .host_delay(RequestDelay::adaptive(throttle))
+            .set_fetcher(fetcher);
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()));
+        collector.crawler_mut().visit("https://example.com/");
+        let mut settings = None;
+        while collector.next().await.is_some() {
+            settings = collector.stats().throttles.get("example.com").copied();
+        }
+
+        // the fast response raised the concurrency, the 429 backed off again
+        let settings = settings.unwrap();
+        assert_eq!(settings.delay, Duration::from_millis(50));
+        assert_eq!(settings.concurrency, 1);
+        assert!(settings.latency.is_some());
+    }
+
+    #[tokio::test]
+    async fn crawl_runs_middlewares() {
+        /// Drops the errors of disallowed requests
+        struct IgnoreDisallowed;
+
+        impl Middleware<()> for IgnoreDisallowed {
+            fn on_error(&mut self, error: &anyhow::Error) -> Action {
+                match error.downcast_ref::<CrawlError<()>>() {
+                    Some(CrawlError::DisallowedRequest { .. }) => Action::Drop,
+                    _ => Action::Continue,
+                }
+            }
+        }
+
+        let fetcher = MockFetcher::new()
+            .html("https://example.com/", page("home", &["/data", "https://other.com/"]))
+            .on(
+                "https://example.com/data",
+                MockResponse::new(200)
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        reqwest::header::HeaderValue::from_static("application/json"),
+                    )
+                    .body("{}"),
+            );
+        let config = CrawlerConfig::default()
+            .allow_domain("example.com")
+            .set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()))
+            .middleware(middleware::ContentTypeFilter::html())
+            .middleware(IgnoreDisallowed);
+        collector.crawler_mut().visit("https://example.com/");
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["home"]);
+        assert!(errors.is_empty());
+        assert_eq!(fetcher.request_count("https://example.com/data"), 1);
+    }
+
+    #[test]
+    fn journal_skips_dropped_requests() {
+        /// Drops the requests to `/drop`
+        struct DropPath;
+
+        impl Middleware<()> for DropPath {
+            fn on_request(&mut self, request: &mut QueuedRequest<()>) -> Action {
+                if request.request.url().path() == "/drop" {
+                    Action::Drop
+                } else {
+                    Action::Continue
+                }
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "rust-crawler-dropped-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut collector = Collector::resume_from(Titles, CrawlerConfig::default(), &path)
+            .unwrap()
+            .middleware(DropPath);
+        collector.crawler_mut().visit("https://example.com/");
+        collector.crawler_mut().visit("https://example.com/drop");
+        drop(collector);
+
+        let (_, restored) = frontier::Journal::<()>::open(&path).unwrap();
+        assert_eq!(restored.requests.len(), 1);
+        assert_eq!(restored.requests[0].request.url().path(), "/");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn crawl_pipes_outputs() {
+        let fetcher = MockFetcher::new()
+            .html("https://example.com/", page("home", &["/a", "/b", "/c"]))
+            .html("https://example.com/a", page("same", &[]))
+            .html("https://example.com/b", page("same", &[]))
+            .html("https://example.com/c", page("", &[]));
+        let config = CrawlerConfig::default().set_fetcher(fetcher);
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()))
+            .pipe(pipeline::Validate::new().check("title", |title: &String| !title.is_empty()))
+            .pipe(pipeline::DedupBy::new(String::clone));
+        collector.crawler_mut().visit("https://example.com/");
+
+        let (titles, errors) = crawl(collector).await;
+        assert_eq!(titles, ["home", "same"]);
+        assert!(errors.is_empty());
+    }
+
+    /// Holds the first item until the page it links to was fetched
+    struct WaitFor(MockFetcher, &'static str);
+
+    impl pipeline::ItemPipeline<String> for WaitFor {
+        fn process_item(&mut self, item: String) -> pipeline::ItemFuture<String> {
+            let (fetcher, url) = (self.0.clone(), self.1);
+            Box::pin(async move {
+                for _ in 0..100 {
+                    if fetcher.request_count(url) > 0 {
+                        return Ok(Some(item));
+                    }
+                    futures_timer::Delay::new(Duration::from_millis(10)).await;
+                }
+                anyhow::bail!("{} was not fetched while {} is pending", url, item)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn crawl_while_a_stage_is_pending() {
+        let fetcher = MockFetcher::new()
+            .html("https://example.com/", page("home", &["/b"]))
+            .html("https://example.com/b", page("b", &[]));
+        let config = CrawlerConfig::default().set_fetcher(fetcher.clone());
+        let mut collector = Collector::new(Titles, config)
+            .follow(FollowRule::new(LinkExtractor::new()))
+            .pipe(WaitFor(fetcher, "https://example.com/b"));
+        collector.crawler_mut().visit("https://example.com/");
+
+        let (titles, errors) = crawl(collector).await;
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(titles, ["b", "home"]);
+    }
 }
@@ -0,0 +1,214 @@
+//! Limits on the number of requests that are in flight at the same time.
+//!
+//! A request is only sent once it holds a permit of the global limit, the
+//! limit of its host and the limit of the ip address the host resolves to.
+//! Hosts are resolved with the `Fetcher` of the request before any permit is
+//! taken.
+//! The limits are shared by all domains of a crawler, so a slow host only
+//! holds its own permits and can't starve the other hosts.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Url;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::pattern::{default_suffixes, DomainPattern, PublicSuffixList};
+
+/// The global, per host and per ip limits of concurrent requests
+#[derive(Debug)]
+pub struct ConcurrencyLimits {
+    /// Shared by all requests
+    global: Arc<Semaphore>,
+    /// The limit of every host, unlimited if `None`
+    per_host: Option<usize>,
+    /// Limits of hosts that match a pattern, instead of `per_host`
+    domains: Vec<(DomainPattern, usize)>,
+    /// The limit of every ip address, unlimited if `None`
+    per_ip: Option<usize>,
+    /// The public suffix list to match registrable domains
+    suffixes: Arc<PublicSuffixList>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    ips: Mutex<HashMap<IpAddr, Arc<Semaphore>>>,
+    /// The address each host resolved to, `None` if it couldn't be resolved
+    addrs: Mutex<HashMap<String, Option<IpAddr>>>,
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        Self::new(Semaphore::MAX_PERMITS)
+    }
+}
+
+impl ConcurrencyLimits {
+    /// Allow at most `max_requests` requests at the same time, to any host
+    pub fn new(max_requests: usize) -> Self {
+        Self {
+            global: Arc::new(Semaphore::new(
+                max_requests.clamp(1, Semaphore::MAX_PERMITS),
+            )),
+            per_host: None,
+            domains: Vec::new(),
+            per_ip: None,
+            suffixes: default_suffixes(),
+            hosts: Default::default(),
+            ips: Default::default(),
+            addrs: Default::default(),
+        }
+    }
+
+    /// Allow at most `max_requests` requests at the same time to each host
+    pub fn per_host(mut self, max_requests: usize) -> Self {
+        self.per_host = Some(max_requests.max(1));
+        self
+    }
+
+    /// Allow at most `max_requests` requests at the same time to each host
+    /// that matches the `pattern`, instead of the `per_host` limit.
+    ///
+    /// The first matching pattern applies.
+    pub fn per_domain(mut self, pattern: impl Into<DomainPattern>, max_requests: usize) -> Self {
+        self.domains.push((pattern.into(), max_requests.max(1)));
+        self
+    }
+
+    /// Allow at most `max_requests` requests at the same time to each ip
+    /// address, hosts are resolved before their first request
+    pub fn per_ip(mut self, max_requests: usize) -> Self {
+        self.per_ip = Some(max_requests.max(1));
+        self
+    }
+
+    /// Use the `suffixes` to match `DomainPattern::Registrable` patterns
+    pub fn with_public_suffix_list(mut self, suffixes: Arc<PublicSuffixList>) -> Self {
+        self.suffixes = suffixes;
+        self
+    }
+
+    /// The limit of concurrent requests to the host of the `url`
+    pub fn host_limit(&self, url: &Url) -> Option<usize> {
+        let host = url.host_str()?;
+        self.domains
+            .iter()
+            .find(|(pattern, _)| pattern.matches(host, url, &self.suffixes))
+            .map(|(_, limit)| *limit)
+            .or(self.per_host)
+    }
+
+    /// The number of requests that may still start before the global limit is
+    /// reached
+    pub fn available(&self) -> usize {
+        self.global.available_permits()
+    }
+
+    /// The host of the `url` if it has to be resolved before a permit for the
+    /// request can be taken
+    pub(crate) fn unresolved_host<'a>(&self, url: &'a Url) -> Option<&'a str> {
+        match url.host() {
+            Some(url::Host::Domain(host)) if self.per_ip.is_some() => {
+                Some(host).filter(|host| !self.addrs.lock().unwrap().contains_key(*host))
+            }
+            _ => None,
+        }
+    }
+
+    /// Remember the `addr` the `host` resolved to
+    pub(crate) fn insert_addr(&self, host: impl Into<String>, addr: Option<IpAddr>) {
+        self.addrs.lock().unwrap().insert(host.into(), addr);
+    }
+
+    /// Take the permits for a request to the `url`, `None` if any of the
+    /// limits is reached.
+    ///
+    /// The ip address of a host that wasn't resolved yet isn't limited, see
+    /// `unresolved_host`.
+    pub(crate) fn try_acquire(&self, url: &Url) -> Option<Permit> {
+        let mut permit = Permit {
+            permits: vec![Arc::clone(&self.global).try_acquire_owned().ok()?],
+        };
+        let host = url.host_str().unwrap_or_default();
+        if let Some(limit) = self.host_limit(url) {
+            let semaphore = semaphore(&self.hosts, host.to_string(), limit);
+            permit.permits.push(semaphore.try_acquire_owned().ok()?);
+        }
+        if let Some(limit) = self.per_ip {
+            let addr = match url.host() {
+                Some(url::Host::Ipv4(addr)) => Some(IpAddr::V4(addr)),
+                Some(url::Host::Ipv6(addr)) => Some(IpAddr::V6(addr)),
+                _ => self.addrs.lock().unwrap().get(host).copied().flatten(),
+            };
+            if let Some(addr) = addr {
+                let semaphore = semaphore(&self.ips, addr, limit);
+                permit.permits.push(semaphore.try_acquire_owned().ok()?);
+            }
+        }
+        Some(permit)
+    }
+}
+
+/// The semaphore of the `key`, created with `limit` permits
+fn semaphore<K: std::hash::Hash + Eq>(
+    semaphores: &Mutex<HashMap<K, Arc<Semaphore>>>,
+    key: K,
+    limit: usize,
+) -> Arc<Semaphore> {
+    Arc::clone(
+        semaphores
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(Semaphore::new(limit))),
+    )
+}
+
+/// The permits a request holds while it is in flight, they are released when
+/// it is dropped
+pub(crate) struct Permit {
+    permits: Vec<OwnedSemaphorePermit>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        url.parse().unwrap()
+    }
+
+    #[test]
+    fn acquire_permits() {
+        let limits = Arc::new(
+            ConcurrencyLimits::new(3)
+                .per_host(1)
+                .per_domain("*.example.com", 2),
+        );
+        assert_eq!(limits.host_limit(&url("https://a.example.com/")), Some(2));
+        assert_eq!(limits.host_limit(&url("https://example.org/")), Some(1));
+
+        let a = limits.try_acquire(&url("https://example.org/a")).unwrap();
+        assert!(limits.try_acquire(&url("https://example.org/b")).is_none());
+        drop(a);
+        let a = limits.try_acquire(&url("https://example.org/a")).unwrap();
+        let b = limits.try_acquire(&url("https://a.example.com/")).unwrap();
+        let c = limits.try_acquire(&url("https://a.example.com/")).unwrap();
+        assert_eq!(limits.available(), 0);
+        assert!(limits.try_acquire(&url("https://example.net/")).is_none());
+        drop((a, b, c));
+        assert_eq!(limits.available(), 3);
+    }
+
+    #[test]
+    fn limit_ip_addresses() {
+        let limits = Arc::new(ConcurrencyLimits::new(10).per_ip(1));
+        let _a = limits.try_acquire(&url("http://127.0.0.1:8080/")).unwrap();
+        assert!(limits.try_acquire(&url("http://127.0.0.1:9000/")).is_none());
+        assert!(limits.try_acquire(&url("http://[::1]/")).is_some());
+        // a name is resolved before its first request
+        let localhost = url("http://localhost/");
+        assert_eq!(limits.unresolved_host(&localhost), Some("localhost"));
+        limits.insert_addr("localhost", Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(limits.unresolved_host(&localhost), None);
+        assert!(limits.try_acquire(&localhost).is_none());
+    }
+}
@@ -0,0 +1,365 @@
+//! Extracting the links of a page and following them automatically.
+//!
+//! A `LinkExtractor` collects the links of a `Response` and filters them, a
+//! `FollowRule` added with `Collector::follow` queues them as new requests.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use crate::{Crawler, Response, Scraper};
+
+/// File extensions of links that are not followed by default, they rarely
+/// point to html pages
+pub const IGNORED_EXTENSIONS: &[&str] = &[
+    "3gp", "7z", "aac", "aiff", "apk", "avi", "avif", "bin", "bmp", "bz2", "cab", "css", "deb",
+    "dll", "dmg", "doc", "docx", "exe", "flac", "flv", "gif", "gz", "heic", "ico", "iso", "jar",
+    "jpeg", "jpg", "js", "m4a", "m4v", "mid", "mkv", "mov", "mp3", "mp4", "mpeg", "mpg", "msi",
+    "odp", "ods", "odt", "oga", "ogg", "opus", "pdf", "png", "ppt", "pptx", "psd", "rar", "rpm",
+    "svg", "tar", "tif", "tiff", "wav", "webm", "webp", "wma", "wmv", "woff", "woff2", "xls",
+    "xlsx", "xz", "zip",
+];
+
+/// The `rel` values of `<link>` elements that point to other pages
+const PAGE_RELS: &[&str] = &["alternate", "canonical", "next", "prev"];
+
+/// A link found on a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The absolute url without fragment
+    pub url: Url,
+    /// The trimmed text of the element, empty for elements without text
+    pub text: String,
+    /// The `rel` attribute of the element
+    pub rel: Option<String>,
+    /// The name of the element, like `a`
+    pub tag: String,
+}
+
+/// Collects the links of a page.
+///
+/// By default the `href` of `<a>` and `<area>` elements and of `<link>`
+/// elements with a `rel` like `canonical` or `next` are extracted, resolved
+/// against the `<base href>` or url of the page. Links with a `nofollow`
+/// `rel`, on pages with a `nofollow` robots `<meta>` tag, with a non http(s)
+/// scheme or one of the `IGNORED_EXTENSIONS` are skipped.
+#[derive(Debug, Clone)]
+pub struct LinkExtractor {
+    /// Only links that match one of these are extracted, all if empty
+    allow: Vec<Regex>,
+    /// Links that match one of these are skipped
+    deny: Vec<Regex>,
+    /// Lowercase file extensions of links that are skipped
+    deny_extensions: HashSet<String>,
+    /// Also extract `nofollow` links
+    follow_nofollow: bool,
+    /// Also extract the `src` of images, scripts, frames and media
+    include_src: bool,
+}
+
+impl Default for LinkExtractor {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            deny_extensions: IGNORED_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+            follow_nofollow: false,
+            include_src: false,
+        }
+    }
+}
+
+impl LinkExtractor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only extract links whose url matches the `regex`, or any other allowed
+    /// regex
+    pub fn allow(mut self, regex: &str) -> Result<Self, regex::Error> {
+        self.allow.push(Regex::new(regex)?);
+        Ok(self)
+    }
+
+    /// Skip links whose url matches the `regex`, this takes precedence over
+    /// `allow`
+    pub fn deny(mut self, regex: &str) -> Result<Self, regex::Error> {
+        self.deny.push(Regex::new(regex)?);
+        Ok(self)
+    }
+
+    /// Skip links with one of the file `extensions` instead of the
+    /// `IGNORED_EXTENSIONS`
+    pub fn deny_extensions<I, E>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = E>,
+        E: AsRef<str>,
+    {
+        self.deny_extensions = extensions
+            .into_iter()
+            .map(|ext| ext.as_ref().trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Also extract links marked as `nofollow`
+    pub fn follow_nofollow(mut self) -> Self {
+        self.follow_nofollow = true;
+        self
+    }
+
+    /// Also extract the `src` of `<img>`, `<script>`, `<iframe>`, `<frame>`,
+    /// `<embed>`, `<source>`, `<audio>` and `<video>` elements
+    pub fn include_src(mut self) -> Self {
+        self.include_src = true;
+        self
+    }
+
+    /// Whether a link to the `url` passes the filters
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+        let extension = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+        if extension.is_some_and(|ext| self.deny_extensions.contains(&ext)) {
+            return false;
+        }
+        if self.deny.iter().any(|regex| regex.is_match(url.as_str())) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|regex| regex.is_match(url.as_str()))
+    }
+
+    /// The distinct links of the `response` in document order
+    pub fn extract<T>(&self, response: &Response<T>) -> Vec<Link> {
+        let page = response.parse();
+        if !self.follow_nofollow && is_nofollow_page(page.html()) {
+            return Vec::new();
+        }
+
+        let mut selector = String::from("a[href], area[href], link[href]");
+        if self.include_src {
+            selector.push_str(
+                ", img[src], script[src], iframe[src], frame[src], embed[src], source[src], \
+                 audio[src], video[src]",
+            );
+        }
+        let selector = Selector::parse(&selector).unwrap();
+        let base = page.base_url();
+
+        let mut seen = HashSet::new();
+        let mut links = Vec::new();
+        for el in page.html().select(&selector) {
+            let value = el.value();
+            let rel = value.attr("rel");
+            let rels = || rel.unwrap_or_default().split_ascii_whitespace();
+            if value.name() == "link"
+                && !rels().any(|r| PAGE_RELS.iter().any(|p| r.eq_ignore_ascii_case(p)))
+            {
+                continue;
+            }
+            if !self.follow_nofollow && rels().any(|r| r.eq_ignore_ascii_case("nofollow")) {
+                continue;
+            }
+            let href = match value.attr("href").or_else(|| value.attr("src")) {
+                Some(href) if !href.trim().is_empty() => href.trim(),
+                _ => continue,
+            };
+            let mut url = match base.join(href) {
+                Ok(url) => url,
+                Err(_) => continue,
+            };
+            url.set_fragment(None);
+            if !self.is_allowed(&url) || !seen.insert(url.clone()) {
+                continue;
+            }
+            links.push(Link {
+                url,
+                text: el.text().collect::<String>().trim().to_string(),
+                rel: rel.map(str::to_string),
+                tag: value.name().to_string(),
+            });
+        }
+        links
+    }
+}
+
+/// Whether a robots `<meta>` tag forbids following the links of the page
+fn is_nofollow_page(html: &Html) -> bool {
+    let selector = Selector::parse("meta[name][content]").unwrap();
+    html.select(&selector).any(|el| {
+        let value = el.value();
+        value
+            .attr("name")
+            .unwrap_or_default()
+            .eq_ignore_ascii_case("robots")
+            && value
+                .attr("content")
+                .unwrap_or_default()
+                .split(',')
+                .any(|directive| {
+                    let directive = directive.trim();
+                    directive.eq_ignore_ascii_case("nofollow")
+                        || directive.eq_ignore_ascii_case("none")
+                })
+    })
+}
+
+/// Derives the scraper state of a followed link
+type LinkStateFn<S> = Box<dyn Fn(&Link) -> Option<S>>;
+
+/// Queues the links a `LinkExtractor` finds on every crawled page, see
+/// `Collector::follow`.
+///
+/// Followed links are one level deeper than their page, so the crawl ends at
+/// `CrawlerConfig::max_depth`. Combine it with
+/// `CrawlerConfig::deduplicate_requests`, otherwise pages that link to each
+/// other are crawled again and again.
+pub struct FollowRule<S> {
+    extractor: LinkExtractor,
+    state_fn: LinkStateFn<S>,
+}
+
+impl<S> FollowRule<S> {
+    /// Follow the links of the `extractor` without state
+    pub fn new(extractor: LinkExtractor) -> Self {
+        Self {
+            extractor,
+            state_fn: Box::new(|_| None),
+        }
+    }
+
+    /// Attach the state returned by `state_fn` to the requests of followed
+    /// links
+    pub fn with_state<F>(mut self, state_fn: F) -> Self
+    where
+        F: Fn(&Link) -> Option<S> + 'static,
+    {
+        self.state_fn = Box::new(state_fn);
+        self
+    }
+
+    /// The extractor of the links that are followed
+    pub fn extractor(&self) -> &LinkExtractor {
+        &self.extractor
+    }
+
+    /// The urls of the links on the `response` with their state
+    pub(crate) fn links(&self, response: &Response<S>) -> Vec<(Url, Option<S>)> {
+        self.extractor
+            .extract(response)
+            .into_iter()
+            .map(|link| {
+                let state = (self.state_fn)(&link);
+                (link.url, state)
+            })
+            .collect()
+    }
+}
+
+/// A scraper that outputs the url of every crawled page, for crawls that are
+/// driven by `FollowRule`s alone
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageUrls;
+
+impl Scraper for PageUrls {
+    type Output = Url;
+    type State = ();
+
+    fn scrape(
+        &mut self,
+        response: Response<Self::State>,
+        _: &mut Crawler<Self>,
+    ) -> Result<Option<Self::Output>> {
+        Ok(Some(response.response_url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><head>
+        <base href="https://example.com/docs/">
+        <link rel="stylesheet" href="/style.css">
+        <link rel="canonical" href="https://example.com/docs/index">
+        </head><body>
+        <a href="intro#setup"> Intro </a>
+        <a href="/intro">Intro again</a>
+        <a href="guide.pdf">Guide</a>
+        <a href="mailto:team@example.com">Mail</a>
+        <a href="/login" rel="nofollow">Login</a>
+        <area href="/map">
+        <img src="/logo.png">
+        </body></html>"#;
+
+    fn urls(links: &[Link]) -> Vec<&str> {
+        links.iter().map(|link| link.url.as_str()).collect()
+    }
+
+    #[test]
+    fn extract_links() {
+        let resp = Response::<()>::from_html("https://example.com/", PAGE);
+        let links = LinkExtractor::new().extract(&resp);
+        assert_eq!(
+            urls(&links),
+            [
+                "https://example.com/docs/index",
+                "https://example.com/docs/intro",
+                "https://example.com/intro",
+                "https://example.com/map",
+            ]
+        );
+        assert_eq!(links[1].text, "Intro");
+        assert_eq!(links[0].rel.as_deref(), Some("canonical"));
+
+        let extractor = LinkExtractor::new()
+            .follow_nofollow()
+            .include_src()
+            .deny_extensions(["pdf"])
+            .deny("/docs/")
+            .unwrap();
+        assert_eq!(
+            urls(&extractor.extract(&resp)),
+            [
+                "https://example.com/intro",
+                "https://example.com/login",
+                "https://example.com/map",
+                "https://example.com/logo.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn allow_patterns_and_nofollow_pages() {
+        let resp = Response::<()>::from_html("https://example.com/", PAGE);
+        let extractor = LinkExtractor::new().allow(r"/docs/\w+$").unwrap();
+        assert_eq!(
+            urls(&extractor.extract(&resp)),
+            [
+                "https://example.com/docs/index",
+                "https://example.com/docs/intro"
+            ]
+        );
+
+        let resp = Response::<()>::from_html(
+            "https://example.com/",
+            r#"<meta name="robots" content="noindex, nofollow"><a href="/a">a</a>"#,
+        );
+        assert!(LinkExtractor::new().extract(&resp).is_empty());
+        assert_eq!(
+            LinkExtractor::new().follow_nofollow().extract(&resp).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Hooks that run between queuing and sending a request, and between
+//! receiving and scraping its response.
+//!
+//! Middlewares are added with `Collector::middleware` and run in the order
+//! they were added, until one of them doesn't `Continue`.
+
+use anyhow::Error;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, USER_AGENT};
+
+use crate::requests::QueuedRequest;
+use crate::response::Response;
+
+/// What happens to a request, response or error after a middleware saw it
+#[derive(Debug)]
+pub enum Action {
+    /// Pass it on to the next middleware
+    Continue,
+    /// Silently drop it
+    Drop,
+    /// Drop it and report the error instead
+    Fail(Error),
+}
+
+/// A hook into the requests and responses of the crawler
+pub trait Middleware<T> {
+    /// Called before the request is queued for its domain
+    fn on_request(&mut self, _request: &mut QueuedRequest<T>) -> Action {
+        Action::Continue
+    }
+
+    /// Called before the response is passed to the scraper
+    fn on_response(&mut self, _response: &mut Response<T>) -> Action {
+        Action::Continue
+    }
+
+    /// Called for every failed or rejected request, before the error is
+    /// reported
+    fn on_error(&mut self, _error: &Error) -> Action {
+        Action::Continue
+    }
+}
+
+/// The middlewares of a `DomainListing`, in order
+pub(crate) struct Middlewares<T> {
+    chain: Vec<Box<dyn Middleware<T>>>,
+}
+
+impl<T> Default for Middlewares<T> {
+    fn default() -> Self {
+        Self { chain: Vec::new() }
+    }
+}
+
+impl<T> Middlewares<T> {
+    pub fn push(&mut self, middleware: Box<dyn Middleware<T>>) {
+        self.chain.push(middleware);
+    }
+
+    pub fn on_request(&mut self, request: &mut QueuedRequest<T>) -> Action {
+        self.run(|middleware| middleware.on_request(request))
+    }
+
+    pub fn on_response(&mut self, response: &mut Response<T>) -> Action {
+        self.run(|middleware| middleware.on_response(response))
+    }
+
+    pub fn on_error(&mut self, error: &Error) -> Action {
+        self.run(|middleware| middleware.on_error(error))
+    }
+
+    /// The first action that isn't `Continue`
+    fn run<F>(&mut self, mut hook: F) -> Action
+    where
+        F: FnMut(&mut dyn Middleware<T>) -> Action,
+    {
+        for middleware in &mut self.chain {
+            match hook(middleware.as_mut()) {
+                Action::Continue => {}
+                action => return action,
+            }
+        }
+        Action::Continue
+    }
+}
+
+/// Adds headers to every request that doesn't set them, like an
+/// `Authorization` header
+#[derive(Debug, Clone, Default)]
+pub struct DefaultHeaders {
+    headers: HeaderMap,
+}
+
+impl DefaultHeaders {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the header `name: value`
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+impl From<HeaderMap> for DefaultHeaders {
+    fn from(headers: HeaderMap) -> Self {
+        Self { headers }
+    }
+}
+
+impl<T> Middleware<T> for DefaultHeaders {
+    fn on_request(&mut self, request: &mut QueuedRequest<T>) -> Action {
+        let headers = request.request.headers_mut();
+        for (name, value) in &self.headers {
+            if !headers.contains_key(name) {
+                headers.insert(name, value.clone());
+            }
+        }
+        Action::Continue
+    }
+}
+
+/// Sends the requests with `User-Agent` headers taken in turn from a list.
+///
+/// The header of every request is replaced, so set the product token of the
+/// robots.txt rules with `CrawlerConfig::robots_user_agent`.
+#[derive(Debug, Clone)]
+pub struct RotateUserAgent {
+    agents: Vec<HeaderValue>,
+    next: usize,
+}
+
+impl RotateUserAgent {
+    /// Rotate the `agents`, invalid header values are skipped
+    pub fn new<I, A>(agents: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<str>,
+    {
+        Self {
+            agents: agents
+                .into_iter()
+                .filter_map(|agent| HeaderValue::from_str(agent.as_ref()).ok())
+                .collect(),
+            next: 0,
+        }
+    }
+}
+
+impl<T> Middleware<T> for RotateUserAgent {
+    fn on_request(&mut self, request: &mut QueuedRequest<T>) -> Action {
+        if let Some(agent) = self.agents.get(self.next) {
+            request
+                .request
+                .headers_mut()
+                .insert(USER_AGENT, agent.clone());
+            self.next = (self.next + 1) % self.agents.len();
+        }
+        Action::Continue
+    }
+}
+
+/// Drops responses whose `Content-Type` is not allowed, before they are
+/// scraped.
+///
+/// Responses without a `Content-Type` are kept.
+#[derive(Debug, Clone, Default)]
+pub struct ContentTypeFilter {
+    /// Lowercase mime types, `text/*` allows all subtypes
+    allowed: Vec<String>,
+}
+
+impl ContentTypeFilter {
+    /// Allow only the mime types, like `text/html` or `application/*`
+    pub fn allow<I, M>(types: I) -> Self
+    where
+        I: IntoIterator<Item = M>,
+        M: AsRef<str>,
+    {
+        Self {
+            allowed: types
+                .into_iter()
+                .map(|mime| mime.as_ref().trim().to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Allow only html and xhtml pages
+    pub fn html() -> Self {
+        Self::allow(["text/html", "application/xhtml+xml"])
+    }
+
+    /// Whether the `content_type` header value is allowed
+    pub fn is_allowed(&self, content_type: &str) -> bool {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.allowed
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) => mime.starts_with(prefix),
+                None => *allowed == mime,
+            })
+    }
+}
+
+impl<T> Middleware<T> for ContentTypeFilter {
+    fn on_response(&mut self, response: &mut Response<T>) -> Action {
+        match response
+            .response_headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(content_type) if !self.is_allowed(content_type) => Action::Drop,
+            _ => Action::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(url: &str) -> QueuedRequest<()> {
+        QueuedRequest {
+            request: reqwest::Request::new(reqwest::Method::GET, url.parse().unwrap()),
+            state: None,
+            depth: 1,
+            attempts: Vec::new(),
+            ticket: None,
+        }
+    }
+
+    #[test]
+    fn rotate_and_default_headers() {
+        let mut chain = Middlewares::default();
+        chain.push(Box::new(RotateUserAgent::new(["a/1", "b/1"])));
+        chain.push(Box::new(
+            DefaultHeaders::new().header(USER_AGENT, HeaderValue::from_static("default/1")),
+        ));
+
+        let agents: Vec<_> = (0..3)
+            .map(|_| {
+                let mut request = queued("https://example.com/");
+                assert!(matches!(chain.on_request(&mut request), Action::Continue));
+                request.request.headers()[USER_AGENT].clone()
+            })
+            .collect();
+        assert_eq!(agents, ["a/1", "b/1", "a/1"]);
+    }
+
+    #[test]
+    fn filter_content_types() {
+        let filter = ContentTypeFilter::allow(["text/html", "application/*"]);
+        assert!(filter.is_allowed("text/html; charset=utf-8"));
+        assert!(filter.is_allowed("Application/JSON"));
+        assert!(!filter.is_allowed("image/png"));
+        assert!(!ContentTypeFilter::html().is_allowed("text/plain"));
+    }
+}
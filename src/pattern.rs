@@ -0,0 +1,265 @@
+//! Patterns that match whole groups of hosts in the allow and block lists.
+//!
+//! A plain domain like `reddit.com` only matches that host. To cover a site
+//! together with its subdomains use `*.reddit.com`, or match everything that
+//! belongs to the same registrable domain according to the public suffix list.
+
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use publicsuffix::Psl;
+use regex::Regex;
+use reqwest::Url;
+
+use crate::canonicalize::CanonicalizeRules;
+
+/// The public suffix list used to determine registrable domains.
+///
+/// See <https://publicsuffix.org/list/> for the current list.
+pub use publicsuffix::List as PublicSuffixList;
+
+/// A pattern that matches the host and optionally the path of a url
+#[derive(Debug, Clone)]
+pub enum DomainPattern {
+    /// Matches exactly this host
+    Exact(String),
+    /// Matches the domain itself and all of its subdomains, written as
+    /// `*.example.com`
+    Subdomains(String),
+    /// Matches every host that has the same registrable domain, like
+    /// `old.reddit.com` and `www.reddit.com` for `reddit.com`
+    Registrable(String),
+    /// Matches every host that matches the regex
+    Regex(Regex),
+    /// Matches urls of the host whose path is the prefix or below it, written as
+    /// `example.com/blog/`
+    PathPrefix { host: String, prefix: String },
+}
+
+impl DomainPattern {
+    /// Parse a pattern: `*.example.com` matches all subdomains,
+    /// `example.com/blog` matches a path prefix and anything else is an exact
+    /// host.
+    pub fn parse(pattern: &str) -> Self {
+        if let Some(domain) = pattern.strip_prefix("*.") {
+            DomainPattern::Subdomains(domain.to_string())
+        } else if let Some(idx) = pattern.find('/') {
+            DomainPattern::path_prefix(&pattern[..idx], &pattern[idx..])
+        } else {
+            DomainPattern::Exact(pattern.to_string())
+        }
+    }
+
+    /// Matches exactly the `host`
+    pub fn exact(host: impl Into<String>) -> Self {
+        DomainPattern::Exact(host.into())
+    }
+
+    /// Matches the `domain` and all of its subdomains
+    pub fn subdomains(domain: impl Into<String>) -> Self {
+        DomainPattern::Subdomains(domain.into())
+    }
+
+    /// Matches all hosts with the same registrable domain as `domain`
+    pub fn registrable(domain: impl Into<String>) -> Self {
+        DomainPattern::Registrable(domain.into())
+    }
+
+    /// Matches all hosts that match the `regex`
+    pub fn regex(regex: &str) -> Result<Self, regex::Error> {
+        Ok(DomainPattern::Regex(Regex::new(regex)?))
+    }
+
+    /// Matches all urls of `host` whose path is `prefix` or below it
+    pub fn path_prefix(host: impl Into<String>, prefix: impl Into<String>) -> Self {
+        DomainPattern::PathPrefix {
+            host: host.into(),
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Whether the pattern only matches a single host
+    pub fn is_exact(&self) -> bool {
+        matches!(self, DomainPattern::Exact(_))
+    }
+
+    /// Apply the `rules` to all hosts of this pattern
+    pub(crate) fn canonicalize(self, rules: &CanonicalizeRules) -> Self {
+        match self {
+            DomainPattern::Exact(host) => DomainPattern::Exact(rules.host(&host)),
+            DomainPattern::Subdomains(domain) => DomainPattern::Subdomains(rules.host(&domain)),
+            DomainPattern::Registrable(domain) => DomainPattern::Registrable(rules.host(&domain)),
+            DomainPattern::PathPrefix { host, prefix } => DomainPattern::PathPrefix {
+                host: rules.host(&host),
+                prefix,
+            },
+            regex => regex,
+        }
+    }
+
+    /// Whether the canonical `host` matches, ignoring any path prefix
+    pub fn matches_host(&self, host: &str, suffixes: &PublicSuffixList) -> bool {
+        match self {
+            DomainPattern::Exact(exact) => host == exact,
+            DomainPattern::Subdomains(domain) => {
+                host == domain
+                    || (host.ends_with(domain.as_str())
+                        && host[..host.len() - domain.len()].ends_with('.'))
+            }
+            DomainPattern::Registrable(domain) => {
+                match (
+                    registrable_domain(host, suffixes),
+                    registrable_domain(domain, suffixes),
+                ) {
+                    (Some(host), Some(domain)) => host == domain,
+                    _ => false,
+                }
+            }
+            DomainPattern::Regex(regex) => regex.is_match(host),
+            DomainPattern::PathPrefix { host: exact, .. } => host == exact,
+        }
+    }
+
+    /// Whether the `url` with the canonical `host` matches
+    pub fn matches(&self, host: &str, url: &Url, suffixes: &PublicSuffixList) -> bool {
+        if let DomainPattern::PathPrefix { prefix, .. } = self {
+            if !is_path_prefix(prefix, url.path()) {
+                return false;
+            }
+        }
+        self.matches_host(host, suffixes)
+    }
+}
+
+impl fmt::Display for DomainPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainPattern::Exact(host) => write!(f, "{}", host),
+            DomainPattern::Subdomains(domain) => write!(f, "*.{}", domain),
+            DomainPattern::Registrable(domain) => write!(f, "registrable:{}", domain),
+            DomainPattern::Regex(regex) => write!(f, "regex:{}", regex),
+            DomainPattern::PathPrefix { host, prefix } => write!(f, "{}{}", host, prefix),
+        }
+    }
+}
+
+impl From<&str> for DomainPattern {
+    fn from(pattern: &str) -> Self {
+        DomainPattern::parse(pattern)
+    }
+}
+
+impl From<String> for DomainPattern {
+    fn from(pattern: String) -> Self {
+        DomainPattern::parse(&pattern)
+    }
+}
+
+/// The public suffix list that is bundled with the crate, a copy of
+/// <https://publicsuffix.org/list/public_suffix_list.dat>
+const BUNDLED_SUFFIXES: &str = include_str!("../data/public_suffix_list.dat");
+
+/// The bundled public suffix list, parsed once and shared by all crawlers
+pub(crate) fn default_suffixes() -> Arc<PublicSuffixList> {
+    static SUFFIXES: OnceLock<Arc<PublicSuffixList>> = OnceLock::new();
+    let suffixes = SUFFIXES.get_or_init(|| {
+        Arc::new(
+            BUNDLED_SUFFIXES
+                .parse()
+                .expect("the bundled public suffix list is valid"),
+        )
+    });
+    Arc::clone(suffixes)
+}
+
+/// Whether the `path` is the `prefix` or one of the paths below it, so
+/// `/blog` matches `/blog/1` but not `/blogger`
+fn is_path_prefix(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The registrable domain of the `host`, like `reddit.com` for `old.reddit.com`
+pub fn registrable_domain(host: &str, suffixes: &PublicSuffixList) -> Option<String> {
+    suffixes
+        .domain(host.as_bytes())
+        .and_then(|domain| std::str::from_utf8(domain.as_bytes()).ok())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn parse_patterns() {
+        assert!(DomainPattern::parse("example.com").is_exact());
+        assert!(matches!(
+            DomainPattern::parse("*.example.com"),
+            DomainPattern::Subdomains(d) if d == "example.com"
+        ));
+        assert!(matches!(
+            DomainPattern::parse("example.com/blog"),
+            DomainPattern::PathPrefix { host, prefix } if host == "example.com" && prefix == "/blog"
+        ));
+    }
+
+    #[test]
+    fn subdomain_patterns() {
+        let list = PublicSuffixList::new();
+        let pattern = DomainPattern::parse("*.reddit.com");
+        assert!(pattern.matches_host("reddit.com", &list));
+        assert!(pattern.matches_host("old.reddit.com", &list));
+        assert!(pattern.matches_host("a.b.reddit.com", &list));
+        assert!(!pattern.matches_host("notreddit.com", &list));
+    }
+
+    #[test]
+    fn registrable_patterns() {
+        let list: PublicSuffixList = "// ===BEGIN PRIVATE DOMAINS===\ngithub.io\n"
+            .parse()
+            .unwrap();
+        let pattern = DomainPattern::registrable("old.reddit.com");
+        assert!(pattern.matches_host("www.reddit.com", &list));
+        assert!(!pattern.matches_host("reddit.org", &list));
+
+        let pattern = DomainPattern::registrable("foo.github.io");
+        assert!(pattern.matches_host("www.foo.github.io", &list));
+        assert!(!pattern.matches_host("bar.github.io", &list));
+    }
+
+    #[test]
+    fn bundled_suffixes() {
+        let list = default_suffixes();
+        assert_eq!(
+            registrable_domain("news.bbc.co.uk", &list).as_deref(),
+            Some("bbc.co.uk")
+        );
+        let pattern = DomainPattern::registrable("bbc.co.uk");
+        assert!(pattern.matches_host("www.bbc.co.uk", &list));
+        assert!(!pattern.matches_host("itv.co.uk", &list));
+    }
+
+    #[test]
+    fn regex_and_path_patterns() {
+        let list = PublicSuffixList::new();
+        let pattern = DomainPattern::regex(r"^cdn\d+\.example\.com$").unwrap();
+        assert!(pattern.matches_host("cdn1.example.com", &list));
+        assert!(!pattern.matches_host("www.example.com", &list));
+
+        let pattern = DomainPattern::parse("example.com/blog/");
+        assert!(pattern.matches("example.com", &url("https://example.com/blog/1"), &list));
+        assert!(!pattern.matches("example.com", &url("https://example.com/shop"), &list));
+
+        let pattern = DomainPattern::parse("example.com/blog");
+        assert!(pattern.matches("example.com", &url("https://example.com/blog"), &list));
+        assert!(pattern.matches("example.com", &url("https://example.com/blog/1"), &list));
+        assert!(!pattern.matches("example.com", &url("https://example.com/blogger"), &list));
+    }
+}
@@ -0,0 +1,371 @@
+//! Stages that validate, transform and store the items of a `Collector`.
+//!
+//! Stages are chained with `Collector::pipe` and see every item in the order
+//! they were added. An item that a stage drops is not passed to the later
+//! stages and not yielded by the collector.
+//!
+//! One item is processed at a time. The crawler keeps running while a stage
+//! is pending, its outputs wait in order, up to
+//! `Collector::MAX_PENDING_OUTPUTS` of them.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::Result;
+use futures::future::{self, FutureExt};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::PipelineError;
+
+pub type ItemFuture<O> = Pin<Box<dyn Future<Output = Result<Option<O>>>>>;
+pub type CloseFuture = Pin<Box<dyn Future<Output = Result<()>>>>;
+
+/// A stage of the pipeline that every item of the `Collector` passes
+pub trait ItemPipeline<O> {
+    /// Process the `item`, resolve to `None` to drop it.
+    ///
+    /// An error is yielded by the collector in place of the item.
+    fn process_item(&mut self, item: O) -> ItemFuture<O>;
+
+    /// Called once after the last item, to flush what the stage buffered.
+    ///
+    /// It isn't called if the collector is dropped before it is done, a stage
+    /// that buffers should also flush when it is dropped.
+    fn close(&mut self) -> CloseFuture {
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+impl<O, P: ItemPipeline<O> + ?Sized> ItemPipeline<O> for Box<P> {
+    fn process_item(&mut self, item: O) -> ItemFuture<O> {
+        (**self).process_item(item)
+    }
+
+    fn close(&mut self) -> CloseFuture {
+        (**self).close()
+    }
+}
+
+/// The stages of a `Collector` and the item that is processed
+pub(crate) struct Pipeline<O> {
+    stages: Vec<Box<dyn ItemPipeline<O>>>,
+    /// The stage that processes the current item
+    processing: Option<(usize, ItemFuture<O>)>,
+    /// The stage that is closed, all stages are closed once this is past the end
+    closing: Option<(usize, CloseFuture)>,
+    closed: usize,
+}
+
+impl<O> Default for Pipeline<O> {
+    fn default() -> Self {
+        Self {
+            stages: Vec::new(),
+            processing: None,
+            closing: None,
+            closed: 0,
+        }
+    }
+}
+
+impl<O> Pipeline<O> {
+    pub fn push(&mut self, stage: Box<dyn ItemPipeline<O>>) {
+        self.stages.push(stage);
+    }
+
+    /// Pass the `item` to the first stage, returns it if there are no stages
+    pub fn start(&mut self, item: O) -> Option<O> {
+        match self.stages.first_mut() {
+            Some(stage) => {
+                self.processing = Some((0, stage.process_item(item)));
+                None
+            }
+            None => Some(item),
+        }
+    }
+
+    /// Drive the current item through the stages.
+    ///
+    /// Resolves to `None` if no item is processed or it was dropped.
+    pub fn poll_item(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<O>>> {
+        while let Some((stage, fut)) = self.processing.as_mut() {
+            let stage = *stage;
+            let item = match fut.poll_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(result) => {
+                    self.processing = None;
+                    match result {
+                        Ok(Some(item)) => item,
+                        Ok(None) => break,
+                        Err(err) => return Poll::Ready(Some(Err(err))),
+                    }
+                }
+            };
+            match self.stages.get_mut(stage + 1) {
+                Some(next) => self.processing = Some((stage + 1, next.process_item(item))),
+                None => return Poll::Ready(Some(Ok(item))),
+            }
+        }
+        Poll::Ready(None)
+    }
+
+    /// Close all stages in order, resolves to the first error
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut result = Ok(());
+        while self.closed < self.stages.len() {
+            let closed = self.closed;
+            let (_, fut) = self
+                .closing
+                .get_or_insert_with(|| (closed, self.stages[closed].close()));
+            match fut.poll_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(closed) => {
+                    self.closing = None;
+                    self.closed += 1;
+                    if result.is_ok() {
+                        result = closed;
+                    }
+                }
+            }
+        }
+        Poll::Ready(result)
+    }
+}
+
+type Check<O> = Box<dyn Fn(&O) -> bool>;
+
+/// Checks fields of the items, invalid items are dropped or, with
+/// `report_invalid`, reported as `PipelineError::Invalid`
+pub struct Validate<O> {
+    checks: Vec<(String, Check<O>)>,
+    report: bool,
+}
+
+impl<O> Default for Validate<O> {
+    fn default() -> Self {
+        Self {
+            checks: Vec::new(),
+            report: false,
+        }
+    }
+}
+
+impl<O> Validate<O> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Require the `check` of the `field` to pass
+    pub fn check<F>(mut self, field: impl Into<String>, check: F) -> Self
+    where
+        F: Fn(&O) -> bool + 'static,
+    {
+        self.checks.push((field.into(), Box::new(check)));
+        self
+    }
+
+    /// Report invalid items as error instead of dropping them silently
+    pub fn report_invalid(mut self) -> Self {
+        self.report = true;
+        self
+    }
+
+    /// The first field whose check fails
+    pub fn invalid_field(&self, item: &O) -> Option<&str> {
+        self.checks
+            .iter()
+            .find(|(_, check)| !check(item))
+            .map(|(field, _)| field.as_str())
+    }
+}
+
+impl<O: Serialize> Validate<O> {
+    /// Require the serialized `field` to be present and neither null nor an
+    /// empty string or array
+    pub fn require(self, field: impl Into<String>) -> Self {
+        let field = field.into();
+        let name = field.clone();
+        self.check(name, move |item| {
+            match serde_json::to_value(item)
+                .ok()
+                .as_ref()
+                .and_then(|v| v.get(&field))
+            {
+                None | Some(Value::Null) => false,
+                Some(Value::String(s)) => !s.trim().is_empty(),
+                Some(Value::Array(a)) => !a.is_empty(),
+                Some(_) => true,
+            }
+        })
+    }
+}
+
+impl<O: 'static> ItemPipeline<O> for Validate<O> {
+    fn process_item(&mut self, item: O) -> ItemFuture<O> {
+        let result = match self.invalid_field(&item) {
+            None => Ok(Some(item)),
+            Some(field) if self.report => Err(PipelineError::Invalid(field.to_string()).into()),
+            Some(_) => Ok(None),
+        };
+        Box::pin(future::ready(result))
+    }
+}
+
+/// Drops items whose key was seen before
+pub struct DedupBy<O, K> {
+    key: Box<dyn Fn(&O) -> K>,
+    seen: HashSet<K>,
+    duplicates: usize,
+}
+
+impl<O, K: Hash + Eq> DedupBy<O, K> {
+    /// Identify items by the `key`, like their url
+    pub fn new<F>(key: F) -> Self
+    where
+        F: Fn(&O) -> K + 'static,
+    {
+        Self {
+            key: Box::new(key),
+            seen: HashSet::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// The number of dropped items
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+}
+
+impl<O: 'static, K: Hash + Eq> ItemPipeline<O> for DedupBy<O, K> {
+    fn process_item(&mut self, item: O) -> ItemFuture<O> {
+        let item = if self.seen.insert((self.key)(&item)) {
+            Some(item)
+        } else {
+            self.duplicates += 1;
+            None
+        };
+        Box::pin(future::ready(Ok(item)))
+    }
+}
+
+type BatchFn<O> = Box<dyn FnMut(Vec<O>) -> CloseFuture>;
+
+/// Collects copies of the items and hands them to a function in batches,
+/// for example to insert them with one query.
+///
+/// The items are passed on to the next stage. The last, smaller batch is
+/// handed over when the collector is done, it is lost if the collector is
+/// dropped before.
+pub struct Batch<O> {
+    size: usize,
+    items: Vec<O>,
+    handler: BatchFn<O>,
+}
+
+impl<O> Batch<O> {
+    /// Hand the items to `handler` in batches of `size`
+    pub fn new<F, Fut>(size: usize, mut handler: F) -> Self
+    where
+        F: FnMut(Vec<O>) -> Fut + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        Self {
+            size: size.max(1),
+            items: Vec::new(),
+            handler: Box::new(move |items| Box::pin(handler(items))),
+        }
+    }
+}
+
+impl<O: Clone + 'static> ItemPipeline<O> for Batch<O> {
+    fn process_item(&mut self, item: O) -> ItemFuture<O> {
+        self.items.push(item.clone());
+        if self.items.len() < self.size {
+            return Box::pin(future::ready(Ok(Some(item))));
+        }
+        let batch = (self.handler)(std::mem::take(&mut self.items));
+        Box::pin(async move {
+            batch.await?;
+            Ok(Some(item))
+        })
+    }
+
+    fn close(&mut self) -> CloseFuture {
+        if self.items.is_empty() {
+            return Box::pin(future::ready(Ok(())));
+        }
+        (self.handler)(std::mem::take(&mut self.items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    struct Book {
+        title: String,
+        isbn: u64,
+    }
+
+    fn book(title: &str, isbn: u64) -> Book {
+        Book {
+            title: title.to_string(),
+            isbn,
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_stages() {
+        let batches = Rc::new(RefCell::new(Vec::new()));
+        let written = Rc::clone(&batches);
+
+        let mut pipeline = Pipeline::default();
+        pipeline.push(Box::new(Validate::new().require("title")));
+        pipeline.push(Box::new(DedupBy::new(|book: &Book| book.isbn)));
+        pipeline.push(Box::new(Batch::new(2, move |books: Vec<Book>| {
+            written.borrow_mut().push(books.len());
+            future::ready(Ok(()))
+        })));
+
+        let mut output = Vec::new();
+        for item in [
+            book("a", 1),
+            book(" ", 2),
+            book("b", 1),
+            book("c", 3),
+            book("d", 4),
+        ] {
+            assert!(pipeline.start(item).is_none());
+            let item = future::poll_fn(|cx| pipeline.poll_item(cx)).await;
+            output.extend(item.transpose().unwrap());
+        }
+        future::poll_fn(|cx| pipeline.poll_close(cx)).await.unwrap();
+
+        assert_eq!(output, [book("a", 1), book("c", 3), book("d", 4)]);
+        assert_eq!(*batches.borrow(), [2, 1]);
+    }
+
+    #[test]
+    fn report_invalid_items() {
+        let mut validate = Validate::new()
+            .check("isbn", |book: &Book| book.isbn > 0)
+            .report_invalid();
+        assert_eq!(validate.invalid_field(&book("a", 0)), Some("isbn"));
+        let err = validate
+            .process_item(book("a", 0))
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PipelineError>(),
+            Some(PipelineError::Invalid(field)) if field == "isbn"
+        ));
+    }
+}
@@ -0,0 +1,558 @@
+//! Querying JSON with JSONPath and XML with a subset of XPath.
+//!
+//! Supported JSONPath: `$` followed by `.name`, `['name']`, `.*`, `[*]`,
+//! `[n]` with negative indexes counting from the end, `[start:end]` slices and
+//! `..` recursive descent, like `$..children[*].data.title`.
+//!
+//! Supported XPath: absolute (`/rss/channel`), anywhere (`//item`) and
+//! relative (`title`, `./title`, `.//link`) location paths with the steps
+//! `name`, `*`, `.`, `..`, `text()` and `@attr`, and the predicates `[n]`,
+//! `[@attr]`, `[@attr='value']`, `[child]` and `[child='value']`. Element
+//! names match the local name, so `atom:link` and `link` both select
+//! `<atom:link>`.
+
+use roxmltree::Node;
+use serde_json::Value;
+
+use crate::error::ExtractError;
+
+fn invalid(query: &str, reason: &str) -> ExtractError {
+    ExtractError::InvalidQuery(query.to_string(), reason.to_string())
+}
+
+/// A step of a JSONPath
+#[derive(Debug, Clone, PartialEq)]
+enum JsonStep {
+    Key(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>),
+    Wildcard,
+    /// Apply the next step to the value and all its descendants
+    Recursive,
+}
+
+/// The values of `root` that the JSONPath `path` selects
+pub fn json_path<'a>(root: &'a Value, path: &str) -> Result<Vec<&'a Value>, ExtractError> {
+    let steps = parse_json_path(path)?;
+    let mut current = vec![root];
+    let mut recursive = false;
+    for step in &steps {
+        if *step == JsonStep::Recursive {
+            recursive = true;
+            continue;
+        }
+        if recursive {
+            let mut all = Vec::new();
+            for value in current {
+                descendants(value, &mut all);
+            }
+            current = all;
+            recursive = false;
+        }
+        current = current
+            .into_iter()
+            .flat_map(|value| apply_json_step(value, step))
+            .collect();
+    }
+    Ok(current)
+}
+
+fn descendants<'a>(value: &'a Value, out: &mut Vec<&'a Value>) {
+    out.push(value);
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| descendants(item, out)),
+        Value::Object(map) => map.values().for_each(|item| descendants(item, out)),
+        _ => {}
+    }
+}
+
+fn apply_json_step<'a>(value: &'a Value, step: &JsonStep) -> Vec<&'a Value> {
+    let resolve = |index: i64, len: usize| {
+        if index < 0 {
+            (len as i64 + index).max(0) as usize
+        } else {
+            (index as usize).min(len)
+        }
+    };
+    match (step, value) {
+        (JsonStep::Key(key), Value::Object(map)) => map.get(key).into_iter().collect(),
+        (JsonStep::Index(index), Value::Array(items)) => {
+            if *index < 0 && index.unsigned_abs() as usize > items.len() {
+                return Vec::new();
+            }
+            items
+                .get(resolve(*index, items.len()))
+                .into_iter()
+                .collect()
+        }
+        (JsonStep::Slice(start, end), Value::Array(items)) => {
+            let start = start.map_or(0, |start| resolve(start, items.len()));
+            let end = end.map_or(items.len(), |end| resolve(end, items.len()));
+            items
+                .get(start..end.max(start))
+                .unwrap_or_default()
+                .iter()
+                .collect()
+        }
+        (JsonStep::Wildcard, Value::Array(items)) => items.iter().collect(),
+        (JsonStep::Wildcard, Value::Object(map)) => map.values().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn parse_json_path(path: &str) -> Result<Vec<JsonStep>, ExtractError> {
+    let mut rest = path
+        .trim()
+        .strip_prefix('$')
+        .ok_or_else(|| invalid(path, "a JSONPath starts with `$`"))?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            steps.push(JsonStep::Recursive);
+            if after.starts_with('[') {
+                rest = after;
+                continue;
+            }
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = bracket_end(after).ok_or_else(|| invalid(path, "unclosed `[`"))?;
+            steps.push(parse_json_bracket(path, after[..end].trim())?);
+            rest = &after[end + 1..];
+            continue;
+        } else {
+            return Err(invalid(path, "expected `.` or `[`"));
+        }
+
+        let end = rest.find(['.', '[']).unwrap_or(rest.len());
+        let name = &rest[..end];
+        match name {
+            "" => return Err(invalid(path, "empty name")),
+            "*" => steps.push(JsonStep::Wildcard),
+            name => steps.push(JsonStep::Key(name.to_string())),
+        }
+        rest = &rest[end..];
+    }
+    if steps.last() == Some(&JsonStep::Recursive) {
+        return Err(invalid(path, "`..` must be followed by a name"));
+    }
+    Ok(steps)
+}
+
+/// The position of the `]` that closes a bracket, skipping quoted strings
+fn bracket_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (idx, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, ']') => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unquote(s: &str) -> Option<&str> {
+    s.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .or_else(|| s.strip_prefix('"').and_then(|s| s.strip_suffix('"')))
+}
+
+fn parse_json_bracket(path: &str, content: &str) -> Result<JsonStep, ExtractError> {
+    if content == "*" {
+        return Ok(JsonStep::Wildcard);
+    }
+    if let Some(key) = unquote(content) {
+        return Ok(JsonStep::Key(key.to_string()));
+    }
+    let number = |s: &str| -> Result<Option<i64>, ExtractError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(None);
+        }
+        s.parse()
+            .map(Some)
+            .map_err(|_| invalid(path, "expected an index"))
+    };
+    match content.split_once(':') {
+        Some((start, end)) => Ok(JsonStep::Slice(number(start)?, number(end)?)),
+        None => number(content)?
+            .map(JsonStep::Index)
+            .ok_or_else(|| invalid(path, "empty `[]`")),
+    }
+}
+
+/// Where an XPath step looks for nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Child,
+    /// The children of the context node and all its descendants
+    Descendant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NodeTest {
+    Name(String),
+    Any,
+    Text,
+    Attr(String),
+    Current,
+    Parent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    Position(usize),
+    HasAttr(String),
+    AttrEq(String, String),
+    HasChild(String),
+    ChildEq(String, String),
+}
+
+#[derive(Debug, Clone)]
+struct XStep {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Predicate>,
+}
+
+/// A node or attribute value selected by an XPath
+#[derive(Debug, Clone, Copy)]
+pub enum XmlMatch<'a, 'input> {
+    Node(Node<'a, 'input>),
+    Attr(&'a str),
+}
+
+impl XmlMatch<'_, '_> {
+    /// The trimmed text of the node or the value of the attribute
+    pub fn value(&self) -> String {
+        match self {
+            XmlMatch::Node(node) if node.is_text() => {
+                node.text().unwrap_or_default().trim().to_string()
+            }
+            XmlMatch::Node(node) => node
+                .descendants()
+                .filter(|n| n.is_text())
+                .filter_map(|n| n.text())
+                .collect::<String>()
+                .trim()
+                .to_string(),
+            XmlMatch::Attr(value) => value.to_string(),
+        }
+    }
+}
+
+/// Whether the element name `name` matches the local part of `test`
+fn name_matches(node: Node, test: &str) -> bool {
+    let local = test.rsplit(':').next().unwrap_or(test);
+    node.is_element() && node.tag_name().name() == local
+}
+
+/// Evaluate the XPath `expr` with `context` as context node
+pub fn xpath<'a, 'input>(
+    context: Node<'a, 'input>,
+    expr: &str,
+) -> Result<Vec<XmlMatch<'a, 'input>>, ExtractError> {
+    let (absolute, steps) = parse_xpath(expr)?;
+    let mut current = vec![if absolute {
+        context.document().root()
+    } else {
+        context
+    }];
+    let mut matches = Vec::new();
+    for (idx, step) in steps.iter().enumerate() {
+        let last = idx + 1 == steps.len();
+        let contexts: Vec<Node> = match step.axis {
+            Axis::Child => current,
+            Axis::Descendant => {
+                let mut descendants = current
+                    .iter()
+                    .flat_map(|node| node.descendants())
+                    .filter(|node| node.is_element() || node.is_root())
+                    .collect();
+                // nested contexts share their descendants
+                document_order(&mut descendants);
+                descendants
+            }
+        };
+        let mut next = Vec::new();
+        for node in contexts {
+            match &step.test {
+                NodeTest::Attr(attr) => {
+                    if !last {
+                        return Err(invalid(expr, "`@attr` must be the last step"));
+                    }
+                    matches.extend(node.attribute(attr.as_str()).map(XmlMatch::Attr));
+                }
+                NodeTest::Current => next.push(node),
+                NodeTest::Parent => next.extend(node.parent()),
+                test => {
+                    let selected = node.children().filter(|child| match test {
+                        NodeTest::Name(name) => name_matches(*child, name),
+                        NodeTest::Any => child.is_element(),
+                        NodeTest::Text => child.is_text(),
+                        _ => false,
+                    });
+                    next.extend(filter_predicates(selected, &step.predicates));
+                }
+            }
+        }
+        // siblings share their parent with `..`
+        document_order(&mut next);
+        current = next;
+    }
+    if matches.is_empty() {
+        matches.extend(current.into_iter().map(XmlMatch::Node));
+    }
+    Ok(matches)
+}
+
+/// Sort the `nodes` in document order without duplicates, like an XPath
+/// node-set
+fn document_order(nodes: &mut Vec<Node>) {
+    nodes.sort_by_key(|node| node.id().get());
+    nodes.dedup_by_key(|node| node.id());
+}
+
+/// The trimmed text of each node, or value of each attribute, that the XPath
+/// `expr` selects
+pub fn xpath_values(context: Node, expr: &str) -> Result<Vec<String>, ExtractError> {
+    Ok(xpath(context, expr)?.iter().map(XmlMatch::value).collect())
+}
+
+fn filter_predicates<'a, 'input>(
+    nodes: impl Iterator<Item = Node<'a, 'input>>,
+    predicates: &[Predicate],
+) -> Vec<Node<'a, 'input>> {
+    let mut nodes: Vec<_> = nodes.collect();
+    for predicate in predicates {
+        nodes = match predicate {
+            Predicate::Position(pos) => nodes.get(pos - 1).copied().into_iter().collect(),
+            Predicate::HasAttr(attr) => nodes
+                .into_iter()
+                .filter(|node| node.has_attribute(attr.as_str()))
+                .collect(),
+            Predicate::AttrEq(attr, value) => nodes
+                .into_iter()
+                .filter(|node| node.attribute(attr.as_str()) == Some(value.as_str()))
+                .collect(),
+            Predicate::HasChild(name) => nodes
+                .into_iter()
+                .filter(|node| node.children().any(|child| name_matches(child, name)))
+                .collect(),
+            Predicate::ChildEq(name, value) => nodes
+                .into_iter()
+                .filter(|node| {
+                    node.children().any(|child| {
+                        name_matches(child, name) && XmlMatch::Node(child).value() == *value
+                    })
+                })
+                .collect(),
+        };
+    }
+    nodes
+}
+
+/// Split the `expr` at `/` outside of predicates
+fn split_steps(expr: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (idx, c) in expr.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            (None, '/') if depth == 0 => {
+                parts.push(&expr[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&expr[start..]);
+    parts
+}
+
+fn parse_xpath(expr: &str) -> Result<(bool, Vec<XStep>), ExtractError> {
+    let expr = expr.trim();
+    if expr.is_empty() {
+        return Err(invalid(expr, "empty XPath"));
+    }
+    let mut parts = split_steps(expr).into_iter().peekable();
+    let absolute = parts.peek() == Some(&"");
+    if absolute {
+        parts.next();
+    }
+
+    let mut steps = Vec::new();
+    let mut axis = Axis::Child;
+    while let Some(part) = parts.next() {
+        if part.is_empty() {
+            if axis == Axis::Descendant || parts.peek().is_none() {
+                return Err(invalid(expr, "expected a step"));
+            }
+            axis = Axis::Descendant;
+            continue;
+        }
+        steps.push(parse_xstep(expr, part.trim(), axis)?);
+        axis = Axis::Child;
+    }
+    if steps.is_empty() && !absolute {
+        return Err(invalid(expr, "expected a step"));
+    }
+    Ok((absolute, steps))
+}
+
+fn parse_xstep(expr: &str, part: &str, axis: Axis) -> Result<XStep, ExtractError> {
+    let (test, mut rest) = match part.find('[') {
+        Some(idx) => (&part[..idx], &part[idx..]),
+        None => (part, ""),
+    };
+    let test = match test.trim() {
+        "" => return Err(invalid(expr, "expected a name")),
+        "*" => NodeTest::Any,
+        "." => NodeTest::Current,
+        ".." => NodeTest::Parent,
+        "text()" => NodeTest::Text,
+        test => match test.strip_prefix('@') {
+            Some(attr) => NodeTest::Attr(attr.to_string()),
+            None => NodeTest::Name(test.to_string()),
+        },
+    };
+
+    let mut predicates = Vec::new();
+    while let Some(after) = rest.strip_prefix('[') {
+        let end = bracket_end(after).ok_or_else(|| invalid(expr, "unclosed `[`"))?;
+        predicates.push(parse_predicate(expr, after[..end].trim())?);
+        rest = after[end + 1..].trim_start();
+    }
+    if !rest.is_empty() {
+        return Err(invalid(expr, "unexpected characters after a predicate"));
+    }
+    Ok(XStep {
+        axis,
+        test,
+        predicates,
+    })
+}
+
+fn parse_predicate(expr: &str, content: &str) -> Result<Predicate, ExtractError> {
+    if let Ok(pos) = content.parse::<usize>() {
+        return if pos == 0 {
+            Err(invalid(expr, "positions start at 1"))
+        } else {
+            Ok(Predicate::Position(pos))
+        };
+    }
+    let (name, value) = match content.split_once('=') {
+        Some((name, value)) => {
+            let value =
+                unquote(value.trim()).ok_or_else(|| invalid(expr, "expected a quoted value"))?;
+            (name.trim(), Some(value.to_string()))
+        }
+        None => (content, None),
+    };
+    if name.is_empty() {
+        return Err(invalid(expr, "empty predicate"));
+    }
+    Ok(match (name.strip_prefix('@'), value) {
+        (Some(attr), None) => Predicate::HasAttr(attr.to_string()),
+        (Some(attr), Some(value)) => Predicate::AttrEq(attr.to_string(), value),
+        (None, None) => Predicate::HasChild(name.to_string()),
+        (None, Some(value)) => Predicate::ChildEq(name.to_string(), value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn query_json() {
+        let listing = json!({
+            "kind": "Listing",
+            "data": {"children": [
+                {"data": {"title": "first", "score": 10, "replies": {"data": {"title": "nested"}}}},
+                {"data": {"title": "second", "score": 3}},
+                {"data": {"title": "third", "score": 7}},
+            ]}
+        });
+        let titles = |path| -> Vec<&str> {
+            json_path(&listing, path)
+                .unwrap()
+                .into_iter()
+                .filter_map(Value::as_str)
+                .collect()
+        };
+        assert_eq!(
+            titles("$.data.children[*].data.title"),
+            ["first", "second", "third"]
+        );
+        assert_eq!(titles("$['data'].children[-1].data.title"), ["third"]);
+        assert_eq!(
+            titles("$.data.children[1:].data.title"),
+            ["second", "third"]
+        );
+        assert_eq!(titles("$..title"), ["first", "nested", "second", "third"]);
+        assert_eq!(titles("$.kind"), ["Listing"]);
+        assert!(titles("$.data.children[5].data").is_empty());
+
+        assert!(json_path(&listing, "data").is_err());
+        assert!(json_path(&listing, "$.data[0").is_err());
+        assert!(json_path(&listing, "$..").is_err());
+    }
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>Hacker News</title>
+    <atom:link href="https://news.ycombinator.com/rss" rel="self"/>
+    <item><title>First</title><link>https://a.example/</link><category>rust</category></item>
+    <item><title>Second</title><link>https://b.example/</link><category>go</category></item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    fn query_xml() {
+        let doc = roxmltree::Document::parse(FEED).unwrap();
+        let root = doc.root_element();
+        let values = |expr| xpath_values(root, expr).unwrap();
+
+        assert_eq!(values("/rss/channel/title"), ["Hacker News"]);
+        assert_eq!(values("//item/title"), ["First", "Second"]);
+        assert_eq!(values("//item[2]/link/text()"), ["https://b.example/"]);
+        assert_eq!(values("//item[category='rust']/title"), ["First"]);
+        assert_eq!(
+            values("channel/atom:link/@href"),
+            ["https://news.ycombinator.com/rss"]
+        );
+        assert_eq!(values("//link[@rel='self']/@href").len(), 1);
+        assert_eq!(values(".//item/title/..").len(), 2);
+
+        let items = xpath(root, "//item").unwrap();
+        let item = match items[1] {
+            XmlMatch::Node(node) => node,
+            XmlMatch::Attr(_) => panic!("expected a node"),
+        };
+        assert_eq!(xpath_values(item, "./title").unwrap(), ["Second"]);
+
+        // nested matching ancestors select each node once, in document order
+        let doc = roxmltree::Document::parse(
+            "<r><a><a><x><b>1</b></x></a><c><b>2</b></c></a><b>3</b></r>",
+        )
+        .unwrap();
+        assert_eq!(
+            xpath_values(doc.root_element(), "//a//b").unwrap(),
+            ["1", "2"]
+        );
+        assert_eq!(xpath_values(doc.root_element(), "//b/..").unwrap().len(), 3);
+
+        assert!(xpath(root, "//").is_err());
+        assert!(xpath(root, "//item[0]").is_err());
+        assert!(xpath(root, "//@href/title").is_err());
+    }
+}
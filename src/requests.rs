@@ -4,15 +4,25 @@ use futures_timer::Delay;
 use reqwest::header::HeaderMap;
 use reqwest::{StatusCode, Url};
 use std::collections::VecDeque;
+use std::fmt;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use crate::error::CrawlError;
+use crate::frontier::Ticket;
+use crate::retry::Attempt;
+use crate::throttle::AutoThrottle;
 
 /// 隊列狀態中的請求封裝
 pub struct QueuedRequest<T> {
     pub request: reqwest::Request,
     pub state: Option<T>,
     pub depth: usize,
+    /// 之前失敗的嘗試
+    pub attempts: Vec<Attempt>,
+    /// 記錄在檢查點日誌中的請求，完成時釋放
+    pub(crate) ticket: Option<Ticket>,
 }
 
 pub struct QueuedRequestBuilder<T> {
@@ -21,9 +31,38 @@ pub struct QueuedRequestBuilder<T> {
     pub depth: usize,
 }
 
+impl<T: fmt::Debug> QueuedRequestBuilder<T> {
+    /// 構建請求，失敗時返回攜帶狀態的錯誤
+    #[allow(clippy::result_large_err)]
+    pub fn build(self) -> Result<QueuedRequest<T>, CrawlError<T>> {
+        let QueuedRequestBuilder {
+            request,
+            state,
+            depth,
+        } = self;
+
+        match request.build() {
+            Ok(request) => Ok(QueuedRequest {
+                request,
+                state,
+                depth,
+                attempts: Vec::new(),
+                ticket: None,
+            }),
+            Err(error) => Err(CrawlError::FailedToBuildRequest {
+                error,
+                state,
+                depth,
+            }),
+        }
+    }
+}
+
 /// 請求隊列    
 pub struct RequestQueue<T> {
     delay: Option<(Delay, RequestDelay)>,
+    /// 上一個請求離開隊列的時間
+    sent: Option<Instant>,
     queued_requests: VecDeque<QueuedRequest<T>>,
 }
 
@@ -31,6 +70,7 @@ impl<T> RequestQueue<T> {
     pub fn with_delay(delay: RequestDelay) -> Self {
         Self {
             delay: Some((Delay::new(Duration::default()), delay)),
+            sent: None,
             queued_requests: Default::default(),
         }
     }
@@ -44,23 +84,47 @@ impl<T> RequestQueue<T> {
         self.delay.take().map(|(_, d)| d)
     }
 
-    /// 設置請求間隔的延時時間
+    /// 設置請求間隔的延時時間，正在等待的請求也使用新的延時
     pub fn set_delay(&mut self, mut delay: RequestDelay) -> Option<RequestDelay> {
-        if let Some((_, d)) = self.delay.as_mut() {
+        let remaining = self
+            .sent
+            .map(|sent| delay.next_delay().saturating_sub(sent.elapsed()))
+            .unwrap_or_default();
+        if let Some((timer, d)) = self.delay.as_mut() {
+            if *d != delay {
+                timer.reset(remaining);
+            }
             std::mem::swap(&mut delay, d);
             Some(delay)
         } else { 
-            self.delay = Some((Delay::new(Duration::default()), delay));
+            self.delay = Some((Delay::new(remaining), delay));
             None 
         }
     }
 
+    /// 確保請求間隔不小於 `min`，用於遵守 robots.txt 的 `Crawl-delay`
+    pub fn raise_delay(&mut self, min: Duration) -> Option<RequestDelay> {
+        let delay = self
+            .delay
+            .as_ref()
+            .map(|(_, d)| d.at_least(min))
+            .unwrap_or_else(|| RequestDelay::fixed(min));
+        self.set_delay(delay)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.queued_requests.is_empty()
     }
 
-    pub fn len(&self) -> usize {
-        self.queued_requests.len()
+    /// 隊列為空且上一個請求之後的延時已經結束時就緒
+    pub fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if !self.queued_requests.is_empty() {
+            return Poll::Pending;
+        }
+        match self.delay.as_mut() {
+            Some((delay, _)) => Pin::new(delay).poll(cx),
+            None => Poll::Ready(()),
+        }
     }
 }
 
@@ -83,22 +147,60 @@ impl<T: Unpin> Stream for RequestQueue<T> {
         } else {
             next = pin.queued_requests.pop_front();
         }
+        if next.is_some() {
+            pin.sent = Some(Instant::now());
+        }
 
         Poll::Ready(next)
     }
 }
 
+/// 等待重試的請求，在各自的延時結束後重新進入請求隊列
+pub struct RetryQueue<T> {
+    pending: Vec<(Delay, QueuedRequest<T>)>,
+}
+
+impl<T> RetryQueue<T> {
+    /// 在 `delay` 之後重試請求
+    pub fn push(&mut self, request: QueuedRequest<T>, delay: Duration) {
+        self.pending.push((Delay::new(delay), request));
+    }
+
+    /// 將所有延時結束的請求放回隊列的最前面
+    pub fn poll_requeue(&mut self, queue: &mut RequestQueue<T>, cx: &mut Context<'_>) {
+        for n in (0..self.pending.len()).rev() {
+            if Pin::new(&mut self.pending[n].0).poll(cx).is_ready() {
+                let (_, request) = self.pending.swap_remove(n);
+                queue.queue_mut().push_front(request);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<T> Default for RetryQueue<T> {
+    fn default() -> Self {
+        Self {
+            pending: Vec::new(),
+        }
+    }
+}
+
 impl<T> Default for RequestQueue<T> {
     fn default() -> Self {
         Self {
             delay: None,
+            sent: None,
             queued_requests: Default::default(),
         }
     }
 }
 
 /// Howto delay a request
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RequestDelay {
     /// 設置一個固定的時間端
     Fixed(Duration),
@@ -106,7 +208,9 @@ pub enum RequestDelay {
     Random {
         min: Duration,
         max: Duration,
-    }
+    },
+    /// 根據響應的延時與錯誤率自動調整，見 `AutoThrottle`
+    Adaptive(AutoThrottle),
 }
 
 impl RequestDelay {
@@ -125,6 +229,24 @@ impl RequestDelay {
         RequestDelay::Random { min, max }
     }
 
+    /// Adapt the delay and concurrency to the responses within the bounds of
+    /// the `throttle`
+    pub fn adaptive(throttle: AutoThrottle) -> Self {
+        RequestDelay::Adaptive(throttle)
+    }
+
+    /// The delay that waits at least `min` between two requests
+    pub fn at_least(&self, min: Duration) -> Self {
+        match *self {
+            RequestDelay::Fixed(delay) => RequestDelay::Fixed(delay.max(min)),
+            RequestDelay::Random { min: lower, max } => RequestDelay::Random {
+                min: lower.max(min),
+                max: max.max(min),
+            },
+            RequestDelay::Adaptive(throttle) => RequestDelay::Adaptive(throttle.at_least(min)),
+        }
+    }
+
     pub fn next_delay(&self) -> Duration {
         use rand::Rng;
 
@@ -133,6 +255,7 @@ impl RequestDelay {
             RequestDelay::Random { min, max } => Duration::from_millis(
                 rand::thread_rng().gen_range(min.as_millis() as u64..=max.as_millis() as u64)
             ),
+            RequestDelay::Adaptive(throttle) => throttle.initial_delay(),
         }
     }
 }
@@ -143,3 +266,41 @@ pub(crate) fn response_info(resp: &mut reqwest::Response) -> (StatusCode, Url, H
     (resp.status(), resp.url().clone(), headers)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str) -> QueuedRequest<()> {
+        QueuedRequest {
+            request: reqwest::Request::new(reqwest::Method::GET, url.parse().unwrap()),
+            state: None,
+            depth: 0,
+            attempts: Vec::new(),
+            ticket: None,
+        }
+    }
+
+    /// The next request of the `queue`, once its delay is over
+    async fn next(queue: &mut RequestQueue<()>) -> QueuedRequest<()> {
+        let next = futures::future::poll_fn(|cx| match Pin::new(&mut *queue).poll_next(cx) {
+            Poll::Ready(Some(req)) => Poll::Ready(req),
+            _ => Poll::Pending,
+        });
+        tokio::time::timeout(Duration::from_secs(5), next)
+            .await
+            .expect("the delay is over")
+    }
+
+    #[tokio::test]
+    async fn apply_a_new_delay_to_the_waiting_request() {
+        let mut queue = RequestQueue::with_delay(RequestDelay::fixed(Duration::from_secs(60)));
+        queue.queue_mut().push_back(request("https://example.com/1"));
+        queue.queue_mut().push_back(request("https://example.com/2"));
+        next(&mut queue).await;
+
+        queue.set_delay(RequestDelay::fixed(Duration::ZERO));
+        let req = next(&mut queue).await;
+        assert_eq!(req.request.url().path(), "/2");
+    }
+}
@@ -1,8 +1,27 @@
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use encoding_rs::Encoding;
 use reqwest::header::HeaderMap;
 use reqwest::{StatusCode, Url};
-use scraper::Html;
+use scraper::{ElementRef, Html, Selector};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::charset::{self, EncodingSource};
+use crate::error::ExtractError;
+use crate::feed::Feed;
+use crate::frontier::Ticket;
+use crate::query;
 
-/// A successful response for an issued request
+/// A successful response for an issued request.
+///
+/// Besides the public fields a response holds the encoding of the body, the
+/// lazily decoded text and the journal ticket of its request, so it can't be
+/// built with a struct literal outside of this crate, use `Response::new`
+/// instead.
+#[non_exhaustive]
 pub struct Response<T> {
     /// The depth of the request that was issued for this
     pub depth: usize,
@@ -14,15 +33,426 @@ pub struct Response<T> {
     pub response_status: StatusCode,
     /// The headers of the received response
     pub response_headers: HeaderMap,
-    /// The full response text.
-    pub text: String,
+    /// The raw body, empty if the body is streamed, see `take_body_stream`
+    pub body: Bytes,
+    /// The encoding of the body and where it was found
+    pub(crate) charset: (&'static Encoding, EncodingSource),
+    /// The body decoded on first access
+    pub(crate) text: OnceLock<String>,
+    /// The body of a large response that is read on demand
+    pub(crate) stream: Option<BodyStream>,
     /// The attached state of the scraper
     pub state: Option<T>,
+    /// Marks the request as finished in the checkpoint journal once the
+    /// response is dropped
+    #[allow(dead_code)]
+    pub(crate) ticket: Option<Ticket>,
 }
 
 impl<T> Response<T> {
-    /// Returns the parsed Html document
+    /// A response of the `url` with the `body` and without state
+    pub fn new(url: Url, status: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+        let (encoding, source) = charset::detect(&headers, &body, Some(&url));
+        Response {
+            depth: 0,
+            request_url: url.clone(),
+            response_url: url,
+            response_status: status,
+            response_headers: headers,
+            charset: (encoding, source),
+            text: Default::default(),
+            body,
+            stream: None,
+            state: None,
+            ticket: None,
+        }
+    }
+
+    /// The body decoded as text with the detected `encoding`, it is only
+    /// decoded once.
+    ///
+    /// Empty if the body is streamed.
+    pub fn text(&self) -> &str {
+        self.text
+            .get_or_init(|| self.charset.0.decode(&self.body).0.into_owned())
+    }
+
+    /// The encoding of the body, see `charset::detect`
+    pub fn encoding(&self) -> &'static Encoding {
+        self.charset.0
+    }
+
+    /// Where the `encoding` of the body was found
+    pub fn encoding_source(&self) -> EncodingSource {
+        self.charset.1
+    }
+
+    /// Whether the body is streamed instead of buffered in `body`
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Take the body of a streamed response, see
+    /// `CrawlerConfig::stream_bodies_over`
+    pub fn take_body_stream(&mut self) -> Option<BodyStream> {
+        self.stream.take()
+    }
+
+    /// Returns the parsed Html document, it is parsed on every call
     pub fn html(&self) -> Html {
-        Html::parse_document(&self.text)
+        Html::parse_document(self.text())
+    }
+
+    /// Parse the html document once to select several elements of it
+    pub fn parse(&self) -> ParsedResponse<'_, T> {
+        ParsedResponse {
+            html: self.html(),
+            response: self,
+        }
+    }
+
+    /// Deserialize the body as json
+    pub fn json<D: DeserializeOwned>(&self) -> serde_json::Result<D> {
+        let body = self
+            .body
+            .strip_prefix(b"\xef\xbb\xbf")
+            .unwrap_or(&self.body);
+        serde_json::from_slice(body)
+    }
+
+    /// The values of the json body that the JSONPath `path` selects, see
+    /// `query::json_path`
+    pub fn json_path(&self, path: &str) -> Result<Vec<Value>, ExtractError> {
+        let root: Value = self.json()?;
+        Ok(query::json_path(&root, path)?
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    /// Parse the body as xml document
+    pub fn xml(&self) -> Result<roxmltree::Document<'_>, ExtractError> {
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        Ok(roxmltree::Document::parse_with_options(
+            self.text(),
+            options,
+        )?)
+    }
+
+    /// The text of each node, or value of each attribute, of the xml body
+    /// that the XPath `expr` selects, see `query::xpath`
+    pub fn xpath(&self, expr: &str) -> Result<Vec<String>, ExtractError> {
+        let doc = self.xml()?;
+        query::xpath_values(doc.root_element(), expr)
+    }
+
+    /// Parse the body as RSS or Atom feed
+    pub fn feed(&self) -> Result<Feed, ExtractError> {
+        Feed::parse(self.text())
+    }
+}
+
+#[cfg(test)]
+impl<T> Response<T> {
+    /// A `200 OK` response with the `html` as body
+    pub(crate) fn from_html(url: &str, html: &str) -> Self {
+        let mut response = Response::new(
+            Url::parse(url).unwrap(),
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::copy_from_slice(html.as_bytes()),
+        );
+        response.depth = 1;
+        response
+    }
+}
+
+/// A `Response` with its parsed html document, see `Response::parse`.
+///
+/// The document is not `Send`, so parse it where the elements are selected
+/// instead of keeping it across an `.await`.
+pub struct ParsedResponse<'a, T> {
+    response: &'a Response<T>,
+    html: Html,
+}
+
+impl<'a, T> ParsedResponse<'a, T> {
+    /// The parsed Html document
+    pub fn html(&self) -> &Html {
+        &self.html
+    }
+
+    /// The response the document was parsed from
+    pub fn response(&self) -> &'a Response<T> {
+        self.response
+    }
+
+    /// All elements that match the css `selector`
+    pub fn select_all(&self, selector: &str) -> Result<Vec<ElementRef<'_>>, ExtractError> {
+        let parsed = parse_selector(selector)?;
+        Ok(self.html().select(&parsed).collect())
+    }
+
+    /// The first element that matches the css `selector`
+    pub fn select_first(&self, selector: &str) -> Result<ElementRef<'_>, ExtractError> {
+        let parsed = parse_selector(selector)?;
+        self.html()
+            .select(&parsed)
+            .next()
+            .ok_or_else(|| ExtractError::NoMatch(selector.to_string()))
+    }
+
+    /// The trimmed text of the first element that matches the css `selector`
+    pub fn select_text(&self, selector: &str) -> Result<String, ExtractError> {
+        let el = self.select_first(selector)?;
+        Ok(el.text().collect::<String>().trim().to_string())
+    }
+
+    /// The attribute `attr` of the first element that matches the css
+    /// `selector`
+    pub fn select_attr(&self, selector: &str, attr: &str) -> Result<String, ExtractError> {
+        self.select_first(selector)?
+            .value()
+            .attr(attr)
+            .map(str::to_string)
+            .ok_or_else(|| ExtractError::MissingAttribute {
+                selector: selector.to_string(),
+                attr: attr.to_string(),
+            })
+    }
+
+    /// The url in the attribute `attr` of the first element that matches the
+    /// css `selector`, resolved with `absolute_url`
+    pub fn select_url(&self, selector: &str, attr: &str) -> Result<Url, ExtractError> {
+        self.absolute_url(&self.select_attr(selector, attr)?)
     }
-}
\ No newline at end of file
+
+    /// The url the document's links are relative to, the `<base href>` if it
+    /// has one and `response_url` otherwise
+    pub fn base_url(&self) -> Url {
+        let base = Selector::parse("base[href]").unwrap();
+        self.html()
+            .select(&base)
+            .next()
+            .and_then(|el| el.value().attr("href"))
+            .and_then(|href| self.response_url.join(href.trim()).ok())
+            .unwrap_or_else(|| self.response_url.clone())
+    }
+
+    /// Resolve the `href` against the `base_url` of the document
+    pub fn absolute_url(&self, href: &str) -> Result<Url, ExtractError> {
+        let base = self.base_url();
+        base.join(href.trim())
+            .map_err(|error| ExtractError::InvalidUrl {
+                href: href.to_string(),
+                base,
+                error,
+            })
+    }
+}
+
+impl<T> Deref for ParsedResponse<'_, T> {
+    type Target = Response<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.response
+    }
+}
+
+/// Parse the css `selector`
+fn parse_selector(selector: &str) -> Result<Selector, ExtractError> {
+    Selector::parse(selector)
+        .map_err(|err| ExtractError::InvalidSelector(selector.to_string(), format!("{:?}", err)))
+}
+
+/// The body of a response that is read chunk by chunk instead of buffered
+#[derive(Debug)]
+pub struct BodyStream {
+    /// The start of the body that was read before it was streamed
+    read: Option<Bytes>,
+    resp: reqwest::Response,
+}
+
+impl BodyStream {
+    /// The next chunk of the body, `None` once the body is read completely
+    pub async fn chunk(&mut self) -> reqwest::Result<Option<Bytes>> {
+        match self.read.take() {
+            Some(read) => Ok(Some(read)),
+            None => self.resp.chunk().await,
+        }
+    }
+
+    /// The length of the body, if the server declared it
+    pub fn content_length(&self) -> Option<u64> {
+        self.resp.content_length()
+    }
+}
+
+/// How response bodies are read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BodyLimits {
+    /// Larger bodies are rejected with `CrawlError::BodyTooLarge`
+    pub max_size: Option<usize>,
+    /// Larger bodies are streamed instead of buffered
+    pub stream_over: Option<usize>,
+}
+
+/// The body of a response as read by `read_body`
+pub(crate) enum Body {
+    Bytes(Bytes),
+    Stream(BodyStream),
+    /// The body exceeds `BodyLimits::max_size`
+    TooLarge,
+}
+
+/// Read the body of the `resp` according to the `limits`.
+///
+/// A body is streamed if its `Content-Length` is over the limit, or once the
+/// bytes read so far are.
+pub(crate) async fn read_body(
+    mut resp: reqwest::Response,
+    limits: BodyLimits,
+) -> reqwest::Result<Body> {
+    let length = resp.content_length();
+    let exceeds = |limit: Option<usize>, len: u64| limit.is_some_and(|limit| len > limit as u64);
+
+    if let Some(len) = length {
+        if exceeds(limits.max_size, len) {
+            return Ok(Body::TooLarge);
+        }
+        if exceeds(limits.stream_over, len) {
+            return Ok(Body::Stream(BodyStream { read: None, resp }));
+        }
+    }
+
+    let capacity = length.unwrap_or_default().min(1 << 20) as usize;
+    let mut body = Vec::with_capacity(capacity);
+    while let Some(chunk) = resp.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if exceeds(limits.max_size, body.len() as u64) {
+            return Ok(Body::TooLarge);
+        }
+        if exceeds(limits.stream_over, body.len() as u64) {
+            let read = Some(body.into());
+            return Ok(Body::Stream(BodyStream { read, resp }));
+        }
+    }
+    Ok(Body::Bytes(body.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(html: &str) -> Response<()> {
+        Response::from_html("https://book.douban.com/subject/1/", html)
+    }
+
+    fn assert_send<S: Send>(_: &S) {}
+
+    #[test]
+    fn extract_with_selectors() {
+        let resp = response(
+            r#"<h1><span> 三體 </span></h1>
+            <strong class="rating_num">8.8</strong>
+            <a class="cover" href="/subject/2/">a</a><a class="cover" href="https://x.org/b">b</a>"#,
+        );
+        // the response moves between threads, the parsed document doesn't
+        assert_send(&resp);
+        let resp = resp.parse();
+        assert_eq!(resp.select_text("h1 span").unwrap(), "三體");
+        assert_eq!(resp.select_attr("a.cover", "href").unwrap(), "/subject/2/");
+        assert_eq!(
+            resp.select_url("a.cover", "href").unwrap().as_str(),
+            "https://book.douban.com/subject/2/"
+        );
+        assert_eq!(resp.select_all("a.cover").unwrap().len(), 2);
+
+        assert!(matches!(
+            resp.select_text("h2"),
+            Err(ExtractError::NoMatch(_))
+        ));
+        assert!(matches!(
+            resp.select_attr("h1", "id"),
+            Err(ExtractError::MissingAttribute { .. })
+        ));
+        assert!(matches!(
+            resp.select_all("h1["),
+            Err(ExtractError::InvalidSelector(..))
+        ));
+    }
+
+    #[test]
+    fn resolve_against_base() {
+        let resp = response(r#"<head><base href="/mirror/"></head><a href="page">x</a>"#);
+        let resp = resp.parse();
+        assert_eq!(
+            resp.absolute_url("page").unwrap().as_str(),
+            "https://book.douban.com/mirror/page"
+        );
+        assert!(resp.absolute_url("http://[::1").is_err());
+    }
+
+    #[test]
+    fn json_and_xml_bodies() {
+        let resp = response(r#"{"data": {"children": [{"data": {"title": "Rust"}}]}}"#);
+        let titles = resp.json_path("$.data.children[*].data.title").unwrap();
+        assert_eq!(titles, [Value::from("Rust")]);
+        assert!(resp.xml().is_err());
+
+        let resp = response("<rss><channel><item><title>Rust</title></item></channel></rss>");
+        assert_eq!(resp.xpath("//item/title").unwrap(), ["Rust"]);
+        assert_eq!(resp.feed().unwrap().items.len(), 1);
+        assert!(resp.json::<Value>().is_err());
+    }
+
+    #[test]
+    fn decode_text_on_first_access() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            "text/plain; charset=gbk".parse().unwrap(),
+        );
+        let url = Url::parse("https://example.com/").unwrap();
+        let body = Bytes::from_static(b"\xc8\xfd\xf3\x77");
+        let resp = Response::<()>::new(url, StatusCode::OK, headers, body);
+        assert!(resp.text.get().is_none());
+        assert_eq!(resp.text(), "三體");
+        assert_eq!(resp.encoding(), encoding_rs::GBK);
+    }
+
+    #[tokio::test]
+    async fn stream_bodies_without_length() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 1024];
+            assert!(stream.read(&mut buf).await.unwrap() > 0);
+            let resp = "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+                        4\r\nabcd\r\n4\r\nefgh\r\n4\r\nijkl\r\n0\r\n\r\n";
+            stream.write_all(resp.as_bytes()).await.unwrap();
+        });
+
+        let resp = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(resp.content_length(), None);
+        let limits = BodyLimits {
+            max_size: None,
+            stream_over: Some(6),
+        };
+        let mut stream = match read_body(resp, limits).await.unwrap() {
+            Body::Stream(stream) => stream,
+            _ => panic!("expected a streamed body"),
+        };
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.chunk().await.unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+        assert_eq!(body, b"abcdefghijkl");
+    }
+}
@@ -0,0 +1,296 @@
+//! Retrying requests that failed for transient reasons.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+use crate::fetch::FetchErrorKind;
+
+/// Decides whether and when a failed request is sent again.
+///
+/// A request is retried if the server answered with one of the retryable
+/// statuses or the connection failed, was reset or timed out. The delay
+/// between two attempts grows exponentially, starting at `base_delay` and
+/// capped at `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// How often a request is sent at most, including the first attempt
+    max_attempts: usize,
+    /// The delay before the first retry
+    base_delay: Duration,
+    /// The upper bound of the backoff, a request whose `Retry-After` is longer
+    /// is not retried
+    max_delay: Duration,
+    /// Randomize the delay between half and the full backoff
+    jitter: bool,
+    /// Response statuses that are retried
+    retry_statuses: HashSet<u16>,
+    /// Retry if the connection could not be established or was reset
+    retry_connect_errors: bool,
+    /// Retry if the request timed out
+    retry_timeouts: bool,
+    /// Wait at least as long as the `Retry-After` header of the response says
+    respect_retry_after: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            retry_statuses: [408, 429, 500, 502, 503, 504].into_iter().collect(),
+            retry_connect_errors: true,
+            retry_timeouts: true,
+            respect_retry_after: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy that sends a request at most `max_attempts` times
+    pub fn new(max_attempts: usize) -> Self {
+        Self::default().max_attempts(max_attempts)
+    }
+
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the delay before the first retry and the upper bound of all delays
+    pub fn backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay.max(base_delay);
+        self
+    }
+
+    /// Always wait the full backoff
+    pub fn without_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    /// Also retry responses with the `status`
+    pub fn retry_status(mut self, status: u16) -> Self {
+        self.retry_statuses.insert(status);
+        self
+    }
+
+    /// Only retry responses with one of the `statuses`
+    pub fn retry_statuses(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.retry_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Don't retry requests whose connection could not be established or was
+    /// reset
+    pub fn no_retry_on_connect_error(mut self) -> Self {
+        self.retry_connect_errors = false;
+        self
+    }
+
+    /// Don't retry requests that timed out
+    pub fn no_retry_on_timeout(mut self) -> Self {
+        self.retry_timeouts = false;
+        self
+    }
+
+    /// Ignore the `Retry-After` header and only use the backoff
+    pub fn ignore_retry_after(mut self) -> Self {
+        self.respect_retry_after = false;
+        self
+    }
+
+    /// How often a request is sent at most
+    pub fn attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Whether a response with this status is retried
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_statuses.contains(&status.as_u16())
+    }
+
+    /// Whether a request that failed with `error` is retried
+    pub fn is_retryable_error(&self, error: &reqwest::Error) -> bool {
+        self.is_retryable_kind(FetchErrorKind::of_reqwest(error))
+    }
+
+    /// Whether a request whose `Fetcher` failed with `error` is retried, see
+    /// `FetchErrorKind::of`
+    pub fn is_retryable_fetch_error(&self, error: &anyhow::Error) -> bool {
+        self.is_retryable_kind(FetchErrorKind::of(error))
+    }
+
+    fn is_retryable_kind(&self, kind: FetchErrorKind) -> bool {
+        match kind {
+            FetchErrorKind::Connect | FetchErrorKind::Reset => self.retry_connect_errors,
+            FetchErrorKind::Timeout => self.retry_timeouts,
+            FetchErrorKind::Other => false,
+        }
+    }
+
+    /// The delay after the `attempt`th failed attempt, starting at 1.
+    ///
+    /// `headers` are the headers of the failed response, if any. A
+    /// `Retry-After` is never shortened, if it is longer than `max_delay` the
+    /// request is not retried and `None` is returned.
+    pub fn delay(&self, attempt: usize, headers: Option<&HeaderMap>) -> Option<Duration> {
+        let exp = attempt.saturating_sub(1).min(31) as u32;
+        let mut delay = self
+            .base_delay
+            .checked_mul(1 << exp)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if self.jitter && !delay.is_zero() {
+            use rand::Rng;
+            let millis = delay.as_millis() as u64;
+            delay = Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis));
+        }
+
+        if self.respect_retry_after {
+            if let Some(retry_after) = headers.and_then(retry_after) {
+                if retry_after > self.max_delay {
+                    return None;
+                }
+                delay = delay.max(retry_after);
+            }
+        }
+
+        Some(delay)
+    }
+}
+
+/// Whether the connection of the `error` was reset or closed by the server
+pub(crate) fn is_connection_reset(error: &reqwest::Error) -> bool {
+    use std::error::Error;
+    use std::io::ErrorKind;
+
+    let mut source = error.source();
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            return matches!(
+                err.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+            );
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Parse the `Retry-After` header, either in seconds or as http date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// A failed attempt to fetch a page
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// The status of the response, if one was received
+    pub status: Option<StatusCode>,
+    /// The error that occurred, if no response was received
+    pub error: Option<String>,
+    /// How long was waited before the next attempt, zero for the last attempt
+    pub delay: Duration,
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.status, &self.error) {
+            (Some(status), _) => write!(f, "status {}", status)?,
+            (None, Some(error)) => write!(f, "{}", error)?,
+            (None, None) => write!(f, "unknown error")?,
+        }
+        if !self.delay.is_zero() {
+            write!(f, " (retried after {:?})", self.delay)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = RetryPolicy::new(5)
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .without_jitter();
+        assert_eq!(policy.delay(1, None), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay(2, None), Some(Duration::from_millis(200)));
+        assert_eq!(policy.delay(3, None), Some(Duration::from_millis(400)));
+        assert_eq!(policy.delay(5, None), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(100, None), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let policy =
+            RetryPolicy::new(3).backoff(Duration::from_millis(400), Duration::from_secs(1));
+        for _ in 0..20 {
+            let delay = policy.delay(1, None).unwrap();
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn honors_retry_after() {
+        let policy = RetryPolicy::new(3)
+            .backoff(Duration::from_millis(100), Duration::from_secs(10))
+            .without_jitter();
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        assert_eq!(policy.delay(1, Some(&headers)), Some(Duration::from_secs(3)));
+
+        // a longer Retry-After gives up instead of retrying too early
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(policy.delay(1, Some(&headers)), None);
+
+        let policy = policy.ignore_retry_after();
+        assert_eq!(
+            policy.delay(1, Some(&headers)),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_reset_connections() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // closing the socket with an unread request resets the connection
+            stream.readable().await.unwrap();
+        });
+        let err = reqwest::get(format!("http://{}/", addr)).await.unwrap_err();
+        assert!(RetryPolicy::default().is_retryable_error(&err));
+        assert!(!RetryPolicy::default()
+            .no_retry_on_connect_error()
+            .is_retryable_error(&err));
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(policy.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+    }
+}
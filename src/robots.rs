@@ -1,10 +1,22 @@
 use crate::error::UnexpectedStatusError;
+use crate::fetch::Fetcher;
+use crate::retry::RetryPolicy;
 use anyhow::Result;
-use reqwest::header::USER_AGENT;
+use futures_timer::Delay;
+use reqwest::header::{HeaderValue, InvalidHeaderValue, LOCATION, USER_AGENT};
+use reqwest::{Method, Request, StatusCode, Url};
 use robotstxt::matcher::{LongestMatchRobotsMatchStrategy, RobotsMatchStrategy};
 use robotstxt::{get_path_params_query, parse_robotstxt, RobotsParseHandler};
 use std::collections::{HashMap, HashSet};
-use std::time::Duration;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How much of a robots.txt is parsed, the rest is ignored
+pub const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+
+/// The longest `Crawl-delay` that is applied, longer delays are clamped to it
+pub const MAX_CRAWL_DELAY: Duration = Duration::from_secs(1000);
 
 /// The handler that parses the `robots.txt` into a `RobotsData`
 #[derive(Debug, Clone, Default)]
@@ -15,6 +27,8 @@ pub struct RobotsHandler {
     group: Option<Group>,
     /// the list of agents for the current group that is currently processed
     agents: Option<HashSet<String>>,
+    /// the urls of all `Sitemap:` lines, they don't belong to any group
+    sitemaps: Vec<Url>,
 }
 
 impl RobotsHandler {
@@ -22,10 +36,8 @@ impl RobotsHandler {
         let status_code = resp.status().as_u16();
 
         if (200..300).contains(&status_code) {
-            let txt = resp.text().await?;
-            let mut handler = RobotsHandler::default();
-            parse_robotstxt(&txt, &mut handler);
-            return Ok(handler.finish());
+            let body = read_capped(resp, MAX_ROBOTS_SIZE).await?;
+            return Ok(RobotsHandler::parse(&body));
         }
 
         // See https://developers.google.com/webmasters/control-crawl-index/docs/robots_txt
@@ -49,6 +61,13 @@ impl RobotsHandler {
         Err(UnexpectedStatusError::new(status_code).into())
     }
 
+    /// Parse the `body` of a robots.txt
+    pub fn parse(body: &[u8]) -> RobotsData {
+        let mut handler = RobotsHandler::default();
+        parse_robotstxt(&String::from_utf8_lossy(body), &mut handler);
+        handler.finish()
+    }
+
     fn finish_group(&mut self) {
         if let Some(group) = self.group.take() {
             self.groups
@@ -61,6 +80,18 @@ impl RobotsHandler {
         let mut group_agents =
             HashMap::with_capacity(self.groups.iter().map(|(a, _)| a.len()).sum());
         for (idx, (agents, group)) in self.groups.into_iter().enumerate() {
+            // `FooBot/1.2` and `foobot` name the same crawler
+            let agents: HashSet<String> = agents
+                .iter()
+                .map(|agent| {
+                    if agent.starts_with('*') {
+                        "*".to_string()
+                    } else {
+                        product_token(agent)
+                    }
+                })
+                .filter(|agent| !agent.is_empty())
+                .collect();
             for agent in agents {
                 let agent_groups = group_agents
                     .entry(agent)
@@ -75,6 +106,7 @@ impl RobotsHandler {
             group_agents,
             allow_all: false,
             disallow_all: false,
+            sitemaps: self.sitemaps,
         }
     }
 }
@@ -107,20 +139,30 @@ impl RobotsParseHandler for RobotsHandler {
         }
     }
 
-    fn handle_sitemap(&mut self, _: u32, _: &str) {}
+    fn handle_sitemap(&mut self, _: u32, value: &str) {
+        if let Ok(url) = Url::parse(value) {
+            self.sitemaps.push(url);
+        }
+    }
 
     fn handle_unknown_action(&mut self, _: u32, action: &str, value: &str) {
-        if let Some(group) = self.group.as_mut() {
-            match action.to_lowercase().as_str() {
-                "crawldelay" | "crawl-delay" => {
-                    if let Ok(sec) = value.parse::<u64>() {
-                        group.crawl_delay = Some(Duration::from_secs(sec % 1000));
-                    } else if let Ok(sec) = value.parse::<f64>() {
-                        group.crawl_delay = Some(Duration::from_millis((sec * 1000.) as u64));
-                    }
-                }
-                _ => {}
+        // a `crawl-delay` may precede any allow/disallow line of its group
+        if self.agents.is_none() {
+            return;
+        }
+        match action.to_lowercase().as_str() {
+            "crawldelay" | "crawl-delay" => {
+                let group = self.group.get_or_insert(Group::default());
+                let delay = if let Ok(sec) = value.parse::<u64>() {
+                    Duration::from_secs(sec)
+                } else if let Ok(sec) = value.parse::<f64>() {
+                    Duration::from_millis((sec * 1000.) as u64)
+                } else {
+                    return;
+                };
+                group.crawl_delay = Some(delay.min(MAX_CRAWL_DELAY));
             }
+            _ => {}
         }
     }
 }
@@ -130,12 +172,15 @@ impl RobotsParseHandler for RobotsHandler {
 pub struct RobotsData {
     /// All the groups in the `robots.txt`
     pub groups: Vec<Group>,
-    /// Mapping of all user-agents to all their groups
+    /// Mapping of the lowercase product tokens of all user-agents to all their
+    /// groups
     pub group_agents: HashMap<String, Vec<usize>>,
     /// Whether to allow all url patterns for all user agents
     pub allow_all: bool,
     /// disallow all url patterns for all user agents
     pub disallow_all: bool,
+    /// The sitemaps declared with `Sitemap:` lines
+    pub sitemaps: Vec<Url>,
 }
 
 impl RobotsData {
@@ -146,6 +191,7 @@ impl RobotsData {
             group_agents: Default::default(),
             allow_all: true,
             disallow_all: false,
+            sitemaps: Vec::new(),
         }
     }
 
@@ -156,6 +202,7 @@ impl RobotsData {
             group_agents: Default::default(),
             allow_all: false,
             disallow_all: true,
+            sitemaps: Vec::new(),
         }
     }
 
@@ -163,40 +210,654 @@ impl RobotsData {
     ///
     /// This does *NOT* mean that it is explicitly allowed
     pub fn is_not_disallowed(&self, request: &reqwest::Request) -> bool {
-        if self.disallow_all {
-            return false;
-        }
-        if self.allow_all {
-            return true;
-        }
-
-        let path = get_path_params_query(request.url().path());
-
-        // if the requests user-agent is not contained in the robots agent list, use the
-        // wildcard instead
-        let agent = request
-            .headers()
-            .get(USER_AGENT)
-            .and_then(|agent| agent.to_str().ok())
-            .unwrap_or("*");
-
-        // check if a rule explicitly disallows access, in other words: if there exists
-        // a rule that disallows access for the url's path, then `is_disallowed == true`
-        let is_disallowed = self
-            .group_agents
-            .get(agent)
-            .map(|groups| {
-                groups
-                    .iter()
-                    .copied()
-                    .map(|i| &self.groups[i])
-                    .flat_map(|g| g.rules.iter())
-                    .filter(|rule| rule.is_disallow())
-                    .any(|rule| LongestMatchRobotsMatchStrategy::matches(&path, &rule.pattern))
+        self.check(request.url(), request_agent(request)).allowed
+    }
+
+    /// Decide whether the `agent` may crawl the `url` and which rule decided,
+    /// following RFC 9309.
+    ///
+    /// The `agent` is a product token like `FooBot`, or a `User-Agent` header
+    /// like `FooBot/2.1 (+https://example.com/bot)` whose leading product
+    /// token is used. The groups of that token are matched case insensitively,
+    /// the wildcard `*` groups are only used if it has none. The rule with the
+    /// longest matching pattern wins, on a tie `Allow` wins over `Disallow`.
+    /// Urls that match no rule are allowed, so is `/robots.txt` itself.
+    pub fn check(&self, url: &Url, agent: &str) -> Decision {
+        if self.disallow_all || self.allow_all {
+            return Decision {
+                allowed: self.allow_all,
+                matched_rule: None,
+                group: None,
+            };
+        }
+
+        let groups = self.agent_groups(agent);
+        if groups.is_empty() || url.path() == "/robots.txt" {
+            return Decision::allow(groups.first().copied());
+        }
+
+        let path = get_path_params_query(url.as_str());
+        let strategy = LongestMatchRobotsMatchStrategy;
+        let mut best: Option<(i32, usize, &Rule)> = None;
+        for &idx in groups {
+            for rule in &self.groups[idx].rules {
+                let priority = if rule.allow {
+                    strategy.match_allow(&path, &rule.pattern)
+                } else {
+                    strategy.match_disallow(&path, &rule.pattern)
+                };
+                if priority < 0 {
+                    continue;
+                }
+                let wins = match best {
+                    None => true,
+                    Some((best_priority, _, best_rule)) => {
+                        priority > best_priority
+                            || (priority == best_priority && rule.allow && !best_rule.allow)
+                    }
+                };
+                if wins {
+                    best = Some((priority, idx, rule));
+                }
+            }
+        }
+
+        match best {
+            Some((_, group, rule)) => Decision {
+                allowed: rule.allow,
+                matched_rule: Some(rule.clone()),
+                group: Some(group),
+            },
+            None => Decision::allow(groups.first().copied()),
+        }
+    }
+
+    /// The `Crawl-delay` of the groups that apply to the request's user-agent.
+    ///
+    /// Falls back to the wildcard `*` group if the agent has no group of its
+    /// own. If several groups match, the longest delay is returned.
+    pub fn crawl_delay(&self, request: &reqwest::Request) -> Option<Duration> {
+        self.crawl_delay_for(request_agent(request))
+    }
+
+    /// The `Crawl-delay` that applies to the `agent`, see `RobotsData::check`
+    /// for how the groups of the agent are selected
+    pub fn crawl_delay_for(&self, agent: &str) -> Option<Duration> {
+        if self.allow_all || self.disallow_all {
+            return None;
+        }
+
+        self.agent_groups(agent)
+            .iter()
+            .filter_map(|&i| self.groups[i].crawl_delay)
+            .max()
+    }
+
+    /// The indices of the groups of the `agent`'s product token, or of the
+    /// `*` groups if it has none
+    fn agent_groups(&self, agent: &str) -> &[usize] {
+        let token = product_token(agent);
+        self.group_agents
+            .get(&token)
+            .filter(|_| !token.is_empty())
+            .or_else(|| self.group_agents.get("*"))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// The lowercase product token of a user-agent, the leading `[a-zA-Z_-]`
+/// characters: `FooBot/2.1` becomes `foobot`
+fn product_token(agent: &str) -> String {
+    let end = agent
+        .find(|c: char| !(c.is_ascii_alphabetic() || c == '-' || c == '_'))
+        .unwrap_or(agent.len());
+    agent[..end].to_ascii_lowercase()
+}
+
+/// The user-agent of the request, `*` if it has none
+pub(crate) fn request_agent(request: &reqwest::Request) -> &str {
+    request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .unwrap_or("*")
+}
+
+/// The identity of the crawler, like `ourbot/1.2 (+https://example.com/bot)`.
+///
+/// It is sent as `User-Agent` header and its product token, `ourbot`, selects
+/// the robots.txt groups the crawler obeys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAgent {
+    /// The name of the crawler
+    product: String,
+    version: Option<String>,
+    /// A page that describes the crawler
+    info_url: Option<String>,
+}
+
+impl UserAgent {
+    pub fn new(product: impl Into<String>) -> Self {
+        Self {
+            product: product.into(),
+            version: None,
+            info_url: None,
+        }
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Link a page that describes the crawler
+    pub fn info_url(mut self, url: impl Into<String>) -> Self {
+        self.info_url = Some(url.into());
+        self
+    }
+
+    /// The token that is matched against the `User-agent` lines of a
+    /// robots.txt
+    pub fn product_token(&self) -> String {
+        product_token(&self.product)
+    }
+
+    /// The value of the `User-Agent` header
+    pub fn header_value(&self) -> Result<HeaderValue, InvalidHeaderValue> {
+        HeaderValue::from_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for UserAgent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.product)?;
+        if let Some(version) = &self.version {
+            write!(f, "/{}", version)?;
+        }
+        if let Some(url) = &self.info_url {
+            write!(f, " (+{})", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of matching an url against a `robots.txt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    /// Whether the url may be crawled
+    pub allowed: bool,
+    /// The rule that decided, `None` if no rule matched
+    pub matched_rule: Option<Rule>,
+    /// The index into `RobotsData::groups` of the group that contains the
+    /// matched rule, or of the first group of the agent if no rule matched
+    pub group: Option<usize>,
+}
+
+impl Decision {
+    fn allow(group: Option<usize>) -> Self {
+        Self {
+            allowed: true,
+            matched_rule: None,
+            group,
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.allowed { "allowed" } else { "disallowed" })?;
+        match (&self.matched_rule, self.group) {
+            (Some(rule), Some(group)) => write!(f, " by `{}` in group {}", rule, group),
+            (Some(rule), None) => write!(f, " by `{}`", rule),
+            (None, _) => write!(f, ", no rule matched"),
+        }
+    }
+}
+
+/// What is assumed while the robots.txt of an origin is unreachable, because
+/// the server answered with a `5xx` or `429` status, could not be connected or
+/// the request failed otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unreachable {
+    /// Disallow all urls of the origin, as RFC 9309 requires
+    DisallowAll,
+    /// Allow all urls of the origin
+    AllowAll,
+    /// Keep obeying the last robots.txt that was fetched successfully, and
+    /// disallow all urls if there is none
+    LastKnown,
+}
+
+/// How the robots.txt of an origin is fetched, see
+/// <https://www.rfc-editor.org/rfc/rfc9309#section-2.3>.
+///
+/// `5xx` and `429` responses and connection errors are retried according to
+/// the `RetryPolicy`. If the robots.txt is still unreachable, or its request
+/// failed for any other reason, the `Unreachable` policy applies until the
+/// robots.txt is fetched again after `unreachable_ttl`. Once the origin was
+/// unreachable for `allow_after`, all its urls are allowed.
+#[derive(Debug, Clone)]
+pub struct RobotsPolicy {
+    /// Retries of unreachable robots.txt files
+    retry: RetryPolicy,
+    /// How many redirects are followed before the robots.txt is treated as
+    /// unavailable, which allows all urls
+    max_redirects: usize,
+    /// How many bytes of the robots.txt are parsed
+    max_size: usize,
+    unreachable: Unreachable,
+    /// How long an unreachable robots.txt is cached
+    unreachable_ttl: Duration,
+    /// Allow all urls of an origin that was unreachable for this long
+    allow_after: Option<Duration>,
+}
+
+impl Default for RobotsPolicy {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::new(3),
+            max_redirects: 5,
+            max_size: MAX_ROBOTS_SIZE,
+            unreachable: Unreachable::DisallowAll,
+            unreachable_ttl: Duration::from_secs(60 * 60),
+            allow_after: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        }
+    }
+}
+
+impl RobotsPolicy {
+    /// Retry unreachable robots.txt files according to the `policy`
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Follow at most `max_redirects` redirects, 5 by default
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Only parse the first `max_size` bytes, 500 KiB by default
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// What to assume while the robots.txt is unreachable
+    pub fn on_unreachable(mut self, unreachable: Unreachable) -> Self {
+        self.unreachable = unreachable;
+        self
+    }
+
+    /// Fetch an unreachable robots.txt again after `ttl`, 1 hour by default
+    pub fn unreachable_ttl(mut self, ttl: Duration) -> Self {
+        self.unreachable_ttl = ttl;
+        self
+    }
+
+    /// Allow all urls of an origin whose robots.txt was unreachable for
+    /// `duration`, 30 days by default. `None` never gives up.
+    pub fn allow_after(mut self, duration: Option<Duration>) -> Self {
+        self.allow_after = duration;
+        self
+    }
+
+    /// Fetch the robots.txt at `url` with the `user_agent`, following
+    /// redirects that the `fetcher` did not follow
+    async fn fetch(
+        &self,
+        fetcher: &dyn Fetcher,
+        mut url: Url,
+        user_agent: Option<&HeaderValue>,
+    ) -> Fetched {
+        let mut redirects = 0;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut request = Request::new(Method::GET, url.clone());
+            if let Some(agent) = user_agent {
+                request.headers_mut().insert(USER_AGENT, agent.clone());
+            }
+            let resp = match fetcher.fetch(request).await {
+                Ok(resp) => resp,
+                Err(err) => {
+                    if attempt < self.retry.attempts() && self.retry.is_retryable_fetch_error(&err)
+                    {
+                        if let Some(delay) = self.retry.delay(attempt, None) {
+                            Delay::new(delay).await;
+                            continue;
+                        }
+                    }
+                    return Fetched::Unreachable(err);
+                }
+            };
+
+            let status = resp.status();
+            if status.is_redirection() {
+                let location = resp
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok());
+                match location {
+                    Some(location) if redirects < self.max_redirects => {
+                        redirects += 1;
+                        attempt = 0;
+                        url = location;
+                        continue;
+                    }
+                    // too many redirects are treated like a missing robots.txt
+                    _ => return Fetched::Robots(RobotsData::allow_all()),
+                }
+            }
+
+            if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                if attempt < self.retry.attempts() && self.retry.is_retryable_status(status) {
+                    if let Some(delay) = self.retry.delay(attempt, Some(resp.headers())) {
+                        Delay::new(delay).await;
+                        continue;
+                    }
+                }
+                let err = UnexpectedStatusError::new(status.as_u16());
+                return Fetched::Unreachable(err.into());
+            }
+
+            return if status.is_success() {
+                match read_capped(resp, self.max_size).await {
+                    Ok(body) => Fetched::Robots(RobotsHandler::parse(&body)),
+                    Err(err) => Fetched::Unreachable(err),
+                }
+            } else if status.is_client_error() {
+                Fetched::Robots(RobotsData::allow_all())
+            } else {
+                Fetched::Unreachable(UnexpectedStatusError::new(status.as_u16()).into())
+            };
+        }
+    }
+
+    /// What is obeyed after the origin was unreachable `since`
+    fn unreachable(&self, since: Instant, last_known: Option<&Arc<RobotsData>>) -> Arc<RobotsData> {
+        if self.allow_after.is_some_and(|after| since.elapsed() >= after) {
+            return Arc::new(RobotsData::allow_all());
+        }
+        match (self.unreachable, last_known) {
+            (Unreachable::AllowAll, _) => Arc::new(RobotsData::allow_all()),
+            (Unreachable::LastKnown, Some(data)) => Arc::clone(data),
+            _ => Arc::new(RobotsData::disallow_all()),
+        }
+    }
+}
+
+/// The outcome of fetching a robots.txt
+enum Fetched {
+    Robots(RobotsData),
+    Unreachable(anyhow::Error),
+}
+
+/// Read at most `max` bytes of the body, a line that is cut off is dropped
+async fn read_capped(mut resp: reqwest::Response, max: usize) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > max {
+            truncate_lines(&mut body, max);
+            break;
+        }
+    }
+    Ok(body)
+}
+
+/// Truncate the `body` to at most `max` bytes, at the end of a line
+fn truncate_lines(body: &mut Vec<u8>, max: usize) {
+    if body.len() > max {
+        body.truncate(max);
+        let end = body.iter().rposition(|b| *b == b'\n').map_or(0, |n| n + 1);
+        body.truncate(end);
+    }
+}
+
+/// Parsed `robots.txt` files keyed by origin (scheme, host and port).
+///
+/// Entries expire after the TTL, 24 hours by default, and are fetched again
+/// the next time a request for the origin is sent. How they are fetched and
+/// what is assumed if that fails is decided by the `RobotsPolicy`. The cache
+/// is cheap to clone, all clones share the same entries.
+#[derive(Clone)]
+pub struct RobotsCache {
+    fetcher: Arc<dyn Fetcher>,
+    /// Sent as `User-Agent` of the robots.txt requests
+    user_agent: Option<HeaderValue>,
+    ttl: Duration,
+    policy: Arc<RobotsPolicy>,
+    entries: Arc<Mutex<HashMap<String, CachedRobots>>>,
+}
+
+impl fmt::Debug for RobotsCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RobotsCache")
+            .field("user_agent", &self.user_agent)
+            .field("ttl", &self.ttl)
+            .field("policy", &self.policy)
+            .field("entries", &self.entries)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct CachedRobots {
+    fetched: Instant,
+    data: Arc<RobotsData>,
+    /// Since when the robots.txt is unreachable, `None` if it was fetched
+    unreachable_since: Option<Instant>,
+    /// The last robots.txt that was fetched successfully
+    last_known: Option<Arc<RobotsData>>,
+}
+
+impl RobotsCache {
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// An empty cache that fetches with the `client`.
+    ///
+    /// Redirects are followed by the `client`, those it does not follow are
+    /// followed up to the limit of the `RobotsPolicy`.
+    pub fn new(client: reqwest::Client) -> Self {
+        Self::with_fetcher(client)
+    }
+
+    /// An empty cache that fetches with the `fetcher`, like a `MockFetcher`
+    pub fn with_fetcher(fetcher: impl Fetcher + 'static) -> Self {
+        Self {
+            fetcher: Arc::new(fetcher),
+            user_agent: None,
+            ttl: Self::DEFAULT_TTL,
+            policy: Default::default(),
+            entries: Default::default(),
+        }
+    }
+
+    /// Refresh entries that are older than `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Send the `agent` as `User-Agent` header of the robots.txt requests
+    pub fn with_user_agent(mut self, agent: HeaderValue) -> Self {
+        self.user_agent = Some(agent);
+        self
+    }
+
+    /// The `User-Agent` header of the robots.txt requests, if set
+    pub fn user_agent(&self) -> Option<&HeaderValue> {
+        self.user_agent.as_ref()
+    }
+
+    /// Fetch robots.txt files according to the `policy`
+    pub fn with_policy(mut self, policy: RobotsPolicy) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    /// The time after which an entry is fetched again
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// How robots.txt files are fetched
+    pub fn policy(&self) -> &RobotsPolicy {
+        &self.policy
+    }
+
+    /// The key of the `url` in the cache, `None` for urls without a host
+    pub fn origin(url: &Url) -> Option<String> {
+        let origin = url.origin();
+        if origin.is_tuple() {
+            Some(origin.ascii_serialization())
+        } else {
+            None
+        }
+    }
+
+    /// The robots.txt of the `url`'s origin, if it is cached and not expired
+    pub fn get(&self, url: &Url) -> Option<Arc<RobotsData>> {
+        let origin = Self::origin(url)?;
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&origin)
+            .filter(|cached| {
+                let ttl = match cached.unreachable_since {
+                    Some(_) => self.policy.unreachable_ttl,
+                    None => self.ttl,
+                };
+                cached.fetched.elapsed() < ttl
+            })
+            .map(|cached| Arc::clone(&cached.data))
+    }
+
+    /// Cache the `data` as robots.txt of the `url`'s origin
+    pub fn insert(&self, url: &Url, data: RobotsData) -> Arc<RobotsData> {
+        let data = Arc::new(data);
+        if let Some(origin) = Self::origin(url) {
+            let cached = CachedRobots {
+                fetched: Instant::now(),
+                data: Arc::clone(&data),
+                unreachable_since: None,
+                last_known: Some(Arc::clone(&data)),
+            };
+            self.entries.lock().unwrap().insert(origin, cached);
+        }
+        data
+    }
+
+    /// Cache what the `RobotsPolicy` assumes for the unreachable robots.txt
+    /// of the `url`'s origin
+    pub(crate) fn insert_unreachable(&self, url: &Url) -> Arc<RobotsData> {
+        let origin = match Self::origin(url) {
+            Some(origin) => origin,
+            None => return self.policy.unreachable(Instant::now(), None),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let (since, last_known) = entries
+            .remove(&origin)
+            .map(|cached| {
+                let since = cached.unreachable_since.unwrap_or_else(Instant::now);
+                (since, cached.last_known)
             })
-            .unwrap_or_default();
+            .unwrap_or_else(|| (Instant::now(), None));
+        let data = self.policy.unreachable(since, last_known.as_ref());
+        let cached = CachedRobots {
+            fetched: Instant::now(),
+            data: Arc::clone(&data),
+            unreachable_since: Some(since),
+            last_known,
+        };
+        entries.insert(origin, cached);
+        data
+    }
+
+    /// How long until the unreachable robots.txt of the `url`'s origin is
+    /// fetched again, if all its urls are disallowed until then
+    pub(crate) fn retry_in(&self, url: &Url) -> Option<Duration> {
+        let origin = Self::origin(url)?;
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.get(&origin)?;
+        (cached.unreachable_since.is_some() && cached.data.disallow_all).then(|| {
+            self.policy
+                .unreachable_ttl
+                .saturating_sub(cached.fetched.elapsed())
+        })
+    }
+
+    /// Whether the robots.txt of the `url`'s origin is currently unreachable
+    pub fn is_unreachable(&self, url: &Url) -> bool {
+        Self::origin(url)
+            .and_then(|origin| {
+                let entries = self.entries.lock().unwrap();
+                entries.get(&origin).map(|cached| cached.unreachable_since.is_some())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Remove the entry of the `url`'s origin
+    pub fn invalidate(&self, url: &Url) -> Option<Arc<RobotsData>> {
+        let origin = Self::origin(url)?;
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&origin)
+            .map(|cached| cached.data)
+    }
+
+    /// Fetch the robots.txt of the `url`'s origin and cache it.
+    ///
+    /// If the robots.txt is unreachable, what the `RobotsPolicy` assumes is
+    /// cached, see `get`, and the error is returned.
+    pub async fn fetch(&self, url: &Url) -> Result<Arc<RobotsData>> {
+        let robots_url = url.join("/robots.txt")?;
+        let user_agent = self.user_agent.as_ref();
+        match self.policy.fetch(&*self.fetcher, robots_url, user_agent).await {
+            Fetched::Robots(data) => Ok(self.insert(url, data)),
+            Fetched::Unreachable(err) => {
+                self.insert_unreachable(url);
+                Err(err)
+            }
+        }
+    }
+
+    /// The cached robots.txt of the `url`'s origin, fetched if it is missing
+    /// or expired.
+    ///
+    /// If the robots.txt is unreachable, what the `RobotsPolicy` assumes is
+    /// returned.
+    pub async fn get_or_fetch(&self, url: &Url) -> Result<Arc<RobotsData>> {
+        if let Some(data) = self.get(url) {
+            return Ok(data);
+        }
+        match self.fetch(url).await {
+            Err(err) => self.get(url).ok_or(err),
+            fetched => fetched,
+        }
+    }
+
+    /// Decide whether the `agent` may crawl the `url`, fetching the robots.txt
+    /// if necessary
+    pub async fn check(&self, url: &Url, agent: &str) -> Result<Decision> {
+        Ok(self.get_or_fetch(url).await?.check(url, agent))
+    }
 
-        !is_disallowed
+    /// The number of cached origins, including expired ones
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all entries
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
     }
 }
 
@@ -210,8 +871,15 @@ pub struct Group {
     rules: Vec<Rule>,
 }
 
+impl Group {
+    /// The `Crawl-delay` declared for this group, if any
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+}
+
 /// A rule that either allows or disallows an url pattern
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rule {
     /// The url pattern like `/`
     pattern: String,
@@ -220,6 +888,11 @@ pub struct Rule {
 }
 
 impl Rule {
+    /// The url pattern of the rule
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
     /// Whether the pattern allows accessing the url
     pub fn is_allow(&self) -> bool {
         self.allow
@@ -247,6 +920,13 @@ impl Rule {
     }
 }
 
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let directive = if self.allow { "Allow" } else { "Disallow" };
+        write!(f, "{}: {}", directive, self.pattern)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -301,4 +981,313 @@ Disallow: /r/rust",
             .unwrap();
         assert!(data.is_not_disallowed(&request))
     }
+
+    #[test]
+    fn robots_crawl_delay() {
+        let mut handler = RobotsHandler::default();
+        parse_robotstxt(
+            "User-Agent: *
+Crawl-delay: 2.5
+Disallow: /r/rust",
+            &mut handler,
+        );
+        let data = handler.finish();
+
+        let client = reqwest::Client::new();
+        let request = client
+            .request(reqwest::Method::GET, "https://old.reddit.com/r/crust")
+            .build()
+            .unwrap();
+        assert_eq!(data.crawl_delay(&request), Some(Duration::from_millis(2_500)));
+        assert_eq!(RobotsData::allow_all().crawl_delay(&request), None);
+    }
+
+    #[test]
+    fn clamp_long_crawl_delays() {
+        for (value, delay) in [("999", 999), ("1000", 1000), ("1500", 1000), ("1e9", 1000)] {
+            let mut handler = RobotsHandler::default();
+            parse_robotstxt(
+                &format!("User-Agent: *\nCrawl-delay: {}\nDisallow: /private", value),
+                &mut handler,
+            );
+            let data = handler.finish();
+            assert_eq!(
+                data.crawl_delay_for("bot"),
+                Some(Duration::from_secs(delay)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn robots_decision() {
+        let mut handler = RobotsHandler::default();
+        parse_robotstxt(
+            "User-Agent: *
+Disallow: /shop
+Allow: /shop/catalog
+
+User-Agent: archiver
+Disallow: /",
+            &mut handler,
+        );
+        let data = handler.finish();
+        let url = |path: &str| Url::parse(&format!("https://example.com{}", path)).unwrap();
+
+        let decision = data.check(&url("/shop/cart"), "crawler");
+        assert!(!decision.allowed);
+        assert_eq!(decision.matched_rule, Some(Rule::disallow("/shop")));
+        assert_eq!(decision.group, Some(0));
+        assert_eq!(decision.to_string(), "disallowed by `Disallow: /shop` in group 0");
+
+        let decision = data.check(&url("/shop/catalog/1"), "crawler");
+        assert!(decision.allowed);
+        assert_eq!(decision.to_string(), "allowed by `Allow: /shop/catalog` in group 0");
+
+        let decision = data.check(&url("/about"), "crawler");
+        assert!(decision.allowed && decision.matched_rule.is_none());
+        assert_eq!(decision.to_string(), "allowed, no rule matched");
+
+        let decision = data.check(&url("/about"), "archiver");
+        assert!(!decision.allowed);
+        assert_eq!(decision.group, Some(1));
+    }
+
+    #[test]
+    fn user_agent_identity() {
+        let agent = UserAgent::new("OurBot")
+            .version("1.2")
+            .info_url("https://example.com/bot");
+        assert_eq!(agent.to_string(), "OurBot/1.2 (+https://example.com/bot)");
+        assert_eq!(agent.product_token(), "ourbot");
+
+        let mut handler = RobotsHandler::default();
+        parse_robotstxt("User-agent: ourbot\nDisallow: /\nCrawl-delay: 3", &mut handler);
+        let data = handler.finish();
+        let url = Url::parse("https://example.com/").unwrap();
+        assert!(!data.check(&url, &agent.to_string()).allowed);
+        assert_eq!(data.crawl_delay_for(&agent.product_token()), Some(Duration::from_secs(3)));
+        assert!(data.check(&url, "*").allowed);
+    }
+
+    #[test]
+    fn truncate_at_line_end() {
+        let mut body = b"User-agent: *\nDisallow: /private\n".to_vec();
+        truncate_lines(&mut body, 20);
+        assert_eq!(body, b"User-agent: *\n");
+
+        let mut body = b"User-agent: *\n".to_vec();
+        truncate_lines(&mut body, MAX_ROBOTS_SIZE);
+        assert_eq!(body, b"User-agent: *\n");
+    }
+
+    #[test]
+    fn unreachable_robots_policy() {
+        let url = Url::parse("https://example.com/page").unwrap();
+        let cache = RobotsCache::new(reqwest::Client::new());
+        assert!(cache.insert_unreachable(&url).disallow_all);
+        assert!(cache.is_unreachable(&url));
+        assert!(cache.get(&url).is_some());
+
+        let policy = RobotsPolicy::default().on_unreachable(Unreachable::LastKnown);
+        let cache = cache.with_policy(policy);
+        cache.insert(&url, RobotsHandler::parse(b"User-agent: *\nDisallow: /page"));
+        let data = cache.insert_unreachable(&url);
+        assert!(!data.check(&url, "*").allowed);
+        assert!(data.check(&url.join("/other").unwrap(), "*").allowed);
+
+        let policy = RobotsPolicy::default()
+            .allow_after(Some(Duration::ZERO))
+            .unreachable_ttl(Duration::ZERO);
+        let cache = cache.with_policy(policy);
+        assert!(cache.insert_unreachable(&url).allow_all);
+        assert!(cache.get(&url).is_none());
+    }
+
+    #[test]
+    fn robots_cache_by_origin() {
+        let cache = RobotsCache::new(reqwest::Client::new());
+        let url = |url: &str| Url::parse(url).unwrap();
+        cache.insert(&url("https://example.com/a"), RobotsData::disallow_all());
+
+        assert!(cache.get(&url("https://example.com:443/b?c")).is_some());
+        assert!(cache.get(&url("http://example.com/")).is_none());
+        assert!(cache.get(&url("https://example.com:8443/")).is_none());
+        assert!(cache.get(&url("https://sub.example.com/")).is_none());
+
+        let expired = cache.clone().with_ttl(Duration::ZERO);
+        assert!(expired.get(&url("https://example.com/")).is_none());
+        assert_eq!(expired.len(), 1);
+    }
+
+    #[test]
+    fn robots_sitemaps() {
+        let mut handler = RobotsHandler::default();
+        parse_robotstxt(
+            "Sitemap: https://example.com/sitemap.xml
+User-Agent: *
+Disallow: /private
+Sitemap: https://example.com/news.xml.gz",
+            &mut handler,
+        );
+        let data = handler.finish();
+        assert_eq!(
+            data.sitemaps,
+            vec![
+                Url::parse("https://example.com/sitemap.xml").unwrap(),
+                Url::parse("https://example.com/news.xml.gz").unwrap(),
+            ]
+        );
+    }
+}
+
+/// The reference cases of Google's robots.txt parser, see
+/// <https://github.com/google/robotstxt/blob/master/robots_test.cc>
+#[cfg(test)]
+mod conformance {
+    use super::*;
+
+    fn allowed(robots: &str, agent: &str, url: &str) -> bool {
+        let mut handler = RobotsHandler::default();
+        parse_robotstxt(robots, &mut handler);
+        handler.finish().check(&Url::parse(url).unwrap(), agent).allowed
+    }
+
+    #[test]
+    fn empty_and_global_groups() {
+        assert!(allowed("", "FooBot", "http://foo.bar/x/y"));
+        let robots = "user-agent: *\nallow: /\nuser-agent: FooBot\ndisallow: /\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/x/y"));
+        assert!(allowed(robots, "BarBot", "http://foo.bar/x/y"));
+
+        let robots = "user-agent: *\ndisallow: /x/\nuser-agent: FooBot\ndisallow: /y/\n";
+        assert!(allowed(robots, "FooBot", "http://foo.bar/x/page"));
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/y/page"));
+        assert!(!allowed(robots, "BarBot", "http://foo.bar/x/page"));
+    }
+
+    #[test]
+    fn groups_are_merged() {
+        let robots = "allow: /foo/bar/
+user-agent: FooBot
+disallow: /
+allow: /x/
+user-agent: BarBot
+disallow: /
+allow: /y/
+
+user-agent: FooBot
+allow: /z/
+";
+        assert!(allowed(robots, "FooBot", "http://foo.bar/x/b"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/z/d"));
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/y/c"));
+        assert!(allowed(robots, "BarBot", "http://foo.bar/y/c"));
+        assert!(!allowed(robots, "BarBot", "http://foo.bar/z/d"));
+        // rules before the first user-agent line belong to no group
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/foo/bar/"));
+
+        let robots = "user-agent: FooBot\nuser-agent: BarBot\ndisallow: /\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/"));
+        assert!(!allowed(robots, "BarBot", "http://foo.bar/"));
+    }
+
+    #[test]
+    fn user_agent_matching() {
+        let robots = "User-Agent: FOO BAR\nAllow: /x/\nDisallow: /\n";
+        assert!(allowed(robots, "Foo", "http://foo.bar/x/y"));
+        assert!(!allowed(robots, "foo", "http://foo.bar/a/b"));
+
+        let robots = "User-agent: FooBot/2.1\nDisallow: /\n";
+        assert!(!allowed(robots, "foobot", "http://foo.bar/"));
+        assert!(!allowed(robots, "FooBot/2.1 (+http://foo.bar/bot)", "http://foo.bar/"));
+        assert!(allowed(robots, "Foo", "http://foo.bar/"));
+        // only the leading product token of a header is matched
+        assert!(allowed(
+            robots,
+            "Mozilla/5.0 (compatible; FooBot/2.1; +http://foo.bar/bot)",
+            "http://foo.bar/"
+        ));
+
+        // the wildcard groups only apply if the agent has no group
+        let robots = "User-agent: *\nDisallow: /\n\nUser-agent: BarBot\nDisallow: /x/\n";
+        assert!(!allowed(robots, "Mozilla/5.0 (compatible; FooBot/2.1)", "http://foo.bar/"));
+        assert!(allowed(robots, "BarBot/1.0", "http://foo.bar/y/"));
+        assert!(!allowed(robots, "barbot", "http://foo.bar/x/"));
+    }
+
+    #[test]
+    fn longest_match() {
+        let url = "http://foo.bar/x/page.html";
+        let robots = "user-agent: FooBot\ndisallow: /x/page.html\nallow: /x/\n";
+        assert!(!allowed(robots, "FooBot", url));
+
+        let robots = "user-agent: FooBot\nallow: /x/page.html\ndisallow: /x/\n";
+        assert!(allowed(robots, "FooBot", url));
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/x/"));
+
+        let robots = "user-agent: FooBot\ndisallow: \nallow: \n";
+        assert!(allowed(robots, "FooBot", url));
+
+        let robots = "user-agent: FooBot\ndisallow: /\nallow: /\n";
+        assert!(allowed(robots, "FooBot", url));
+
+        let robots = "user-agent: FooBot\ndisallow: /x\nallow: /x/\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/x"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/x/"));
+
+        let robots = "user-agent: FooBot\ndisallow: /x/page.html\nallow: /x/page.html\n";
+        assert!(allowed(robots, "FooBot", url));
+
+        let robots = "user-agent: FooBot\nallow: /page\ndisallow: /*.html\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/page.html"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/page"));
+
+        let robots = "user-agent: FooBot\nallow: /x/page.\ndisallow: /*.html\n";
+        assert!(allowed(robots, "FooBot", url));
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/x/y.html"));
+    }
+
+    #[test]
+    fn wildcards_and_anchors() {
+        let robots = "user-agent: FooBot\ndisallow: /foo/bar$\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/foo/bar"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/foo/bar/"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/foo/bar?x"));
+
+        let robots = "user-agent: FooBot\ndisallow: /*/bar/*.php$\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/foo/bar/x.php"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/foo/bar/x.php5"));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/bar/x.php"));
+
+        let robots =
+            "user-agent: FooBot\ndisallow: /foo/bar?qux=taz&baz=http://foo.bar?tar&par\n";
+        assert!(!allowed(
+            robots,
+            "FooBot",
+            "http://foo.bar/foo/bar?qux=taz&baz=http://foo.bar?tar&par"
+        ));
+        assert!(allowed(robots, "FooBot", "http://foo.bar/foo/bar?qux=taz"));
+    }
+
+    #[test]
+    fn paths_are_case_sensitive_and_percent_encoded() {
+        let robots = "user-agent: FooBot\ndisallow: /x/\n";
+        assert!(allowed(robots, "FooBot", "http://foo.bar/X/y"));
+
+        let robots = "user-agent: FooBot\ndisallow: /foo/bar/ツ\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/foo/bar/ツ"));
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/foo/bar/%E3%83%84"));
+
+        let robots = "user-agent: FooBot\ndisallow: /foo/bar/%62%61%7A\n";
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/foo/bar/%62%61%7A"));
+    }
+
+    #[test]
+    fn robots_txt_is_always_allowed() {
+        let robots = "user-agent: *\ndisallow: /\n";
+        assert!(allowed(robots, "FooBot", "http://foo.bar/robots.txt"));
+        assert!(!allowed(robots, "FooBot", "http://foo.bar/robots.txt.bak"));
+    }
 }
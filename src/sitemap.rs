@@ -0,0 +1,416 @@
+//! Sitemaps as described on <https://www.sitemaps.org/protocol.html>.
+//!
+//! A sitemap either lists the urls of a site or, as sitemap index, other
+//! sitemaps. Both may be gzipped. The sitemaps of a host are declared with
+//! `Sitemap:` lines in its robots.txt, or are expected at `/sitemap.xml`.
+
+use std::future::Future;
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::{Method, Request, Url};
+
+use crate::error::{DisallowReason, SitemapError, UnexpectedStatusError};
+use crate::fetch::{Fetcher, Fetchers};
+use crate::robots::RobotsCache;
+
+/// The maximum size of an uncompressed sitemap according to the protocol
+pub const MAX_SITEMAP_SIZE: usize = 50 * 1024 * 1024;
+
+/// How many sitemap indexes may be nested before giving up
+pub const MAX_SITEMAP_NESTING: usize = 3;
+
+/// How frequently the page is likely to change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "always" => Some(ChangeFreq::Always),
+            "hourly" => Some(ChangeFreq::Hourly),
+            "daily" => Some(ChangeFreq::Daily),
+            "weekly" => Some(ChangeFreq::Weekly),
+            "monthly" => Some(ChangeFreq::Monthly),
+            "yearly" => Some(ChangeFreq::Yearly),
+            "never" => Some(ChangeFreq::Never),
+            _ => None,
+        }
+    }
+}
+
+/// A `<url>` entry of a sitemap
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapUrl {
+    /// The url of the page
+    pub loc: Url,
+    /// The date of the last modification in W3C datetime format, as written
+    pub lastmod: Option<String>,
+    /// How frequently the page is likely to change
+    pub changefreq: Option<ChangeFreq>,
+    /// The priority relative to the other urls of the site, between 0.0 and 1.0
+    pub priority: Option<f32>,
+}
+
+impl SitemapUrl {
+    /// An entry that only has a `loc`
+    pub fn new(loc: Url) -> Self {
+        Self {
+            loc,
+            lastmod: None,
+            changefreq: None,
+            priority: None,
+        }
+    }
+}
+
+/// A `<sitemap>` entry of a sitemap index
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    /// The url of the sitemap
+    pub loc: Url,
+    /// The date of the last modification in W3C datetime format, as written
+    pub lastmod: Option<String>,
+}
+
+/// A parsed sitemap
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    /// A `<urlset>` or a plain text sitemap with one url per line
+    Urls(Vec<SitemapUrl>),
+    /// A `<sitemapindex>` that lists other sitemaps
+    Index(Vec<SitemapEntry>),
+}
+
+impl Sitemap {
+    /// Parse a sitemap, which may be gzipped.
+    ///
+    /// Entries without a valid absolute `loc` are skipped.
+    pub fn parse(body: &[u8]) -> Result<Self, SitemapError> {
+        let decompressed;
+        let body = if body.starts_with(&[0x1f, 0x8b]) {
+            decompressed = gunzip(body)?;
+            decompressed.as_slice()
+        } else {
+            body
+        };
+        if body.len() > MAX_SITEMAP_SIZE {
+            return Err(SitemapError::TooLarge(MAX_SITEMAP_SIZE));
+        }
+
+        let text = String::from_utf8_lossy(body);
+        let text = text.trim_start_matches('\u{feff}').trim();
+        if text.starts_with('<') {
+            parse_xml(text)
+        } else {
+            Ok(Sitemap::Urls(
+                text.lines()
+                    .filter_map(|line| Url::parse(line.trim()).ok())
+                    .map(SitemapUrl::new)
+                    .collect(),
+            ))
+        }
+    }
+
+    /// Fetch and parse the sitemap at `url`.
+    ///
+    /// The download is aborted once the body exceeds `MAX_SITEMAP_SIZE`.
+    pub async fn fetch(client: &reqwest::Client, url: Url) -> Result<Self> {
+        Self::download(client, Request::new(Method::GET, url)).await
+    }
+
+    async fn download(fetcher: &dyn Fetcher, request: Request) -> Result<Self> {
+        let mut resp = fetcher.fetch(request).await?;
+        if !resp.status().is_success() {
+            return Err(UnexpectedStatusError::new(resp.status().as_u16()).into());
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            if body.len() + chunk.len() > MAX_SITEMAP_SIZE {
+                return Err(SitemapError::TooLarge(MAX_SITEMAP_SIZE).into());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Sitemap::parse(&body)?)
+    }
+}
+
+fn gunzip(body: &[u8]) -> Result<Vec<u8>, SitemapError> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(body)
+        .take(MAX_SITEMAP_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+fn parse_xml(text: &str) -> Result<Sitemap, SitemapError> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+
+    // the text of the first child element with the `name`
+    let child = |node: roxmltree::Node, name: &str| -> Option<String> {
+        node.children()
+            .find(|c| c.is_element() && c.tag_name().name().eq_ignore_ascii_case(name))
+            .and_then(|c| c.text())
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    };
+    let entries = |name: &'static str| {
+        root.children()
+            .filter(move |c| c.is_element() && c.tag_name().name().eq_ignore_ascii_case(name))
+    };
+    let loc = |node: roxmltree::Node| child(node, "loc").and_then(|loc| Url::parse(&loc).ok());
+
+    match root.tag_name().name().to_ascii_lowercase().as_str() {
+        "urlset" => Ok(Sitemap::Urls(
+            entries("url")
+                .filter_map(|node| {
+                    Some(SitemapUrl {
+                        loc: loc(node)?,
+                        lastmod: child(node, "lastmod"),
+                        changefreq: child(node, "changefreq")
+                            .and_then(|freq| ChangeFreq::parse(&freq)),
+                        priority: child(node, "priority")
+                            .and_then(|priority| priority.parse::<f32>().ok())
+                            .filter(|priority| (0.0..=1.0).contains(priority)),
+                    })
+                })
+                .collect(),
+        )),
+        "sitemapindex" => Ok(Sitemap::Index(
+            entries("sitemap")
+                .filter_map(|node| {
+                    Some(SitemapEntry {
+                        loc: loc(node)?,
+                        lastmod: child(node, "lastmod"),
+                    })
+                })
+                .collect(),
+        )),
+        _ => Err(SitemapError::UnexpectedRoot(
+            root.tag_name().name().to_string(),
+        )),
+    }
+}
+
+/// The sitemaps declared in the robots.txt of the `url`'s host, or its
+/// `/sitemap.xml` if the robots.txt declares none
+pub async fn discover(client: &reqwest::Client, url: &Url) -> Result<Vec<Url>> {
+    discover_with(&RobotsCache::new(client.clone()), url).await
+}
+
+/// Like `discover`, with the robots.txt of the `cache`
+async fn discover_with(cache: &RobotsCache, url: &Url) -> Result<Vec<Url>> {
+    let robots = cache.get_or_fetch(url).await?;
+    if robots.sitemaps.is_empty() {
+        Ok(vec![url.join("/sitemap.xml")?])
+    } else {
+        Ok(robots.sitemaps.clone())
+    }
+}
+
+/// Derives the scraper state for an url of a sitemap
+pub(crate) type StateFn<S> = Arc<dyn Fn(&SitemapUrl) -> Option<S>>;
+
+/// What a `SitemapRequest` resolves to
+pub(crate) enum Found {
+    /// A downloaded sitemap
+    Sitemap(Sitemap),
+    /// Sitemaps of a host, found in its robots.txt
+    Discovered(Vec<Url>),
+}
+
+/// The context of a sitemap that is downloaded by the `Crawler`
+pub(crate) struct SitemapJob<S> {
+    /// The url of the sitemap or the host
+    pub url: Url,
+    pub state_fn: StateFn<S>,
+    /// The depth of the crawler when the sitemap was requested, its urls are
+    /// queued one level deeper
+    pub depth: usize,
+    /// How many sitemap indexes led to this sitemap
+    pub nesting: usize,
+}
+
+impl<S> SitemapJob<S> {
+    /// The job for a sitemap referenced by this one
+    pub fn nested(&self, url: Url, nesting: usize) -> Self {
+        Self {
+            url,
+            state_fn: Arc::clone(&self.state_fn),
+            depth: self.depth,
+            nesting,
+        }
+    }
+}
+
+pub(crate) type SitemapRequest<S> = Pin<Box<dyn Future<Output = (SitemapJob<S>, Result<Found>)>>>;
+
+/// Sends the requests for the sitemaps of a `Crawler`
+#[derive(Clone)]
+pub(crate) struct SitemapClient {
+    /// The fetchers of the crawler
+    pub fetchers: Arc<Fetchers>,
+    /// Sent as `User-Agent`, a custom client doesn't know it
+    pub user_agent: Option<HeaderValue>,
+}
+
+impl SitemapClient {
+    fn get(&self, url: Url) -> Request {
+        let mut request = Request::new(Method::GET, url);
+        if let Some(agent) = &self.user_agent {
+            request.headers_mut().insert(USER_AGENT, agent.clone());
+        }
+        request
+    }
+}
+
+/// The robots.txt rules that sitemap downloads obey
+#[derive(Clone)]
+pub(crate) struct SitemapRobots {
+    pub cache: RobotsCache,
+    /// The agent whose rules apply
+    pub agent: String,
+}
+
+/// Download and parse the sitemap of the `job`, unless the `robots` disallow
+/// it
+pub(crate) fn fetch<S: 'static>(
+    client: SitemapClient,
+    robots: Option<SitemapRobots>,
+    job: SitemapJob<S>,
+) -> SitemapRequest<S> {
+    Box::pin(async move {
+        if let Some(robots) = robots {
+            match robots.cache.check(&job.url, &robots.agent).await {
+                Ok(decision) if !decision.allowed => {
+                    let err = SitemapError::Disallowed(job.url.clone(), DisallowReason::RobotsTxt);
+                    return (job, Err(err.into()));
+                }
+                Err(err) => return (job, Err(err)),
+                Ok(_) => {}
+            }
+        }
+        let request = client.get(job.url.clone());
+        let sitemap = Sitemap::download(&*client.fetchers, request).await;
+        (job, sitemap.map(Found::Sitemap))
+    })
+}
+
+/// Find the sitemaps of the host of the `job` in its robots.txt
+pub(crate) fn find<S: 'static>(robots: RobotsCache, job: SitemapJob<S>) -> SitemapRequest<S> {
+    Box::pin(async move {
+        let sitemaps = discover_with(&robots, &job.url).await;
+        (job, sitemaps.map(Found::Discovered))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/</loc>
+    <lastmod>2022-06-01</lastmod>
+    <changefreq>daily</changefreq>
+    <priority>0.8</priority>
+  </url>
+  <url>
+    <loc> https://example.com/catalog?item=12&amp;desc=vacation </loc>
+  </url>
+  <url>
+    <loc>not a url</loc>
+  </url>
+</urlset>"#;
+
+    #[test]
+    fn parse_urlset() {
+        let sitemap = Sitemap::parse(URLSET.as_bytes()).unwrap();
+        let urls = match sitemap {
+            Sitemap::Urls(urls) => urls,
+            _ => panic!("expected an urlset"),
+        };
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[0].loc.as_str(), "https://example.com/");
+        assert_eq!(urls[0].lastmod.as_deref(), Some("2022-06-01"));
+        assert_eq!(urls[0].changefreq, Some(ChangeFreq::Daily));
+        assert_eq!(urls[0].priority, Some(0.8));
+        assert_eq!(
+            urls[1].loc.as_str(),
+            "https://example.com/catalog?item=12&desc=vacation"
+        );
+        assert_eq!(urls[1].priority, None);
+    }
+
+    #[test]
+    fn parse_gzipped_index() {
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://example.com/sitemap1.xml.gz</loc><lastmod>2004-10-01T18:23:17+00:00</lastmod></sitemap>
+  <sitemap><loc>https://example.com/sitemap2.xml.gz</loc></sitemap>
+</sitemapindex>"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(index.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let entries = match Sitemap::parse(&gzipped).unwrap() {
+            Sitemap::Index(entries) => entries,
+            _ => panic!("expected a sitemap index"),
+        };
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1].loc.as_str(),
+            "https://example.com/sitemap2.xml.gz"
+        );
+        assert_eq!(
+            entries[0].lastmod.as_deref(),
+            Some("2004-10-01T18:23:17+00:00")
+        );
+    }
+
+    #[test]
+    fn parse_text_sitemap() {
+        let sitemap = Sitemap::parse(b"https://example.com/a\n\nhttps://example.com/b\n").unwrap();
+        assert_eq!(
+            sitemap,
+            Sitemap::Urls(vec![
+                SitemapUrl::new(Url::parse("https://example.com/a").unwrap()),
+                SitemapUrl::new(Url::parse("https://example.com/b").unwrap()),
+            ])
+        );
+        assert!(matches!(
+            Sitemap::parse(b"<rss></rss>"),
+            Err(SitemapError::UnexpectedRoot(_))
+        ));
+    }
+
+    #[test]
+    fn root_tags_ignore_case() {
+        let sitemap =
+            Sitemap::parse(b"<URLSet><URL><LOC>https://example.com/</LOC></URL></URLSet>").unwrap();
+        assert_eq!(
+            sitemap,
+            Sitemap::Urls(vec![SitemapUrl::new(
+                Url::parse("https://example.com/").unwrap()
+            )])
+        );
+        let index = Sitemap::parse(b"<SitemapIndex></SitemapIndex>").unwrap();
+        assert_eq!(index, Sitemap::Index(Vec::new()));
+    }
+}
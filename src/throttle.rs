@@ -0,0 +1,255 @@
+//! Adapting the delay and concurrency of a domain to how fast it responds.
+//!
+//! The delay follows the latency of the responses divided by the target
+//! concurrency, so a fast host is crawled quickly and a slow host gets more
+//! time between requests. Overloaded responses, `429 Too Many Requests` and
+//! `503 Service Unavailable`, and failed connections double the delay and
+//! halve the concurrency, a `Retry-After` header raises the delay to at least
+//! its value, even above the upper bound.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::requests::RequestDelay;
+use crate::retry::retry_after;
+
+/// The bounds of an adaptive delay, see `RequestDelay::Adaptive`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoThrottle {
+    /// The delay before the first response was received
+    start_delay: Duration,
+    min_delay: Duration,
+    max_delay: Duration,
+    /// The average number of requests that should be in flight to a domain
+    target_concurrency: f64,
+    /// The upper bound of requests in flight to a domain
+    max_concurrency: usize,
+}
+
+impl Default for AutoThrottle {
+    fn default() -> Self {
+        Self {
+            start_delay: Duration::from_secs(1),
+            min_delay: Duration::ZERO,
+            max_delay: Duration::from_secs(60),
+            target_concurrency: 1.0,
+            max_concurrency: 8,
+        }
+    }
+}
+
+impl AutoThrottle {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The delay until the first response was received
+    pub fn start_delay(mut self, delay: Duration) -> Self {
+        self.start_delay = delay;
+        self
+    }
+
+    /// Keep the delay between `min` and `max`
+    pub fn bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.min_delay = min;
+        self.max_delay = max.max(min);
+        self
+    }
+
+    /// The average number of requests that should be in flight to a domain,
+    /// values below `1.0` send requests less often than the latency allows
+    pub fn target_concurrency(mut self, concurrency: f64) -> Self {
+        if concurrency > 0.0 {
+            self.target_concurrency = concurrency;
+        }
+        self
+    }
+
+    /// The most requests in flight to a domain at the same time
+    pub fn max_concurrency(mut self, concurrency: usize) -> Self {
+        self.max_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The same bounds but a delay of at least `min`
+    pub fn at_least(mut self, min: Duration) -> Self {
+        self.min_delay = self.min_delay.max(min);
+        self.max_delay = self.max_delay.max(min);
+        self.start_delay = self.start_delay.max(min);
+        self
+    }
+
+    /// The first delay, within the bounds
+    pub fn initial_delay(&self) -> Duration {
+        self.start_delay.clamp(self.min_delay, self.max_delay)
+    }
+
+    fn initial_concurrency(&self) -> usize {
+        (self.target_concurrency.ceil() as usize).clamp(1, self.max_concurrency)
+    }
+}
+
+/// The current delay and concurrency of a throttled domain
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThrottleSettings {
+    /// The delay between two requests
+    pub delay: Duration,
+    /// The most requests in flight at the same time
+    pub concurrency: usize,
+    /// The latency of the last response
+    pub latency: Option<Duration>,
+}
+
+/// The state of an `AutoThrottle` that the requests of a domain update
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    /// The bounds and the current settings
+    state: Mutex<(AutoThrottle, ThrottleSettings)>,
+    /// Whether the settings changed since they were last reported
+    changed: AtomicBool,
+}
+
+impl Throttle {
+    pub fn new(config: AutoThrottle) -> Self {
+        let settings = ThrottleSettings {
+            delay: config.initial_delay(),
+            concurrency: config.initial_concurrency(),
+            latency: None,
+        };
+        Self {
+            state: Mutex::new((config, settings)),
+            changed: AtomicBool::new(true),
+        }
+    }
+
+    pub fn settings(&self) -> ThrottleSettings {
+        self.state.lock().unwrap().1
+    }
+
+    /// The adaptive delay with the current bounds
+    pub fn delay(&self) -> RequestDelay {
+        RequestDelay::Adaptive(self.state.lock().unwrap().0)
+    }
+
+    /// The settings, if they changed since the last call
+    pub fn take_changed(&self) -> Option<ThrottleSettings> {
+        self.changed
+            .swap(false, Ordering::Relaxed)
+            .then(|| self.settings())
+    }
+
+    /// Never go below the `Crawl-delay` of the robots.txt
+    pub fn raise_min_delay(&self, min: Duration) {
+        let (config, settings) = &mut *self.state.lock().unwrap();
+        *config = config.at_least(min);
+        settings.delay = settings.delay.max(min);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Adapt to a response that took `latency`, `None` if the request failed
+    /// without a response
+    pub fn record(&self, latency: Duration, response: Option<(StatusCode, &HeaderMap)>) {
+        let (config, settings) = &mut *self.state.lock().unwrap();
+        settings.latency = Some(latency);
+        let overloaded = match response {
+            Some((status, _)) => {
+                status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+            }
+            None => true,
+        };
+        let retry_after = response
+            .filter(|_| overloaded)
+            .and_then(|(_, headers)| retry_after(headers))
+            .unwrap_or_default();
+        if overloaded {
+            // start from one second if there was no delay to double
+            settings.delay = settings.delay.max(Duration::from_secs(1)) * 2;
+            settings.concurrency = (settings.concurrency / 2).max(1);
+        } else {
+            let target = latency.div_f64(config.target_concurrency);
+            let average = (settings.delay + target) / 2;
+            if response.is_some_and(|(status, _)| status.is_success()) {
+                settings.delay = average;
+                if settings.concurrency < config.max_concurrency && target <= settings.delay {
+                    settings.concurrency += 1;
+                }
+            } else {
+                // error statuses must not speed up the crawl
+                settings.delay = average.max(settings.delay);
+            }
+        }
+        // the bounds don't apply to the wait the server asked for
+        settings.delay = settings
+            .delay
+            .clamp(config.min_delay, config.max_delay)
+            .max(retry_after);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, RETRY_AFTER};
+
+    #[test]
+    fn adapt_to_latency_and_errors() {
+        let throttle = Throttle::new(
+            AutoThrottle::new()
+                .start_delay(Duration::from_secs(4))
+                .bounds(Duration::from_millis(100), Duration::from_secs(30))
+                .max_concurrency(2),
+        );
+        let ok = HeaderMap::new();
+        assert_eq!(
+            throttle.take_changed().unwrap().delay,
+            Duration::from_secs(4)
+        );
+        assert!(throttle.take_changed().is_none());
+
+        // the delay approaches the latency
+        throttle.record(Duration::from_secs(2), Some((StatusCode::OK, &ok)));
+        assert_eq!(throttle.settings().delay, Duration::from_secs(3));
+        throttle.record(Duration::from_millis(10), Some((StatusCode::OK, &ok)));
+        throttle.record(Duration::from_millis(10), Some((StatusCode::OK, &ok)));
+        let settings = throttle.take_changed().unwrap();
+        assert_eq!(settings.delay, Duration::from_micros(757_500));
+        assert_eq!(settings.concurrency, 2);
+
+        // an error status doesn't lower the delay
+        throttle.record(
+            Duration::from_millis(10),
+            Some((StatusCode::NOT_FOUND, &ok)),
+        );
+        assert_eq!(throttle.settings().delay, Duration::from_micros(757_500));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        throttle.record(
+            Duration::from_millis(10),
+            Some((StatusCode::TOO_MANY_REQUESTS, &headers)),
+        );
+        let settings = throttle.settings();
+        assert_eq!(settings.delay, Duration::from_secs(5));
+        assert_eq!(settings.concurrency, 1);
+
+        throttle.record(Duration::from_secs(1), None);
+        throttle.record(Duration::from_secs(1), None);
+        assert_eq!(throttle.settings().delay, Duration::from_secs(20));
+        throttle.record(Duration::from_secs(1), None);
+        assert_eq!(throttle.settings().delay, Duration::from_secs(30));
+
+        // a Retry-After above the upper bound is respected
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("60"));
+        throttle.record(
+            Duration::from_millis(10),
+            Some((StatusCode::SERVICE_UNAVAILABLE, &headers)),
+        );
+        assert_eq!(throttle.settings().delay, Duration::from_secs(60));
+    }
+}